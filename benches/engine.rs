@@ -0,0 +1,110 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Benchmarks of the price tag engine on large documents, run with `cargo bench`
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use regex::{Regex, RegexBuilder};
+
+use sesters::currency::ALL_CURRENCIES;
+use sesters::price_in_text::{Engine, EngineBuilder};
+
+/// Document of about size bytes, with price tags in various currencies among plain words
+fn document(size: usize) -> String {
+    const LINES: [&str; 6] = [
+        "Taxi from the airport 23 €, tip included. ",
+        "Hotel for three nights, £140 paid at the desk. ",
+        "Dinner with the team: 45 USD, shared later. ",
+        "Museum tickets were CHF 12.50 each for adults. ",
+        "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ",
+        "Sent 0.002 BTC and 100 USDT to the exchange. ",
+    ];
+    let mut document = String::with_capacity(size + 64);
+    for line in LINES.iter().cycle() {
+        if document.len() >= size {
+            break;
+        }
+        document.push_str(line);
+    }
+    document
+}
+
+/// One regex per currency, as currencies were looked for before the single automaton
+fn per_currency_regexes() -> Vec<Regex> {
+    ALL_CURRENCIES
+        .iter()
+        .map(|currency| {
+            let alternatives: Vec<String> = currency
+                .isos()
+                .iter()
+                .chain(currency.symbols())
+                .map(|s| s.escape_unicode().to_string())
+                .collect();
+            RegexBuilder::new(&alternatives.join("|"))
+                .case_insensitive(true)
+                .build()
+                .unwrap()
+        })
+        .collect()
+}
+
+fn engine_new(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine_new");
+    group.bench_function("compiled", |b| b.iter(|| EngineBuilder::new().fire()));
+    group.bench_function("cached", |b| b.iter(Engine::new));
+    group.bench_function("per_currency_regexes", |b| b.iter(per_currency_regexes));
+    group.finish();
+}
+
+fn large_documents(c: &mut Criterion) {
+    let engine = Engine::new();
+    let regexes = per_currency_regexes();
+
+    let mut group = c.benchmark_group("large_documents");
+    for size in [10_000, 100_000, 1_000_000] {
+        let document = document(size);
+        group.throughput(Throughput::Bytes(document.len() as u64));
+        // Only the currency lookup of the former engine, for comparison
+        group.bench_with_input(
+            BenchmarkId::new("per_currency_regexes", size),
+            &document,
+            |b, document| {
+                b.iter(|| {
+                    regexes
+                        .iter()
+                        .map(|regex| regex.find_iter(black_box(document)).count())
+                        .sum::<usize>()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("all_price_tags", size),
+            &document,
+            |b, document| b.iter(|| engine.all_price_tags(black_box(document))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("distinct_price_tags", size),
+            &document,
+            |b, document| b.iter(|| engine.distinct_price_tags(black_box(document))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, engine_new, large_documents);
+criterion_main!(benches);
@@ -0,0 +1,334 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Alert subcommand, and reporting of the alerts triggered by new rates
+
+use anyhow::{bail, Result};
+use log::info;
+
+use std::io::Write;
+use std::process::Command;
+
+use crate::db::alert::Triggered;
+
+#[cfg(feature = "cli")]
+use {
+    crate::cli::AlertCommands,
+    crate::currency,
+    crate::db::alert::{Alert, Direction},
+    crate::MainContext,
+    anyhow::anyhow,
+    log::warn,
+    std::io,
+    term_table::{row::Row, Table},
+};
+
+#[cfg(feature = "cli")]
+pub(crate) fn run(ctxt: MainContext, subcommand: AlertCommands) -> Result<()> {
+    match subcommand {
+        AlertCommands::Add {
+            src,
+            dst,
+            above,
+            below,
+            days,
+        } => add(&ctxt, &src, &dst, above, below, days),
+        AlertCommands::List => list(&ctxt),
+        AlertCommands::Remove { id } => {
+            if ctxt.db.remove_alert(id)? == 0 {
+                bail!("No alert with id {}", id);
+            }
+            Ok(())
+        }
+        AlertCommands::Check { hook } => {
+            let hook = hook.or_else(|| ctxt.cfg.alert_hook.clone());
+            let triggered = check(&ctxt)?;
+            emit(hook.as_deref(), &triggered, &mut io::stdout())
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+fn add(
+    ctxt: &MainContext,
+    src: &str,
+    dst: &str,
+    above: Option<f64>,
+    below: Option<f64>,
+    days: Option<u32>,
+) -> Result<()> {
+    let currency = |iso: &str| {
+        currency::existing_from_iso(iso).ok_or_else(|| anyhow!("Unknown currency '{}'", iso))
+    };
+    let (src, dst) = (currency(src)?, currency(dst)?);
+    if src == dst {
+        bail!("Source and destination currencies are the same");
+    }
+    let (direction, threshold) = match (above, below) {
+        (Some(above), None) => (Direction::Above, above),
+        (None, Some(below)) => (Direction::Below, below),
+        _ => bail!("Give either a threshold above or below"),
+    };
+
+    let mut alert = Alert {
+        rowid: 0,
+        src: src.get_main_iso().to_string(),
+        dst: dst.get_main_iso().to_string(),
+        direction,
+        threshold,
+        days,
+        triggered: false,
+    };
+    alert.rowid = ctxt.db.add_alert(&alert)?;
+    println!("Alert {} added: {}", alert.rowid, alert);
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn list(ctxt: &MainContext) -> Result<()> {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec!["Id", "Alert", "State"]));
+    for alert in ctxt.db.read_alerts()? {
+        let state = if alert.triggered {
+            "triggered"
+        } else {
+            "waiting"
+        };
+        table.add_row(Row::new(vec![
+            alert.rowid.to_string(),
+            alert.to_string(),
+            state.to_string(),
+        ]));
+    }
+
+    println!("{}", table.render());
+    Ok(())
+}
+
+/// Evaluate all alerts against the latest rates stored, without retrieving rates online.
+/// Returns the alerts whose condition started to hold
+#[cfg(feature = "cli")]
+fn check(ctxt: &MainContext) -> Result<Vec<Triggered>> {
+    let mut pairs: Vec<(String, String)> = ctxt
+        .db
+        .read_alerts()?
+        .into_iter()
+        .map(|alert| (alert.src, alert.dst))
+        .collect();
+    pairs.sort();
+    pairs.dedup();
+
+    let mut triggered = Vec::new();
+    for (src, dst) in pairs {
+        let (src, dst) = match (
+            currency::existing_from_iso(&src),
+            currency::existing_from_iso(&dst),
+        ) {
+            (Some(src), Some(dst)) => (src, dst),
+            // Currency removed since the alert was added
+            _ => continue,
+        };
+        match ctxt.store().get_latest_rate(src, dst)? {
+            Some(rate) => triggered.append(&mut ctxt.db.check_alerts(&rate)?),
+            None => warn!("No rate stored from {} to {}, alerts not checked", src, dst),
+        }
+    }
+
+    Ok(triggered)
+}
+
+/// Report triggered alerts, with the hook command if there is one, to out otherwise
+pub(crate) fn emit(hook: Option<&str>, triggered: &[Triggered], out: &mut dyn Write) -> Result<()> {
+    for triggered in triggered {
+        info!("Alert triggered: {}", triggered);
+        match hook {
+            Some(hook) => run_hook(hook, triggered)?,
+            None => writeln!(out, "Alert: {}", triggered)?,
+        }
+    }
+    Ok(())
+}
+
+/// Run the hook command in a shell, with the alert in environment variables
+fn run_hook(hook: &str, triggered: &Triggered) -> Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    let status = command
+        .arg(hook)
+        .env("SESTERS_ALERT", triggered.to_string())
+        .env("SESTERS_ALERT_ID", triggered.alert.rowid.to_string())
+        .env("SESTERS_ALERT_SRC", &triggered.alert.src)
+        .env("SESTERS_ALERT_DST", &triggered.alert.dst)
+        .env("SESTERS_ALERT_RATE", triggered.rate.to_string())
+        .status()?;
+    if !status.success() {
+        bail!("Alert hook failed ({})", status);
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::currency::{EUR, GBP};
+    use crate::rate::Rate;
+    use chrono::{Duration, Utc};
+
+    fn context() -> MainContext<'static> {
+        MainContext::new_in_memory(Config::default(), vec![]).unwrap()
+    }
+
+    fn alert(direction: Direction, threshold: f64, days: Option<u32>) -> Alert {
+        Alert {
+            rowid: 0,
+            src: String::from("GBP"),
+            dst: String::from("EUR"),
+            direction,
+            threshold,
+            days,
+            triggered: false,
+        }
+    }
+
+    /// Store rates from EUR to GBP, a day apart and ending today, returning the rates of the
+    /// alerts triggered by each of them
+    fn simulate(ctxt: &MainContext, gbp_eur: &[f64]) -> Vec<Vec<f64>> {
+        let days = gbp_eur.len() as i64;
+        gbp_eur
+            .iter()
+            .enumerate()
+            .map(|(i, rate)| {
+                let date = Utc::now() - Duration::days(days - 1 - i as i64);
+                let rate = Rate::new(
+                    &EUR,
+                    &GBP,
+                    date,
+                    1. / rate,
+                    String::from("test"),
+                    Some(date + Duration::hours(1)),
+                );
+                ctxt.db
+                    .set_rate(&rate)
+                    .unwrap()
+                    .iter()
+                    .map(|t| (t.rate * 1000.).round() / 1000.)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn threshold_test() {
+        let ctxt = context();
+        ctxt.db
+            .add_alert(&alert(Direction::Above, 1.2, None))
+            .unwrap();
+
+        // Triggers when crossing the threshold, again only once it went back below
+        let triggered = simulate(&ctxt, &[1.18, 1.19, 1.21, 1.22, 1.19, 1.205]);
+        let none: Vec<f64> = Vec::new();
+        assert_eq!(
+            triggered,
+            vec![
+                none.clone(),
+                none.clone(),
+                vec![1.21],
+                none.clone(),
+                none,
+                vec![1.205]
+            ]
+        );
+    }
+
+    #[test]
+    fn move_test() {
+        let ctxt = context();
+        ctxt.db
+            .add_alert(&alert(Direction::Below, 2., Some(2)))
+            .unwrap();
+
+        // 1.16 is 1.7% below 1.18, 1.15 is 2.5% below 1.18
+        let triggered = simulate(&ctxt, &[1.18, 1.2, 1.16, 1.15]);
+        assert_eq!(triggered.concat(), vec![1.15]);
+    }
+
+    #[test]
+    fn check_test() {
+        let ctxt = context();
+        assert!(check(&ctxt).unwrap().is_empty());
+
+        simulate(&ctxt, &[1.21]);
+        // Added after the rate was stored, triggers on check, once
+        ctxt.db
+            .add_alert(&alert(Direction::Above, 1.2, None))
+            .unwrap();
+        let triggered = check(&ctxt).unwrap();
+        assert_eq!(triggered.len(), 1);
+        assert!(check(&ctxt).unwrap().is_empty());
+        assert!(ctxt.db.read_alerts().unwrap()[0].triggered);
+
+        let mut out = Vec::new();
+        emit(None, &triggered, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.starts_with("Alert: GBP ➜ EUR above 1.2: 1.2100 on "),
+            "{}",
+            out
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hook_test() {
+        let ctxt = context();
+        ctxt.db
+            .add_alert(&alert(Direction::Above, 1.2, None))
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("sesters-hook-{}", std::process::id()));
+        let hook = format!(
+            "echo \"$SESTERS_ALERT_SRC $SESTERS_ALERT_DST\" > {}",
+            path.display()
+        );
+        let alert = ctxt.db.read_alerts().unwrap().remove(0);
+        let triggered = Triggered {
+            alert,
+            rate: 1.25,
+            reference: None,
+            provider: String::from("test"),
+            date: Utc::now(),
+        };
+        emit(
+            Some(&hook),
+            std::slice::from_ref(&triggered),
+            &mut io::sink(),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "GBP EUR\n");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(emit(Some("exit 3"), &[triggered], &mut io::sink()).is_err());
+    }
+}
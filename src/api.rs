@@ -18,22 +18,35 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Access several API used by Sesters
 
-use anyhow::{anyhow, Result};
-use chrono::Duration;
-use log::{debug, error, trace};
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use lazy_static::lazy_static;
+use log::{debug, trace, warn};
+use regex::Regex;
 use serde_json::Value;
-use std::error::Error;
 
-use crate::config::{Config, CurrencyConverterApiCom, ExchangeRatesApiIo};
-use crate::currency::Currency;
+use crate::config::{CoinGecko, Config, CurrencyConverterApiCom, Ecb, ExchangeRatesApiIo, Quota};
+use crate::currency::{self, Currency};
+use crate::http::{self, Client};
 use crate::rate::Rate;
 
 use ureq::{Agent, Request, Response};
 
+pub use error::{RateError, DB_EXIT_CODE};
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+mod error;
+#[cfg(test)]
+pub(crate) mod mock;
+#[cfg(test)]
+mod tests;
+
 /// Trait common to all supported API endpoints
 pub trait RateApi {
     /// Initialise the rate API struct with config, as it may contain API key
-    fn new(config: &Config) -> &Self;
+    fn new(config: &Config) -> &Self
+    where
+        Self: Sized;
 
     // TODO Add method to get possible conversion and store it in initial
     // struct. This requires passing the agent to new
@@ -41,39 +54,260 @@ pub trait RateApi {
     /// Provider identifier, should be based on provider url
     fn provider_id(&self) -> String;
 
-    /// Build the query to get rate from currency src to currency dst
-    fn rate_query<'c>(&self, agent: &Agent, src: &'c Currency, dst: &'c Currency) -> Request;
+    /// API key sent with requests, if any. It is hidden in logs and errors
+    fn key(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether the provider has rates from src to dst. Only rates between fiat currencies by
+    /// default
+    fn supports(&self, src: &Currency, dst: &Currency) -> bool {
+        !src.is_crypto() && !dst.is_crypto()
+    }
+
+    /// Whether the API key in use is shared by all users, and so is its quota
+    fn uses_shared_key(&self) -> bool {
+        false
+    }
+
+    /// Budget of requests to the provider, unlimited by default
+    fn quota(&self) -> Quota {
+        Quota::default()
+    }
+
+    /// Largest number of currencies a single request can get rates to, unlimited by default
+    fn max_pairs_per_request(&self) -> Option<usize> {
+        None
+    }
+
+    /// Number of requests sent by [`RateApi::rates`] to get rates to n currencies
+    fn requests_for(&self, n: usize) -> usize {
+        match self.max_pairs_per_request() {
+            Some(max) if n > max => n.div_ceil(max.max(1)),
+            _ => 1,
+        }
+    }
+
+    /// Build the query to get rates from currency src to every currency in dsts, in one request
+    fn rates_query<'c>(&self, agent: &Agent, src: &'c Currency, dsts: &[&'c Currency]) -> Request;
 
-    /// Treat result of the query to get a rate
-    fn treat_result<'c>(
+    /// Treat result of the query to get several rates. Rates for currencies not in dsts may be
+    /// returned as well, if the provider gave them
+    fn treat_results<'c>(
         &self,
         res: Response,
         src: &'c Currency,
-        dst: &'c Currency,
-    ) -> Result<Rate<'c>, Box<dyn Error>>;
-
-    /// Perform request to get rate, if it exists
-    fn rate<'c>(&self, agent: &Agent, src: &'c Currency, dst: &'c Currency) -> Option<Rate<'c>> {
-        let rate_err = || -> Result<Rate, Box<dyn Error>> {
-            debug!("Performing conversion request for {} -> {}", src, dst);
-            let res = self.rate_query(agent, src, dst).call()?;
-            debug!("Conversion request for {} -> {} done", src, dst);
-            trace!("Conversion request result: {:?}", &res);
-            self.treat_result(res, src, dst)
+        dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError>;
+
+    /// Error for a response with an HTTP error status, to a request for rates from src to dsts.
+    /// Providers may refine it by looking at the body of the response
+    fn status_error(
+        &self,
+        status: u16,
+        retry_after: Option<Duration>,
+        body: &str,
+        _src: &Currency,
+        _dsts: &[&Currency],
+    ) -> RateError {
+        status_error(self.provider_id(), status, retry_after, body)
+    }
+
+    /// Send the request for rates from src to dsts, turning transport failures and HTTP error
+    /// statuses into errors
+    fn send(
+        &self,
+        client: &Client,
+        request: Request,
+        src: &Currency,
+        dsts: &[&Currency],
+    ) -> Result<Response, RateError> {
+        trace!("Sending request to {}", self.redact(request.url()));
+        match client.call(request) {
+            Ok(res) => {
+                trace!("Request result: {:?}", &res);
+                Ok(res)
+            }
+            Err(ureq::Error::Status(status, res)) => {
+                let retry_after = http::retry_after(&res).and_then(|d| Duration::from_std(d).ok());
+                let body = res.into_string().unwrap_or_default();
+                Err(self.status_error(status, retry_after, &body, src, dsts))
+            }
+            Err(ureq::Error::Transport(transport)) => Err(RateError::Network {
+                provider: self.provider_id(),
+                message: transport.to_string(),
+            }),
+        }
+    }
+
+    /// Get rates from src to all dsts, in a single request unless there are more dsts than the
+    /// provider accepts in one. Returns every rate the provider gave, possibly including currencies
+    /// not in dsts
+    fn rates<'c>(
+        &self,
+        client: &Client,
+        src: &'c Currency,
+        dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        let chunks: Vec<&[&Currency]> = match self.max_pairs_per_request() {
+            Some(max) if dsts.len() > max => dsts.chunks(max.max(1)).collect(),
+            _ => vec![dsts],
         };
-        match rate_err() {
-            Err(e) => {
-                error!(
-                    "Error while performing request for {} -> {}: {}",
-                    src, dst, e
-                );
-                None
+        let chunked = chunks.len() > 1;
+        let mut rates = Vec::new();
+        for chunk in chunks {
+            debug!(
+                "Performing conversion request for {} -> {}",
+                src,
+                join_isos(chunk, ",")
+            );
+            let res = self
+                .send(
+                    client,
+                    self.rates_query(client.agent(), src, chunk),
+                    src,
+                    chunk,
+                )
+                .map_err(|e| self.redact_error(e))?;
+            debug!(
+                "Conversion request for {} -> {} done",
+                src,
+                join_isos(chunk, ",")
+            );
+            match self.treat_results(res, src, chunk) {
+                Ok(mut chunk_rates) => rates.append(&mut chunk_rates),
+                // Other chunks may have rates
+                Err(RateError::UnsupportedPair { .. }) if chunked => {}
+                Err(e) => return Err(self.redact_error(e)),
             }
-            Ok(rate) => Some(rate),
         }
+
+        if chunked && rates.is_empty() {
+            return Err(RateError::UnsupportedPair {
+                provider: self.provider_id(),
+                src: src.get_main_iso().to_string(),
+                dst: join_isos(dsts, ", "),
+            });
+        }
+        Ok(rates)
+    }
+
+    /// Build the query to get rates from currency src to every currency in dsts, as they were on
+    /// a given day. None if the provider has no historical rates
+    fn historical_rates_query<'c>(
+        &self,
+        _agent: &Agent,
+        _src: &'c Currency,
+        _dsts: &[&'c Currency],
+        _day: NaiveDate,
+    ) -> Option<Request> {
+        None
+    }
+
+    /// Treat result of the query to get historical rates. Rates are dated with the day they were
+    /// published, which may be before the requested day (week-ends, bank holidays…)
+    fn treat_historical_results<'c>(
+        &self,
+        _res: Response,
+        _src: &'c Currency,
+        _dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        Ok(Vec::new())
+    }
+
+    /// Perform a single request to get rates from src to all dsts on a given day. Returns an empty
+    /// vector if the provider has no historical rates
+    fn historical_rates<'c>(
+        &self,
+        client: &Client,
+        src: &'c Currency,
+        dsts: &[&'c Currency],
+        day: NaiveDate,
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        let query = match self.historical_rates_query(client.agent(), src, dsts, day) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+        debug!(
+            "Performing historical request for {} -> {} on {}",
+            src,
+            join_isos(dsts, ","),
+            day
+        );
+        let res = self
+            .send(client, query, src, dsts)
+            .map_err(|e| self.redact_error(e))?;
+        self.treat_historical_results(res, src, dsts)
+            .map_err(|e| self.redact_error(e))
+    }
+
+    /// Hide the API key in a text
+    fn redact(&self, text: &str) -> String {
+        redact(text, self.key().unwrap_or(""))
+    }
+
+    /// Hide the API key in the messages of an error
+    fn redact_error(&self, err: RateError) -> RateError {
+        match self.key() {
+            Some(key) => err.redact(key),
+            None => err,
+        }
+    }
+}
+
+/// Generic error for an HTTP error status
+fn status_error(
+    provider: String,
+    status: u16,
+    retry_after: Option<Duration>,
+    body: &str,
+) -> RateError {
+    match status {
+        401 | 403 => RateError::InvalidKey { provider },
+        429 => RateError::QuotaExceeded {
+            provider,
+            retry_after,
+        },
+        _ => RateError::HttpStatus {
+            provider,
+            status,
+            body: body.to_string(),
+        },
+    }
+}
+
+/// Replace every occurrence of a secret in a text
+pub(crate) fn redact(text: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(secret, "[REDACTED]")
     }
 }
 
+/// Read the body of a response
+fn body_string(provider: &str, res: Response) -> Result<String, RateError> {
+    res.into_string().map_err(|e| RateError::Network {
+        provider: provider.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Parse the JSON body of a response
+fn body_json(provider: &str, body: &str) -> Result<Value, RateError> {
+    serde_json::from_str(body)
+        .map_err(|e| RateError::malformed(provider, format!("{}\nReturned JSON: {}", e, body)))
+}
+
+/// Join main iso symbols of currencies, for logs and queries
+fn join_isos(currencies: &[&Currency], sep: &str) -> String {
+    currencies
+        .iter()
+        .map(|c| c.get_main_iso())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
 impl RateApi for CurrencyConverterApiCom {
     fn new(config: &Config) -> &CurrencyConverterApiCom {
         &config.apis.currency_converter_api_com
@@ -83,48 +317,222 @@ impl RateApi for CurrencyConverterApiCom {
         String::from("currencyconverterapi.com")
     }
 
-    fn rate_query<'c>(&self, agent: &Agent, src: &'c Currency, dst: &'c Currency) -> Request {
-        let pair = format!("{0}_{1}", src.get_main_iso(), dst.get_main_iso());
+    fn key(&self) -> Option<&str> {
+        Some(&self.key)
+    }
+
+    fn uses_shared_key(&self) -> bool {
+        CurrencyConverterApiCom::uses_shared_key(self)
+    }
+
+    fn quota(&self) -> Quota {
+        self.quota.clone()
+    }
+
+    fn max_pairs_per_request(&self) -> Option<usize> {
+        // Limit of the free version
+        Some(2)
+    }
+
+    fn rates_query<'c>(&self, agent: &Agent, src: &'c Currency, dsts: &[&'c Currency]) -> Request {
+        let pairs = dsts
+            .iter()
+            .map(|dst| format!("{0}_{1}", src.get_main_iso(), dst.get_main_iso()))
+            .collect::<Vec<_>>()
+            .join(",");
         agent
-            .get("https://free.currconv.com/api/v7/convert")
-            .query("q", &pair)
+            .get(&format!("{}/api/v7/convert", self.base_url))
+            .query("q", &pairs)
             .query("compact", "ultra")
             .query("apiKey", &self.key)
     }
 
-    fn treat_result<'c>(
+    fn treat_results<'c>(
+        &self,
+        res: Response,
+        src: &'c Currency,
+        dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        let provider = self.provider_id();
+        let response_string = body_string(&provider, res)?;
+        let rates = body_json(&provider, &response_string)?;
+        if rates.get("error").is_some() {
+            return Err(self.status_error(200, None, &response_string, src, dsts));
+        }
+
+        let mut found_rates = Vec::with_capacity(dsts.len());
+        for &dst in dsts {
+            let pair = format!("{0}_{1}", src.get_main_iso(), dst.get_main_iso());
+            let rate = match rates.get(&pair) {
+                Some(rate) => rate.as_f64().ok_or_else(|| {
+                    RateError::malformed(&provider, format!("non-f64 value for {}", pair))
+                })?,
+                None => {
+                    warn!("missing key in returned JSON: {}", &pair);
+                    continue;
+                }
+            };
+            found_rates.push(Rate::now(
+                src,
+                dst,
+                rate,
+                self.provider_id(),
+                Some(Duration::hours(1)),
+            ));
+        }
+
+        if found_rates.is_empty() && !dsts.is_empty() {
+            return Err(RateError::UnsupportedPair {
+                provider,
+                src: src.get_main_iso().to_string(),
+                dst: join_isos(dsts, ", "),
+            });
+        }
+        Ok(found_rates)
+    }
+
+    fn status_error(
+        &self,
+        status: u16,
+        retry_after: Option<Duration>,
+        body: &str,
+        src: &Currency,
+        dsts: &[&Currency],
+    ) -> RateError {
+        // Errors are given as {"status": 400, "error": "Invalid API key."}
+        let message = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|json| json.get("error")?.as_str().map(|s| s.to_lowercase()))
+            .unwrap_or_default();
+        if message.contains("api key") {
+            RateError::InvalidKey {
+                provider: self.provider_id(),
+            }
+        } else if message.contains("pairs") {
+            // Too many pairs in the request for the plan of the key
+            RateError::UnsupportedPair {
+                provider: self.provider_id(),
+                src: src.get_main_iso().to_string(),
+                dst: join_isos(dsts, ", "),
+            }
+        } else if message.contains("limit") || message.contains("quota") {
+            RateError::QuotaExceeded {
+                provider: self.provider_id(),
+                retry_after,
+            }
+        } else {
+            status_error(self.provider_id(), status, retry_after, body)
+        }
+    }
+}
+
+impl ExchangeRatesApiIo {
+    /// Add the API key to a request, if there is one
+    fn with_key(&self, request: Request) -> Request {
+        match self.key() {
+            Some(key) => request.query("access_key", key),
+            None => request,
+        }
+    }
+
+    /// Parse the rates returned by the API for a request from src to dsts. Rates are dated with
+    /// the day given in the response if day is true, with the current time otherwise
+    fn parse_rates<'c>(
         &self,
         res: Response,
         src: &'c Currency,
-        dst: &'c Currency,
-    ) -> Result<Rate<'c>, Box<dyn Error>> {
-        let pair = format!("{0}_{1}", src.get_main_iso(), dst.get_main_iso());
-        let response_string = res.into_string()?;
-        let rates: Value = serde_json::from_str(&response_string)?;
-
-        let rate = rates
-            .get(&pair)
-            .ok_or_else(|| {
-                anyhow!(
+        dsts: &[&'c Currency],
+        day: bool,
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        let provider = self.provider_id();
+        let response_string = body_string(&provider, res)?;
+        let json = body_json(&provider, &response_string)?;
+        if json.get("success").and_then(|s| s.as_bool()) == Some(false) {
+            return Err(self.status_error(200, None, &response_string, src, dsts));
+        }
+        let missing_key = |key: &str| {
+            RateError::malformed(
+                &provider,
+                format!(
                     "missing key in returned JSON: {}\nReturned JSON: {}",
-                    &pair,
-                    response_string
-                )
-            })?
-            .as_f64()
-            .ok_or(anyhow!("got a non-f64 value"))?;
+                    key, response_string
+                ),
+            )
+        };
 
-        Ok(Rate::now(
-            src,
-            dst,
-            rate,
-            self.provider_id(),
-            Some(Duration::hours(1)),
-        ))
+        let rates = json
+            .get("rates")
+            .and_then(|rates| rates.as_object())
+            .ok_or_else(|| missing_key("rates"))?;
+        let date = if day {
+            let day: NaiveDate = json
+                .get("date")
+                .and_then(|date| date.as_str())
+                .and_then(|date| date.parse().ok())
+                .ok_or_else(|| missing_key("date"))?;
+            Some(Utc.from_utc_date(&day).and_hms(0, 0, 0))
+        } else {
+            None
+        };
+
+        let mut found_rates = Vec::with_capacity(rates.len());
+        for (iso, rate) in rates {
+            // Only keep currencies we know about
+            let dst = match currency::existing_from_iso(iso) {
+                Some(dst) if dst != src => dst,
+                _ => continue,
+            };
+            let rate = rate
+                .as_f64()
+                .ok_or_else(|| RateError::malformed(&provider, "got a non-f64 value"))?;
+            found_rates.push(match date {
+                Some(date) => Rate::new(src, dst, date, rate, self.provider_id(), None),
+                None => Rate::now(
+                    src,
+                    dst,
+                    rate,
+                    self.provider_id(),
+                    // Updated once a day, let’s bet for a refresh every few hours
+                    Some(Duration::hours(6)),
+                ),
+            });
+        }
+
+        Ok(found_rates)
     }
 }
 
 impl RateApi for ExchangeRatesApiIo {
+    fn status_error(
+        &self,
+        status: u16,
+        retry_after: Option<Duration>,
+        body: &str,
+        src: &Currency,
+        dsts: &[&Currency],
+    ) -> RateError {
+        // Errors are given as {"success": false, "error": {"code": 101, "type": "…"}}, see
+        // <https://exchangeratesapi.io/documentation/>
+        let code = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|json| json.get("error")?.get("code")?.as_u64());
+        let provider = self.provider_id();
+        match code {
+            Some(101) | Some(102) => RateError::InvalidKey { provider },
+            Some(104) | Some(106) => RateError::QuotaExceeded {
+                provider,
+                retry_after,
+            },
+            // Invalid base currency or destination currencies
+            Some(201) | Some(202) => RateError::UnsupportedPair {
+                provider,
+                src: src.get_main_iso().to_string(),
+                dst: join_isos(dsts, ", "),
+            },
+            _ => status_error(provider, status, retry_after, body),
+        }
+    }
+
     fn new(config: &Config) -> &Self {
         &config.apis.exchange_rates_api_io
     }
@@ -133,49 +541,311 @@ impl RateApi for ExchangeRatesApiIo {
         String::from("exchangeratesapi.io")
     }
 
-    fn rate_query<'c>(&self, agent: &Agent, src: &'c Currency, _dst: &'c Currency) -> Request {
-        agent
-            .get("https://api.exchangeratesapi.io/latest")
-            .query("base", src.get_main_iso())
+    fn key(&self) -> Option<&str> {
+        Some(self.key.as_str()).filter(|key| !key.is_empty())
+    }
+
+    fn quota(&self) -> Quota {
+        self.quota.clone()
     }
 
-    // TODO Use other rates given
-    fn treat_result<'c>(
+    fn rates_query<'c>(&self, agent: &Agent, src: &'c Currency, _dsts: &[&'c Currency]) -> Request {
+        // Every rate for the base currency is returned, no need to filter on dsts
+        self.with_key(
+            agent
+                .get(&format!("{}/latest", self.base_url))
+                .query("base", src.get_main_iso()),
+        )
+    }
+
+    fn treat_results<'c>(
         &self,
         res: Response,
         src: &'c Currency,
-        dst: &'c Currency,
-    ) -> Result<Rate<'c>, Box<dyn Error>> {
-        let response_string = res.into_string()?;
-        let rates: serde_json::Value = serde_json::from_str(&response_string)?;
+        dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        self.parse_rates(res, src, dsts, false)
+    }
 
-        let rate = rates
-            .get("rates")
-            .ok_or_else(|| {
-                anyhow!(
-                    "missing key in returned JSON: {}\nReturned JSON: {}",
-                    &"rates",
-                    response_string
-                )
-            })?
-            .get(&dst.get_main_iso())
-            .ok_or_else(|| {
-                anyhow!(
-                    "missing key in returned JSON: {}\nReturned JSON: {}",
-                    &dst.get_main_iso(),
-                    response_string
-                )
-            })?
-            .as_f64()
-            .ok_or(anyhow!("got a non-f64 value"))?;
+    fn historical_rates_query<'c>(
+        &self,
+        agent: &Agent,
+        src: &'c Currency,
+        _dsts: &[&'c Currency],
+        day: NaiveDate,
+    ) -> Option<Request> {
+        Some(
+            self.with_key(
+                agent
+                    .get(&format!("{}/{}", self.base_url, day.format("%F")))
+                    .query("base", src.get_main_iso()),
+            ),
+        )
+    }
 
-        Ok(Rate::now(
-            src,
-            dst,
-            rate,
-            self.provider_id(),
-            // Updated once a day, let’s bet for a refresh every few hours
-            Some(Duration::hours(6)),
+    fn treat_historical_results<'c>(
+        &self,
+        res: Response,
+        src: &'c Currency,
+        dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        self.parse_rates(res, src, dsts, true)
+    }
+}
+
+lazy_static! {
+    /// Match the day and rates in ECB XML files, in document order
+    static ref ECB_CUBE: Regex = Regex::new(
+        r#"<Cube\s+time=["'](?P<time>\d{4}-\d{2}-\d{2})["']|<Cube\s+currency=["'](?P<currency>\w+)["']\s+rate=["'](?P<rate>[0-9.]+)["']"#
+    )
+    .unwrap();
+}
+
+impl Ecb {
+    /// Parse an ECB reference rate file, where all rates are given from EUR. Returns rates from
+    /// src, computed as cross rates when src is not EUR, for every day in the file, most recent
+    /// first
+    fn parse_rates<'c>(
+        &self,
+        res: Response,
+        src: &'c Currency,
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        let provider = self.provider_id();
+        let response_string = body_string(&provider, res)?;
+
+        // Rates from EUR, by day
+        let mut days: Vec<(NaiveDate, Vec<(&'static Currency, f64)>)> = Vec::new();
+        for cap in ECB_CUBE.captures_iter(&response_string) {
+            if let Some(time) = cap.name("time") {
+                let day = time
+                    .as_str()
+                    .parse()
+                    .map_err(|e| RateError::malformed(&provider, e))?;
+                days.push((day, vec![(&currency::EUR, 1.)]));
+            } else if let (Some(iso), Some(rate)) = (cap.name("currency"), cap.name("rate")) {
+                let (_, eur_rates) = days.last_mut().ok_or_else(|| {
+                    RateError::malformed(&provider, "rate outside of a day in ECB file")
+                })?;
+                if let Some(currency) = currency::existing_from_iso(iso.as_str()) {
+                    let rate = rate
+                        .as_str()
+                        .parse()
+                        .map_err(|e| RateError::malformed(&provider, e))?;
+                    eur_rates.push((currency, rate));
+                }
+            }
+        }
+        if days.is_empty() {
+            return Err(RateError::malformed(
+                &provider,
+                format!("no rate found in ECB file:\n{}", response_string),
+            ));
+        }
+
+        let mut rates = Vec::with_capacity(days.len());
+        for (day, eur_rates) in days {
+            let src_rate = match eur_rates.iter().find(|(c, _)| *c == src) {
+                Some((_, src_rate)) => *src_rate,
+                None => continue,
+            };
+            let date = Utc.from_utc_date(&day).and_hms(0, 0, 0);
+            for &(dst, dst_rate) in eur_rates.iter().filter(|(dst, _)| *dst != src) {
+                rates.push(Rate::new(
+                    src,
+                    dst,
+                    date,
+                    dst_rate / src_rate,
+                    self.provider_id(),
+                    None,
+                ));
+            }
+        }
+
+        Ok(rates)
+    }
+}
+
+impl RateApi for Ecb {
+    fn new(config: &Config) -> &Self {
+        &config.apis.ecb
+    }
+
+    fn provider_id(&self) -> String {
+        String::from("ecb.europa.eu")
+    }
+
+    fn rates_query<'c>(
+        &self,
+        agent: &Agent,
+        _src: &'c Currency,
+        _dsts: &[&'c Currency],
+    ) -> Request {
+        agent.get(&format!(
+            "{}/stats/eurofxref/eurofxref-daily.xml",
+            self.base_url
         ))
     }
+
+    fn treat_results<'c>(
+        &self,
+        res: Response,
+        src: &'c Currency,
+        _dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        Ok(self
+            .parse_rates(res, src)?
+            .into_iter()
+            .map(|rate| {
+                // Published once a day, around 16:00 CET
+                Rate::now(
+                    src,
+                    rate.dst(),
+                    rate.rate(),
+                    self.provider_id(),
+                    Some(Duration::hours(6)),
+                )
+            })
+            .collect())
+    }
+
+    fn historical_rates_query<'c>(
+        &self,
+        agent: &Agent,
+        _src: &'c Currency,
+        _dsts: &[&'c Currency],
+        day: NaiveDate,
+    ) -> Option<Request> {
+        // The last 90 days are available in a much smaller file
+        let recent = Utc::today().naive_utc() - Duration::days(85);
+        if day >= recent {
+            Some(agent.get(&format!(
+                "{}/stats/eurofxref/eurofxref-hist-90d.xml",
+                self.base_url
+            )))
+        } else {
+            Some(agent.get(&format!(
+                "{}/stats/eurofxref/eurofxref-hist.xml",
+                self.base_url
+            )))
+        }
+    }
+
+    fn treat_historical_results<'c>(
+        &self,
+        res: Response,
+        src: &'c Currency,
+        _dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        self.parse_rates(res, src)
+    }
+}
+
+/// Identifier of a cryptocurrency in the CoinGecko API
+fn coingecko_id(currency: &Currency) -> Option<&'static str> {
+    match currency.get_main_iso() {
+        "BTC" => Some("bitcoin"),
+        "ETH" => Some("ethereum"),
+        "USDT" => Some("tether"),
+        "USDC" => Some("usd-coin"),
+        "BNB" => Some("binancecoin"),
+        "XRP" => Some("ripple"),
+        "LTC" => Some("litecoin"),
+        "DOGE" => Some("dogecoin"),
+        _ => None,
+    }
+}
+
+/// Currency prices of cryptocurrencies are compared through this one
+const COINGECKO_PIVOT: &str = "usd";
+
+impl RateApi for CoinGecko {
+    fn new(config: &Config) -> &CoinGecko {
+        &config.apis.coingecko
+    }
+
+    fn provider_id(&self) -> String {
+        String::from("coingecko.com")
+    }
+
+    fn supports(&self, src: &Currency, dst: &Currency) -> bool {
+        let known = |c: &Currency| !c.is_crypto() || coingecko_id(c).is_some();
+        (src.is_crypto() || dst.is_crypto()) && known(src) && known(dst)
+    }
+
+    fn quota(&self) -> Quota {
+        self.quota.clone()
+    }
+
+    fn rates_query<'c>(&self, agent: &Agent, src: &'c Currency, dsts: &[&'c Currency]) -> Request {
+        // Prices of every cryptocurrency in every fiat currency involved
+        let mut ids = Vec::new();
+        let mut vs = vec![COINGECKO_PIVOT.to_string()];
+        for currency in std::iter::once(src).chain(dsts.iter().copied()) {
+            if let Some(id) = coingecko_id(currency) {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            } else if !currency.is_crypto() {
+                let iso = currency.get_main_iso().to_lowercase();
+                if !vs.contains(&iso) {
+                    vs.push(iso);
+                }
+            }
+        }
+        agent
+            .get(&format!("{}/api/v3/simple/price", self.base_url))
+            .query("ids", &ids.join(","))
+            .query("vs_currencies", &vs.join(","))
+    }
+
+    fn treat_results<'c>(
+        &self,
+        res: Response,
+        src: &'c Currency,
+        dsts: &[&'c Currency],
+    ) -> Result<Vec<Rate<'c>>, RateError> {
+        // Prices are given as {"bitcoin": {"usd": 67012.5, "eur": 61890.2}, …}
+        let provider = self.provider_id();
+        let response_string = body_string(&provider, res)?;
+        let prices = body_json(&provider, &response_string)?;
+        let price = |crypto: &Currency, vs: &str| -> Option<f64> {
+            prices
+                .get(coingecko_id(crypto)?)?
+                .get(vs)?
+                .as_f64()
+                .filter(|p| *p > 0.)
+        };
+
+        let mut found_rates = Vec::with_capacity(dsts.len());
+        for &dst in dsts {
+            let rate = match (src.is_crypto(), dst.is_crypto()) {
+                (true, false) => price(src, &dst.get_main_iso().to_lowercase()),
+                (false, true) => price(dst, &src.get_main_iso().to_lowercase()).map(|p| 1. / p),
+                (true, true) => {
+                    price(src, COINGECKO_PIVOT).and_then(|s| Some(s / price(dst, COINGECKO_PIVOT)?))
+                }
+                (false, false) => None,
+            };
+            match rate {
+                Some(rate) => found_rates.push(Rate::now(
+                    src,
+                    dst,
+                    rate,
+                    self.provider_id(),
+                    // Prices of cryptocurrencies change quickly
+                    Some(Duration::minutes(5)),
+                )),
+                None => warn!("no price for {} -> {} in returned JSON", src, dst),
+            }
+        }
+
+        if found_rates.is_empty() && !dsts.is_empty() {
+            return Err(RateError::UnsupportedPair {
+                provider,
+                src: src.get_main_iso().to_string(),
+                dst: join_isos(dsts, ", "),
+            });
+        }
+        Ok(found_rates)
+    }
 }
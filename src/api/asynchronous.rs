@@ -0,0 +1,398 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Rates retrieved concurrently, with the `async` feature
+//!
+//! A [`Fetcher`] sends requests to several [`AsyncRateApi`] at the same time, with at most a
+//! given number of them in flight. Blocking rate sources become asynchronous with [`Blocking`].
+
+use futures::future::{self, BoxFuture, FutureExt, Shared};
+use log::{debug, trace};
+use tokio::sync::Semaphore;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{join_isos, RateApi, RateError};
+use crate::currency::Currency;
+use crate::http::Client;
+use crate::rate::Rate;
+
+/// Rate source answering asynchronously
+pub trait AsyncRateApi: Send + Sync {
+    /// Provider identifier, should be based on provider url
+    fn provider_id(&self) -> String;
+
+    /// Whether the provider has rates from src to dst
+    fn supports(&self, src: &Currency, dst: &Currency) -> bool;
+
+    /// Perform a single request to get rates from src to all dsts. Returns every rate the
+    /// provider gave, possibly including currencies not in dsts
+    fn rates(
+        &self,
+        client: &Client,
+        src: &'static Currency,
+        dsts: Vec<&'static Currency>,
+    ) -> BoxFuture<'static, Result<Vec<Rate<'static>>, RateError>>;
+}
+
+/// Blocking rate source, its requests run on the threads the runtime keeps for blocking tasks
+pub struct Blocking<A>(Arc<A>);
+
+impl<A> Blocking<A> {
+    pub fn new(api: A) -> Self {
+        Blocking(Arc::new(api))
+    }
+}
+
+impl<A: RateApi + Send + Sync + 'static> AsyncRateApi for Blocking<A> {
+    fn provider_id(&self) -> String {
+        self.0.provider_id()
+    }
+
+    fn supports(&self, src: &Currency, dst: &Currency) -> bool {
+        self.0.supports(src, dst)
+    }
+
+    fn rates(
+        &self,
+        client: &Client,
+        src: &'static Currency,
+        dsts: Vec<&'static Currency>,
+    ) -> BoxFuture<'static, Result<Vec<Rate<'static>>, RateError>> {
+        let api = Arc::clone(&self.0);
+        let client = client.clone();
+        async move {
+            match tokio::task::spawn_blocking(move || api.rates(&client, src, &dsts)).await {
+                Ok(result) => result,
+                // Blocking tasks are only cancelled when the runtime shuts down
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Result of a request, shared by the pairs it was sent for
+type Request = Shared<BoxFuture<'static, Result<Vec<Rate<'static>>, Arc<RateError>>>>;
+
+/// Rate of a pair, shared by all callers asking for it while it is in flight
+type PairRate = Shared<BoxFuture<'static, Result<Option<Rate<'static>>, Arc<RateError>>>>;
+
+/// Provider, source and destination currency of a pair in flight
+type Key = (String, String, String);
+
+/// Sends requests concurrently, with a bounded number of requests in flight. A pair asked for
+/// while a request for it is already in flight, for the same provider, waits for that request
+/// instead of sending a new one
+pub struct Fetcher {
+    client: Client,
+    permits: Arc<Semaphore>,
+    in_flight: Mutex<HashMap<Key, PairRate>>,
+}
+
+impl Fetcher {
+    /// Fetcher sending at most max_concurrent requests at the same time, at least one
+    pub fn new(client: Client, max_concurrent: usize) -> Self {
+        Fetcher {
+            client,
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn in_flight(&self) -> MutexGuard<'_, HashMap<Key, PairRate>> {
+        // Pairs are only inserted or removed as a whole, a panic can’t leave the map half written
+        self.in_flight.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Rates from src to each of dsts, in the same order, None when the provider gave none.
+    /// Pairs not in flight are retrieved with a single request
+    pub async fn rates(
+        &self,
+        api: Arc<dyn AsyncRateApi>,
+        src: &'static Currency,
+        dsts: &[&'static Currency],
+    ) -> Result<Vec<Option<Rate<'static>>>, Arc<RateError>> {
+        let provider = api.provider_id();
+        let key = |dst: &Currency| -> Key {
+            (
+                provider.clone(),
+                src.get_main_iso().to_string(),
+                dst.get_main_iso().to_string(),
+            )
+        };
+
+        let mut started: Vec<&'static Currency> = Vec::new();
+        let pair_rates: Vec<PairRate> = {
+            let mut in_flight = self.in_flight();
+            for dst in dsts {
+                if !in_flight.contains_key(&key(dst)) && !started.contains(dst) {
+                    started.push(dst);
+                }
+            }
+            if !started.is_empty() {
+                let request = self.request(api, src, started.clone());
+                for dst in &started {
+                    let dst: &'static Currency = dst;
+                    let pair_rate = request
+                        .clone()
+                        .map(move |rates| {
+                            rates.map(|rates| rates.into_iter().find(|r| r.dst() == dst))
+                        })
+                        .boxed()
+                        .shared();
+                    in_flight.insert(key(dst), pair_rate);
+                }
+            } else {
+                trace!("Rates from {} already in flight", src);
+            }
+            dsts.iter()
+                .map(|dst| in_flight[&key(dst)].clone())
+                .collect()
+        };
+        let _started = Started {
+            fetcher: self,
+            keys: started.iter().map(|dst| key(dst)).collect(),
+        };
+
+        let rates = future::join_all(pair_rates).await;
+        rates.into_iter().collect()
+    }
+
+    /// Rates of several requests, each made of a rate source, a source currency and destination
+    /// currencies, retrieved concurrently. Results are in the same order as the requests
+    pub async fn all_rates(
+        &self,
+        requests: &[(
+            Arc<dyn AsyncRateApi>,
+            &'static Currency,
+            Vec<&'static Currency>,
+        )],
+    ) -> Vec<Result<Vec<Option<Rate<'static>>>, Arc<RateError>>> {
+        future::join_all(
+            requests
+                .iter()
+                .map(|(api, src, dsts)| self.rates(Arc::clone(api), src, dsts)),
+        )
+        .await
+    }
+
+    /// Request sent once a permit is available
+    fn request(
+        &self,
+        api: Arc<dyn AsyncRateApi>,
+        src: &'static Currency,
+        dsts: Vec<&'static Currency>,
+    ) -> Request {
+        let permits = Arc::clone(&self.permits);
+        let client = self.client.clone();
+        async move {
+            // The semaphore is never closed
+            let _permit = permits.acquire_owned().await.unwrap();
+            debug!(
+                "Concurrent request to {} for {} -> {}",
+                api.provider_id(),
+                src,
+                join_isos(&dsts, ",")
+            );
+            api.rates(&client, src, dsts).await.map_err(Arc::new)
+        }
+        .boxed()
+        .shared()
+    }
+}
+
+/// Pairs whose request was started by a caller, no longer in flight once the caller is done with
+/// them, even when it gives up before the request completes. Other callers waiting for them keep
+/// the request going
+struct Started<'f> {
+    fetcher: &'f Fetcher,
+    keys: Vec<Key>,
+}
+
+impl Drop for Started<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.fetcher.in_flight();
+        for key in &self.keys {
+            in_flight.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::{fixtures, MockServer, Route};
+    use crate::config::{Config, CurrencyConverterApiCom, Http};
+    use crate::currency::{CHF, EUR, GBP, JPY, USD};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Rate source answering 2 to everything, recording requests and how many were in flight
+    #[derive(Default)]
+    struct Fake {
+        requests: Mutex<Vec<(String, Vec<String>)>>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl AsyncRateApi for Fake {
+        fn provider_id(&self) -> String {
+            String::from("fake")
+        }
+
+        fn supports(&self, _src: &Currency, _dst: &Currency) -> bool {
+            true
+        }
+
+        fn rates(
+            &self,
+            _client: &Client,
+            src: &'static Currency,
+            dsts: Vec<&'static Currency>,
+        ) -> BoxFuture<'static, Result<Vec<Rate<'static>>, RateError>> {
+            self.requests.lock().unwrap().push((
+                src.get_main_iso().to_string(),
+                dsts.iter().map(|d| d.get_main_iso().to_string()).collect(),
+            ));
+            let in_flight = Arc::clone(&self.in_flight);
+            let max_in_flight = Arc::clone(&self.max_in_flight);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(now, Ordering::SeqCst);
+                // Let the other requests start
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(dsts
+                    .into_iter()
+                    .map(|dst| Rate::now(src, dst, 2., String::from("fake"), None))
+                    .collect())
+            }
+            .boxed()
+        }
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    fn client() -> Client {
+        Client::new(&Http {
+            retries: 0,
+            ..Http::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn dedup_in_flight_test() {
+        let fake = Arc::new(Fake::default());
+        let api: Arc<dyn AsyncRateApi> = fake.clone();
+        let fetcher = Fetcher::new(client(), 4);
+
+        let results = runtime().block_on(fetcher.all_rates(&[
+            (Arc::clone(&api), &EUR, vec![&USD, &GBP]),
+            (Arc::clone(&api), &EUR, vec![&GBP, &CHF, &CHF]),
+            (Arc::clone(&api), &USD, vec![&EUR]),
+        ]));
+
+        let requests = fake.requests.lock().unwrap();
+        assert_eq!(
+            *requests,
+            vec![
+                (
+                    String::from("EUR"),
+                    vec![String::from("USD"), String::from("GBP")]
+                ),
+                (String::from("EUR"), vec![String::from("CHF")]),
+                (String::from("USD"), vec![String::from("EUR")]),
+            ]
+        );
+        let second = results[1].as_ref().unwrap();
+        assert_eq!(second.len(), 3);
+        assert_eq!(second[0].as_ref().unwrap().dst(), &GBP);
+        assert_eq!(second[2].as_ref().unwrap().dst(), &CHF);
+        assert!(fetcher.in_flight().is_empty());
+    }
+
+    #[test]
+    fn dropped_caller_test() {
+        let fake = Arc::new(Fake::default());
+        let api: Arc<dyn AsyncRateApi> = fake.clone();
+        let fetcher = Fetcher::new(client(), 1);
+
+        runtime().block_on(async {
+            // Given up while the request is in flight
+            let rates = fetcher.rates(Arc::clone(&api), &EUR, &[&USD]);
+            assert!(rates.now_or_never().is_none());
+            assert!(fetcher.in_flight().is_empty());
+
+            // The permit is released, a new request is sent
+            let rates = fetcher.rates(api, &EUR, &[&USD]).await.unwrap();
+            assert_eq!(rates[0].as_ref().unwrap().dst(), &USD);
+        });
+        assert_eq!(fake.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn bounded_concurrency_test() {
+        let fake = Arc::new(Fake::default());
+        let api: Arc<dyn AsyncRateApi> = fake.clone();
+        let fetcher = Fetcher::new(client(), 2);
+        let requests: Vec<_> = [&EUR, &USD, &GBP, &CHF, &JPY]
+            .iter()
+            .map(|src| (Arc::clone(&api), *src, vec![&EUR, &USD]))
+            .collect();
+
+        let results = runtime().block_on(fetcher.all_rates(&requests));
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(fake.requests.lock().unwrap().len(), 5);
+        assert_eq!(fake.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn blocking_api_test() {
+        let server = MockServer::start(vec![
+            Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR).times(1),
+            Route::new("/api/v7/convert", 500, "{}"),
+        ]);
+        let mut cfg = Config::default();
+        cfg.apis.currency_converter_api_com.base_url = server.url();
+        let api: Arc<dyn AsyncRateApi> =
+            Arc::new(Blocking::new(CurrencyConverterApiCom::new(&cfg).clone()));
+        let fetcher = Fetcher::new(client(), 4);
+        let runtime = runtime();
+
+        let rates = runtime
+            .block_on(fetcher.rates(Arc::clone(&api), &EUR, &[&GBP, &JPY]))
+            .unwrap();
+        assert_eq!(rates[0].as_ref().unwrap().rate(), 0.8532);
+        assert_eq!(rates[1], None);
+
+        let err = runtime
+            .block_on(fetcher.rates(api, &EUR, &[&USD]))
+            .unwrap_err();
+        assert!(matches!(*err, RateError::HttpStatus { status: 500, .. }));
+        assert_eq!(server.requests().len(), 2);
+    }
+}
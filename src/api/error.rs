@@ -0,0 +1,158 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Errors while getting rates
+
+use std::fmt;
+
+use chrono::Duration;
+
+use super::redact;
+
+/// Process exit code for database errors
+pub const DB_EXIT_CODE: i32 = 9;
+
+/// Error while getting rates from a provider or from the database
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RateError {
+    /// Provider can’t be reached: DNS, connection, TLS…
+    Network { provider: String, message: String },
+    /// Unexpected HTTP status returned by the provider
+    HttpStatus {
+        provider: String,
+        status: u16,
+        body: String,
+    },
+    /// API key missing or rejected by the provider
+    InvalidKey { provider: String },
+    /// Request quota of the provider is exhausted, possibly until some time
+    QuotaExceeded {
+        provider: String,
+        retry_after: Option<Duration>,
+    },
+    /// The provider has no rate for these currencies
+    UnsupportedPair {
+        provider: String,
+        src: String,
+        dst: String,
+    },
+    /// Response of the provider can’t be understood
+    MalformedPayload { provider: String, message: String },
+    /// Rates can’t be read from or written to the database
+    Db(anyhow::Error),
+}
+
+impl RateError {
+    /// Process exit code for the category of the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RateError::Network { .. } => 3,
+            RateError::HttpStatus { .. } => 4,
+            RateError::InvalidKey { .. } => 5,
+            RateError::QuotaExceeded { .. } => 6,
+            RateError::UnsupportedPair { .. } => 7,
+            RateError::MalformedPayload { .. } => 8,
+            RateError::Db(_) => DB_EXIT_CODE,
+        }
+    }
+
+    /// Hide an API key in the messages of the error
+    pub fn redact(self, key: &str) -> Self {
+        match self {
+            RateError::Network { provider, message } => RateError::Network {
+                provider,
+                message: redact(&message, key),
+            },
+            RateError::HttpStatus {
+                provider,
+                status,
+                body,
+            } => RateError::HttpStatus {
+                provider,
+                status,
+                body: redact(&body, key),
+            },
+            RateError::MalformedPayload { provider, message } => RateError::MalformedPayload {
+                provider,
+                message: redact(&message, key),
+            },
+            err => err,
+        }
+    }
+
+    /// Error for a response of the provider that can’t be understood
+    pub fn malformed<M: fmt::Display>(provider: &str, message: M) -> Self {
+        RateError::MalformedPayload {
+            provider: provider.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateError::Network { provider, message } => write!(
+                f,
+                "Can’t reach {}, please check your network connection ({})",
+                provider, message
+            ),
+            RateError::HttpStatus {
+                provider,
+                status,
+                body,
+            } => write!(
+                f,
+                "{} answered with an unexpected HTTP status {}: {}",
+                provider, status, body
+            ),
+            RateError::InvalidKey { provider } => write!(
+                f,
+                "{} rejected the API key, please set a valid key in the environment, a key file or the configuration file",
+                provider
+            ),
+            RateError::QuotaExceeded {
+                provider,
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "Request quota of {} exceeded, please retry in {} minute(s)",
+                provider,
+                (retry_after.num_seconds() + 59) / 60
+            ),
+            RateError::QuotaExceeded {
+                provider,
+                retry_after: None,
+            } => write!(
+                f,
+                "Request quota of {} exceeded, please retry later",
+                provider
+            ),
+            RateError::UnsupportedPair { provider, src, dst } => {
+                write!(f, "{} has no rate from {} to {}", provider, src, dst)
+            }
+            RateError::MalformedPayload { provider, message } => {
+                write!(f, "Unexpected response from {}: {}", provider, message)
+            }
+            RateError::Db(err) => write!(f, "Database error: {:#}", err),
+        }
+    }
+}
+
+impl std::error::Error for RateError {}
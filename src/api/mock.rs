@@ -0,0 +1,239 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Mock HTTP server replaying recorded responses of exchange rate providers, for tests
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::config::Config;
+use crate::currency::{EUR, GBP, USD};
+use crate::MainContext;
+
+/// Recorded responses, see the fixtures directory
+pub mod fixtures {
+    pub const CURRCONV_EUR: &str = include_str!("fixtures/currconv_eur.json");
+    pub const CURRCONV_INVALID_KEY: &str = include_str!("fixtures/currconv_invalid_key.json");
+    pub const CURRCONV_TOO_MANY_PAIRS: &str = include_str!("fixtures/currconv_too_many_pairs.json");
+    pub const CURRCONV_RATE_LIMITED: &str = include_str!("fixtures/currconv_rate_limited.json");
+    pub const EXCHANGERATES_LATEST_EUR: &str =
+        include_str!("fixtures/exchangeratesapi_latest_eur.json");
+    pub const EXCHANGERATES_HISTORICAL_EUR: &str =
+        include_str!("fixtures/exchangeratesapi_2026-03-14_eur.json");
+    pub const EXCHANGERATES_RATE_LIMITED: &str =
+        include_str!("fixtures/exchangeratesapi_rate_limited.json");
+    pub const EXCHANGERATES_INVALID_BASE: &str =
+        include_str!("fixtures/exchangeratesapi_invalid_base.json");
+    pub const ECB_DAILY: &str = include_str!("fixtures/ecb_daily.xml");
+    pub const ECB_HIST_90D: &str = include_str!("fixtures/ecb_hist_90d.xml");
+    pub const MALFORMED_JSON: &str = include_str!("fixtures/malformed.json");
+    pub const COINGECKO_PRICES: &str = include_str!("fixtures/coingecko_simple_price.json");
+}
+
+/// Headers of a request, with lowercase names
+type Headers = Vec<(String, String)>;
+
+/// Response to send when the path and query of a request start with a given prefix
+#[derive(Debug, Clone)]
+pub struct Route {
+    prefix: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+    /// Number of requests the route still answers, unlimited if None
+    remaining: Option<usize>,
+}
+
+impl Route {
+    /// Successful response with the given body
+    pub fn ok(prefix: &str, body: &str) -> Self {
+        Route::new(prefix, 200, body)
+    }
+
+    /// Response with an arbitrary status
+    pub fn new(prefix: &str, status: u16, body: &str) -> Self {
+        Route {
+            prefix: prefix.to_string(),
+            status,
+            headers: Vec::new(),
+            body: body.to_string(),
+            remaining: None,
+        }
+    }
+
+    /// Answer only the first n matching requests, later ones go to the next matching route
+    pub fn times(mut self, n: usize) -> Self {
+        self.remaining = Some(n);
+        self
+    }
+
+    /// Add a header to the response
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// HTTP server listening on an ephemeral port of localhost, stopped when dropped
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+    headers: Arc<Mutex<Vec<Headers>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start serving routes, the first matching route is used. Unknown paths get a 404
+    pub fn start(mut routes: Vec<Route>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let headers = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let requests = Arc::clone(&requests);
+            let headers = Arc::clone(&headers);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        serve(stream, &mut routes, &requests, &headers);
+                    }
+                }
+            })
+        };
+
+        MockServer {
+            addr,
+            requests,
+            headers,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Base url of the server, without trailing slash
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Paths (with query) of the requests received so far
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Headers of the requests received so far, with lowercase names
+    pub fn headers(&self) -> Vec<Headers> {
+        self.headers.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the listening thread
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Default configuration, with every rate provider replaced by the server
+pub fn config(server: &MockServer) -> Config {
+    let mut cfg = Config::default();
+    cfg.apis.currency_converter_api_com.base_url = server.url();
+    cfg.apis.exchange_rates_api_io.base_url = server.url();
+    cfg.apis.ecb.base_url = server.url();
+    cfg.apis.coingecko.base_url = server.url();
+    cfg
+}
+
+/// Context with an in-memory database and EUR, USD and GBP as destination currencies
+pub fn context_with(cfg: Config) -> MainContext<'static> {
+    MainContext::new_in_memory(cfg, vec![&EUR, &USD, &GBP]).unwrap()
+}
+
+/// Same as [`context_with`], with every rate provider replaced by the server
+pub fn context(server: &MockServer) -> MainContext<'static> {
+    context_with(config(server))
+}
+
+/// Answer a single request
+fn serve(
+    mut stream: TcpStream,
+    routes: &mut [Route],
+    requests: &Mutex<Vec<String>>,
+    headers: &Mutex<Vec<Headers>>,
+) {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Requests from the APIs have no body
+    let mut request_headers = Vec::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+        if let Some((name, value)) = line.split_once(':') {
+            request_headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+        line.clear();
+    }
+    headers.lock().unwrap().push(request_headers);
+
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("")
+        .to_string();
+    requests.lock().unwrap().push(path.clone());
+
+    let not_found = Route::new("", 404, "Not found");
+    let route = match routes
+        .iter_mut()
+        .find(|route| path.starts_with(&route.prefix) && route.remaining != Some(0))
+    {
+        Some(route) => {
+            if let Some(remaining) = route.remaining.as_mut() {
+                *remaining -= 1;
+            }
+            &*route
+        }
+        None => &not_found,
+    };
+    let mut response = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        route.status,
+        route.body.len()
+    );
+    for (name, value) in &route.headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    response.push_str(&route.body);
+    let _ = stream.write_all(response.as_bytes());
+}
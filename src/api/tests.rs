@@ -0,0 +1,475 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests for the API, against a mock server
+
+use super::mock::{config, fixtures, MockServer, Route};
+use super::*;
+use crate::config::Http;
+use crate::currency::{BTC, CHF, DOGE, ETH, EUR, GBP, JPY, USD, USDT};
+
+/// Client without retries, so that error responses are returned right away
+fn client() -> Client {
+    Client::new(&Http {
+        retries: 0,
+        ..Http::default()
+    })
+    .unwrap()
+}
+
+fn day(d: &str) -> NaiveDate {
+    d.parse().unwrap()
+}
+
+#[test]
+fn currconv_rates_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let cfg = config(&server);
+    let api = CurrencyConverterApiCom::new(&cfg);
+
+    let rates = api.rates(&client(), &EUR, &[&USD, &GBP]).unwrap();
+    assert_eq!(rates.len(), 2);
+    assert_eq!(rates[0].dst(), &USD);
+    assert_eq!(rates[0].rate(), 1.0876);
+    assert_eq!(rates[1].dst(), &GBP);
+    assert_eq!(rates[1].rate(), 0.8532);
+    assert!(rates.iter().all(|r| r.provider() == api.provider_id()));
+
+    // All pairs in a single request
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(
+        requests[0].contains("q=EUR_USD%2CEUR_GBP"),
+        "{}",
+        requests[0]
+    );
+}
+
+#[test]
+fn currconv_missing_pair_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let cfg = config(&server);
+    let api = CurrencyConverterApiCom::new(&cfg);
+
+    // Missing pairs are skipped
+    let rates = api.rates(&client(), &EUR, &[&USD, &JPY]).unwrap();
+    assert_eq!(rates.len(), 1);
+    assert_eq!(rates[0].dst(), &USD);
+
+    // No pair at all is an error
+    let err = api.rates(&client(), &EUR, &[&JPY]).unwrap_err();
+    assert!(
+        matches!(&err, RateError::UnsupportedPair { src, dst, .. } if src == "EUR" && dst == "JPY"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn currconv_error_body_test() {
+    let server = MockServer::start(vec![Route::new(
+        "/api/v7/convert",
+        400,
+        fixtures::CURRCONV_INVALID_KEY,
+    )]);
+    let cfg = config(&server);
+    let api = CurrencyConverterApiCom::new(&cfg);
+
+    let err = api.rates(&client(), &EUR, &[&USD]).unwrap_err();
+    assert!(matches!(err, RateError::InvalidKey { .. }), "{:?}", err);
+    assert_eq!(err.exit_code(), 5);
+}
+
+#[test]
+fn currconv_pairs_per_request_test() {
+    let server = MockServer::start(vec![
+        Route::ok(
+            "/api/v7/convert?q=EUR_USD%2CEUR_GBP&",
+            fixtures::CURRCONV_EUR,
+        ),
+        Route::ok("/api/v7/convert?q=EUR_JPY&", r#"{"EUR_JPY":161.2}"#),
+        Route::ok("/api/v7/convert?q=EUR_CHF", "{}"),
+    ]);
+    let cfg = config(&server);
+    let api = CurrencyConverterApiCom::new(&cfg);
+
+    // At most 2 pairs in each request
+    let rates = api.rates(&client(), &EUR, &[&USD, &GBP, &JPY]).unwrap();
+    assert_eq!(rates.len(), 3);
+    assert_eq!(rates[2].dst(), &JPY);
+    assert_eq!(server.requests().len(), 2);
+
+    // Requests without any pair found are skipped, unless they all are
+    let rates = api.rates(&client(), &EUR, &[&USD, &GBP, &CHF]).unwrap();
+    assert_eq!(rates.len(), 2);
+    let err = api.rates(&client(), &EUR, &[&CHF, &CHF, &CHF]).unwrap_err();
+    assert!(
+        matches!(&err, RateError::UnsupportedPair { dst, .. } if dst == "CHF, CHF, CHF"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn currconv_limit_errors_test() {
+    let server = MockServer::start(vec![
+        Route::new("/api/v7/convert", 400, fixtures::CURRCONV_TOO_MANY_PAIRS).times(1),
+        Route::new("/api/v7/convert", 400, fixtures::CURRCONV_RATE_LIMITED),
+    ]);
+    let cfg = config(&server);
+    let api = CurrencyConverterApiCom::new(&cfg);
+
+    // Too many pairs is not an exceeded quota
+    let err = api.rates(&client(), &EUR, &[&USD]).unwrap_err();
+    assert!(
+        matches!(&err, RateError::UnsupportedPair { src, dst, .. } if src == "EUR" && dst == "USD"),
+        "{:?}",
+        err
+    );
+    let err = api.rates(&client(), &EUR, &[&USD]).unwrap_err();
+    assert!(matches!(err, RateError::QuotaExceeded { .. }), "{:?}", err);
+}
+
+#[test]
+fn http_status_test() {
+    let server = MockServer::start(vec![
+        Route::new("/api/v7/convert", 500, "Internal error"),
+        Route::new("/latest", 401, ""),
+    ]);
+    let cfg = config(&server);
+    let client = client();
+
+    let err = CurrencyConverterApiCom::new(&cfg)
+        .rates(&client, &EUR, &[&USD])
+        .unwrap_err();
+    assert!(
+        matches!(&err, RateError::HttpStatus { status: 500, body, .. } if body == "Internal error"),
+        "{:?}",
+        err
+    );
+    let err = ExchangeRatesApiIo::new(&cfg)
+        .rates(&client, &EUR, &[&USD])
+        .unwrap_err();
+    assert!(matches!(err, RateError::InvalidKey { .. }), "{:?}", err);
+}
+
+#[test]
+fn network_error_test() {
+    let server = MockServer::start(vec![]);
+    let mut cfg = config(&server);
+    // Nothing listens on port 1
+    cfg.apis.ecb.base_url = String::from("http://127.0.0.1:1");
+
+    let err = Ecb::new(&cfg).rates(&client(), &EUR, &[&USD]).unwrap_err();
+    assert!(matches!(err, RateError::Network { .. }), "{:?}", err);
+    assert_eq!(err.exit_code(), 3);
+}
+
+#[test]
+fn exchangeratesapi_rates_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/latest?base=EUR",
+        fixtures::EXCHANGERATES_LATEST_EUR,
+    )]);
+    let cfg = config(&server);
+    let api = ExchangeRatesApiIo::new(&cfg);
+
+    // Every known currency returned is kept, even if not asked for
+    let rates = api.rates(&client(), &EUR, &[&USD]).unwrap();
+    let mut dsts: Vec<&str> = rates.iter().map(|r| r.dst().get_main_iso()).collect();
+    dsts.sort_unstable();
+    assert_eq!(dsts, vec!["CHF", "GBP", "JPY", "USD"]);
+}
+
+#[test]
+fn exchangeratesapi_key_test() {
+    let server = MockServer::start(vec![
+        Route::ok("/latest", fixtures::EXCHANGERATES_LATEST_EUR),
+        Route::ok("/2026-03-14", fixtures::EXCHANGERATES_HISTORICAL_EUR),
+    ]);
+    let mut cfg = config(&server);
+
+    // No key, no parameter
+    ExchangeRatesApiIo::new(&cfg)
+        .rates(&client(), &EUR, &[&USD])
+        .unwrap();
+    assert_eq!(server.requests(), vec!["/latest?base=EUR"]);
+
+    cfg.apis.exchange_rates_api_io.key = String::from("secret-key");
+    let api = ExchangeRatesApiIo::new(&cfg);
+    api.rates(&client(), &EUR, &[&USD]).unwrap();
+    api.historical_rates(&client(), &EUR, &[&USD], day("2026-03-14"))
+        .unwrap();
+    let requests = server.requests();
+    assert_eq!(requests[1], "/latest?base=EUR&access_key=secret-key");
+    assert_eq!(requests[2], "/2026-03-14?base=EUR&access_key=secret-key");
+}
+
+#[test]
+fn key_redaction_test() {
+    let server = MockServer::start(vec![Route::new(
+        "/api/v7/convert",
+        500,
+        "Unknown apiKey secret-key",
+    )]);
+    let mut cfg = config(&server);
+    cfg.apis.currency_converter_api_com.key = String::from("secret-key");
+
+    let err = CurrencyConverterApiCom::new(&cfg)
+        .rates(&client(), &EUR, &[&USD])
+        .unwrap_err();
+    assert!(matches!(err, RateError::HttpStatus { .. }), "{:?}", err);
+    assert!(
+        !format!("{} {:?}", err, err).contains("secret-key"),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains("[REDACTED]"), "{}", err);
+
+    // Transport errors mention the url, with the key in the query
+    cfg.apis.currency_converter_api_com.base_url = String::from("http://127.0.0.1:1");
+    let err = CurrencyConverterApiCom::new(&cfg)
+        .rates(&client(), &EUR, &[&USD])
+        .unwrap_err();
+    assert!(matches!(err, RateError::Network { .. }), "{:?}", err);
+    assert!(
+        !format!("{} {:?}", err, err).contains("secret-key"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn exchangeratesapi_historical_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/2026-03-14?base=EUR",
+        fixtures::EXCHANGERATES_HISTORICAL_EUR,
+    )]);
+    let cfg = config(&server);
+    let api = ExchangeRatesApiIo::new(&cfg);
+
+    let rates = api
+        .historical_rates(&client(), &EUR, &[&USD], day("2026-03-14"))
+        .unwrap();
+    assert_eq!(rates.len(), 2);
+    // Dated with the day of publication
+    for rate in &rates {
+        assert_eq!(rate.date().naive_utc().date(), day("2026-03-13"));
+        assert_eq!(rate.cache_until(), &None);
+    }
+}
+
+#[test]
+fn exchangeratesapi_rate_limited_test() {
+    let server = MockServer::start(vec![Route::new(
+        "/latest",
+        429,
+        fixtures::EXCHANGERATES_RATE_LIMITED,
+    )
+    .header("Retry-After", "3600")]);
+    let cfg = config(&server);
+    let api = ExchangeRatesApiIo::new(&cfg);
+
+    let err = api.rates(&client(), &EUR, &[&USD]).unwrap_err();
+    assert!(
+        matches!(err, RateError::QuotaExceeded { retry_after: Some(d), .. } if d == Duration::hours(1)),
+        "{:?}",
+        err
+    );
+    assert_eq!(
+        err.to_string(),
+        "Request quota of exchangeratesapi.io exceeded, please retry in 60 minute(s)"
+    );
+}
+
+#[test]
+fn exchangeratesapi_error_body_test() {
+    // Errors may come with a successful status
+    let server = MockServer::start(vec![Route::ok(
+        "/latest",
+        fixtures::EXCHANGERATES_RATE_LIMITED,
+    )]);
+    let cfg = config(&server);
+    let api = ExchangeRatesApiIo::new(&cfg);
+
+    let err = api.rates(&client(), &EUR, &[&USD]).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            RateError::QuotaExceeded {
+                retry_after: None,
+                ..
+            }
+        ),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn exchangeratesapi_invalid_base_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/latest",
+        fixtures::EXCHANGERATES_INVALID_BASE,
+    )]);
+    let cfg = config(&server);
+    let api = ExchangeRatesApiIo::new(&cfg);
+
+    let err = api.rates(&client(), &EUR, &[&USD, &GBP]).unwrap_err();
+    match err {
+        RateError::UnsupportedPair { provider, src, dst } => {
+            assert_eq!(provider, "exchangeratesapi.io");
+            assert_eq!(src, "EUR");
+            assert_eq!(dst, "USD, GBP");
+        }
+        err => panic!("{:?}", err),
+    }
+}
+
+#[test]
+fn malformed_payload_test() {
+    let server = MockServer::start(vec![
+        Route::ok("/api/v7/convert", fixtures::MALFORMED_JSON),
+        Route::ok("/latest", fixtures::MALFORMED_JSON),
+        Route::ok("/stats/eurofxref/", fixtures::MALFORMED_JSON),
+    ]);
+    let cfg = config(&server);
+    let client = client();
+
+    let errors = vec![
+        CurrencyConverterApiCom::new(&cfg)
+            .rates(&client, &EUR, &[&USD])
+            .unwrap_err(),
+        ExchangeRatesApiIo::new(&cfg)
+            .rates(&client, &EUR, &[&USD])
+            .unwrap_err(),
+        Ecb::new(&cfg).rates(&client, &EUR, &[&USD]).unwrap_err(),
+    ];
+    for err in errors {
+        assert!(
+            matches!(err, RateError::MalformedPayload { .. }),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.exit_code(), 8);
+    }
+}
+
+#[test]
+fn ecb_rates_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/stats/eurofxref/eurofxref-daily.xml",
+        fixtures::ECB_DAILY,
+    )]);
+    let cfg = config(&server);
+    let api = Ecb::new(&cfg);
+
+    // Cross rates from CHF, unknown currencies are ignored
+    let rates = api.rates(&client(), &CHF, &[&USD]).unwrap();
+    assert_eq!(rates.len(), 4);
+    let chf_eur = rates.iter().find(|r| r.dst() == &EUR).unwrap();
+    assert_eq!(chf_eur.rate(), 1. / 0.9412);
+    let chf_usd = rates.iter().find(|r| r.dst() == &USD).unwrap();
+    assert_eq!(chf_usd.rate(), 1.0876 / 0.9412);
+    assert!(rates.iter().all(|r| r.cache_until().is_some()));
+}
+
+#[test]
+fn ecb_historical_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/stats/eurofxref/eurofxref-hist",
+        fixtures::ECB_HIST_90D,
+    )]);
+    let cfg = config(&server);
+    let api = Ecb::new(&cfg);
+
+    let rates = api
+        .historical_rates(&client(), &USD, &[&GBP], day("2026-10-15"))
+        .unwrap();
+    // Three days, with rates to EUR and GBP
+    assert_eq!(rates.len(), 6);
+    let usd_gbp = rates
+        .iter()
+        .find(|r| r.dst() == &GBP && r.date().naive_utc().date() == day("2026-10-15"))
+        .unwrap();
+    assert_eq!(usd_gbp.rate(), 0.6);
+    assert_eq!(usd_gbp.src(), &USD);
+}
+
+#[test]
+fn no_historical_rates_test() {
+    let server = MockServer::start(vec![]);
+    let cfg = config(&server);
+    let api = CurrencyConverterApiCom::new(&cfg);
+
+    let rates = api
+        .historical_rates(&client(), &EUR, &[&USD], day("2026-03-14"))
+        .unwrap();
+    assert!(rates.is_empty());
+    // Not even a request
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn coingecko_rates_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/api/v3/simple/price",
+        fixtures::COINGECKO_PRICES,
+    )]);
+    let cfg = config(&server);
+    let api = CoinGecko::new(&cfg);
+
+    let rates = api.rates(&client(), &BTC, &[&EUR, &ETH, &USDT]).unwrap();
+    assert_eq!(rates.len(), 3);
+    assert_eq!(rates[0].dst(), &EUR);
+    assert_eq!(rates[0].rate(), 61890.2);
+    assert_eq!(rates[1].dst(), &ETH);
+    assert_eq!(rates[1].rate(), 67012.5 / 2450.75);
+    assert_eq!(rates[2].rate(), 67012.5 / 1.0002);
+    // Short cache for volatile prices
+    assert!(rates[0].cache_until().unwrap() < Utc::now() + Duration::minutes(6));
+    assert_eq!(
+        server.requests()[0],
+        "/api/v3/simple/price?ids=bitcoin%2Cethereum%2Ctether&vs_currencies=usd%2Ceur"
+    );
+
+    // From fiat currencies, prices are inverted
+    let rates = api.rates(&client(), &EUR, &[&BTC]).unwrap();
+    assert_eq!(rates[0].rate(), 1. / 61890.2);
+
+    // DOGE is not in the response
+    let err = api.rates(&client(), &DOGE, &[&EUR]).unwrap_err();
+    assert!(
+        matches!(err, RateError::UnsupportedPair { .. }),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn supports_test() {
+    let cfg = Config::default();
+    let coingecko = CoinGecko::new(&cfg);
+    let currconv = CurrencyConverterApiCom::new(&cfg);
+
+    assert!(coingecko.supports(&BTC, &EUR));
+    assert!(coingecko.supports(&USD, &ETH));
+    assert!(!coingecko.supports(&USD, &EUR));
+    assert!(currconv.supports(&USD, &EUR));
+    assert!(!currconv.supports(&BTC, &EUR));
+}
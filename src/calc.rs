@@ -0,0 +1,454 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Calc subcommand: arithmetic on amounts in several currencies, like
+//! `120 USD + 35 € - 10% in GBP`
+//!
+//! Grammar:
+//! ```text
+//! input   := expr [ ("in" | "to") currency ]
+//! expr    := term { ("+" | "-") term }
+//! term    := unary { ("*" | "/") unary }
+//! unary   := "-" unary | primary [ "%" ]
+//! primary := number [ currency ] | currency number | "(" expr ")"
+//! ```
+//! Adding or subtracting a percentage increases or decreases the left hand side by that
+//! percentage. Amounts in different currencies are kept apart until the end, so that each of
+//! them is converted only once.
+
+use anyhow::{anyhow, Result};
+use log::trace;
+
+use std::fmt;
+
+use crate::convert::current_rates;
+use crate::currency::{self, Currency, PriceTag};
+use crate::MainContext;
+
+#[cfg(test)]
+mod tests;
+
+/// Error in an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    /// The expression can’t be read, at a character offset
+    Syntax {
+        position: usize,
+        message: String,
+    },
+    /// Operation on incompatible operands, like the product of two amounts
+    Dimension(String),
+    DivisionByZero,
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::Syntax { position, message } => {
+                write!(f, "{}, at character {}", message, position + 1)
+            }
+            CalcError::Dimension(message) => write!(f, "{}", message),
+            CalcError::DivisionByZero => write!(f, "Division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Currency(&'static Currency),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    LParen,
+    RParen,
+    /// `in` or `to`, before the target currency
+    In,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Currency(c) => write!(f, "{}", c),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::In => write!(f, "in"),
+        }
+    }
+}
+
+/// Split an expression in tokens, with the character offset of each
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, CalcError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' | '−' => Token::Minus,
+            '*' | '×' => Token::Star,
+            '/' | '÷' => Token::Slash,
+            '%' => Token::Percent,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            c if c.is_ascii_digit() || c == '.' => {
+                // A comma followed by digits is a decimal separator too, like in 12,50 €
+                let in_number = |j: usize| {
+                    chars[j].is_ascii_digit()
+                        || chars[j] == '.'
+                        || (chars[j] == ','
+                            && matches!(chars.get(j + 1), Some(d) if d.is_ascii_digit()))
+                };
+                while i < chars.len() && in_number(i) {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let number = number
+                    .replace(',', ".")
+                    .parse()
+                    .map_err(|_| CalcError::Syntax {
+                        position: start,
+                        message: format!("Invalid number '{}'", number),
+                    })?;
+                tokens.push((start, Token::Number(number)));
+                continue;
+            }
+            c if c.is_alphabetic() => {
+                while i < chars.len() && chars[i].is_alphabetic() {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let token = match word.to_lowercase().as_str() {
+                    "in" | "to" => Token::In,
+                    _ => Token::Currency(currency::from_iso_or_symbol(&word).ok_or_else(|| {
+                        CalcError::Syntax {
+                            position: start,
+                            message: format!("Unknown currency '{}'", word),
+                        }
+                    })?),
+                };
+                tokens.push((start, token));
+                continue;
+            }
+            c => {
+                Token::Currency(currency::from_iso_or_symbol(&c.to_string()).ok_or_else(|| {
+                    CalcError::Syntax {
+                        position: start,
+                        message: format!("Unexpected character '{}'", c),
+                    }
+                })?)
+            }
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+/// Result of an expression or of a part of it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    /// A percentage, 10 for 10%
+    Percent(f64),
+    /// Sum of amounts in distinct currencies
+    Amount(Vec<(&'static Currency, f64)>),
+}
+
+impl Value {
+    /// Multiply by a number, whatever the value
+    fn scale(self, factor: f64) -> Value {
+        match self {
+            Value::Number(n) => Value::Number(n * factor),
+            Value::Percent(p) => Value::Percent(p * factor),
+            Value::Amount(amounts) => {
+                Value::Amount(amounts.into_iter().map(|(c, a)| (c, a * factor)).collect())
+            }
+        }
+    }
+
+    /// Value as a plain number, None for amounts
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Percent(p) => Some(p / 100.),
+            Value::Amount(_) => None,
+        }
+    }
+
+    fn add(self, rhs: Value) -> Result<Value, CalcError> {
+        match (self, rhs) {
+            // Increase by a percentage
+            (lhs @ Value::Number(_), Value::Percent(p))
+            | (lhs @ Value::Amount(_), Value::Percent(p)) => Ok(lhs.scale(1. + p / 100.)),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            (Value::Percent(a), Value::Percent(b)) => Ok(Value::Percent(a + b)),
+            (Value::Amount(mut amounts), Value::Amount(other)) => {
+                for (currency, amount) in other {
+                    match amounts.iter_mut().find(|(c, _)| *c == currency) {
+                        Some((_, sum)) => *sum += amount,
+                        None => amounts.push((currency, amount)),
+                    }
+                }
+                Ok(Value::Amount(amounts))
+            }
+            _ => Err(CalcError::Dimension(String::from(
+                "Can’t add or subtract an amount and a number, is a currency missing?",
+            ))),
+        }
+    }
+
+    fn mul(self, rhs: Value) -> Result<Value, CalcError> {
+        match (rhs.as_number(), self.as_number()) {
+            (Some(factor), _) => Ok(self.scale(factor)),
+            (None, Some(factor)) => Ok(rhs.scale(factor)),
+            (None, None) => Err(CalcError::Dimension(String::from(
+                "Can’t multiply an amount by another amount",
+            ))),
+        }
+    }
+
+    fn div(self, rhs: Value) -> Result<Value, CalcError> {
+        match rhs.as_number() {
+            Some(0.) => Err(CalcError::DivisionByZero),
+            Some(divisor) => Ok(self.scale(1. / divisor)),
+            None => Err(CalcError::Dimension(String::from(
+                "Can’t divide by an amount",
+            ))),
+        }
+    }
+}
+
+/// Recursive descent parser, evaluating the expression as it goes
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Length of the input, for errors at the end
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.len, |(p, _)| *p)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, CalcError> {
+        let message = match self.peek() {
+            Some(token) => format!("{}, found '{}'", message, token),
+            None => format!("{}, found the end of the expression", message),
+        };
+        Err(CalcError::Syntax {
+            position: self.position(),
+            message,
+        })
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// input := expr [ ("in" | "to") currency ]
+    fn input(&mut self) -> Result<(Value, Option<&'static Currency>), CalcError> {
+        let value = self.expr()?;
+        let target = if self.eat(&Token::In) {
+            match self.peek() {
+                Some(Token::Currency(c)) => {
+                    let c = *c;
+                    self.next += 1;
+                    Some(c)
+                }
+                _ => return self.error("Expected a currency"),
+            }
+        } else {
+            None
+        };
+        if self.peek().is_some() {
+            return self.error("Expected an operator");
+        }
+        Ok((value, target))
+    }
+
+    /// expr := term { ("+" | "-") term }
+    fn expr(&mut self) -> Result<Value, CalcError> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat(&Token::Plus) {
+                value = value.add(self.term()?)?;
+            } else if self.eat(&Token::Minus) {
+                value = value.add(self.term()?.scale(-1.))?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// term := unary { ("*" | "/") unary }
+    fn term(&mut self) -> Result<Value, CalcError> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat(&Token::Star) {
+                value = value.mul(self.unary()?)?;
+            } else if self.eat(&Token::Slash) {
+                value = value.div(self.unary()?)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// unary := "-" unary | primary [ "%" ]
+    fn unary(&mut self) -> Result<Value, CalcError> {
+        if self.eat(&Token::Minus) {
+            return Ok(self.unary()?.scale(-1.));
+        }
+        let value = self.primary()?;
+        if self.eat(&Token::Percent) {
+            match value {
+                Value::Number(n) => Ok(Value::Percent(n)),
+                _ => Err(CalcError::Dimension(String::from(
+                    "Only numbers can be percentages",
+                ))),
+            }
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// primary := number [ currency ] | currency number | "(" expr ")"
+    fn primary(&mut self) -> Result<Value, CalcError> {
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.next += 1;
+                if let Some(Token::Currency(c)) = self.peek() {
+                    let c = *c;
+                    self.next += 1;
+                    Ok(Value::Amount(vec![(c, n)]))
+                } else {
+                    Ok(Value::Number(n))
+                }
+            }
+            Some(Token::Currency(c)) => {
+                self.next += 1;
+                match self.peek() {
+                    Some(Token::Number(n)) => {
+                        let n = *n;
+                        self.next += 1;
+                        Ok(Value::Amount(vec![(c, n)]))
+                    }
+                    _ => self.error(&format!("Expected an amount after {}", c)),
+                }
+            }
+            Some(Token::LParen) => {
+                self.next += 1;
+                let value = self.expr()?;
+                if !self.eat(&Token::RParen) {
+                    return self.error("Expected ')'");
+                }
+                Ok(value)
+            }
+            _ => self.error("Expected a number, an amount or '('"),
+        }
+    }
+}
+
+/// Evaluate an expression, without converting amounts. Returns the result and the target
+/// currency, if any
+pub fn evaluate(input: &str) -> Result<(Value, Option<&'static Currency>), CalcError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        next: 0,
+        len: input.chars().count(),
+    };
+    parser.input()
+}
+
+/// Evaluate an expression and convert the result to the target currency or, without one, to its
+/// currency and the destination currencies. Returns lines to print
+pub fn calc(ctxt: &MainContext, input: &str) -> Result<Vec<String>> {
+    let (value, target) = evaluate(input)?;
+    trace!("calc {} = {:?} in {:?}", input, value, target);
+    let amounts = match value {
+        Value::Number(n) => return Ok(vec![format!("{}", n)]),
+        Value::Percent(p) => return Ok(vec![format!("{}%", p)]),
+        Value::Amount(amounts) => amounts,
+    };
+
+    let targets: Vec<&Currency> = match target {
+        Some(target) => vec![target],
+        None => {
+            let mut targets = Vec::new();
+            if let [(currency, _)] = amounts.as_slice() {
+                targets.push(*currency);
+            }
+            for dst in &ctxt.destination_currencies {
+                if !targets.contains(dst) {
+                    targets.push(dst);
+                }
+            }
+            targets
+        }
+    };
+
+    let mut totals = vec![0.; targets.len()];
+    for (currency, amount) in amounts {
+        let rates = current_rates(ctxt, currency, &targets)?;
+        for ((total, target), rate) in totals.iter_mut().zip(&targets).zip(rates) {
+            let rate = rate.ok_or_else(|| anyhow!("No rate from {} to {}", currency, target))?;
+            *total += amount * rate.rate();
+        }
+    }
+
+    Ok(targets
+        .iter()
+        .zip(totals)
+        .map(|(target, total)| format!("= {}", PriceTag::new(target, total)))
+        .collect())
+}
+
+/// Parse arguments for the calc subcommand and run it
+pub(crate) fn run(ctxt: MainContext, expression: Vec<String>) -> Result<()> {
+    for line in calc(&ctxt, &expression.join(" "))? {
+        println!("{}", line);
+    }
+    Ok(())
+}
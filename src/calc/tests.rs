@@ -0,0 +1,104 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests for the calc subcommand
+
+use test_case::test_case;
+
+use super::*;
+use crate::api::mock::{self, fixtures, MockServer, Route};
+use crate::currency::{BTC, EUR, GBP, USD};
+
+fn amounts(amounts: &[(&'static Currency, f64)]) -> Value {
+    Value::Amount(amounts.to_vec())
+}
+
+#[test_case("2 + 3 * 4", Value::Number(14.) ; "precedence")]
+#[test_case("(2 + 3) * 4", Value::Number(20.) ; "parentheses")]
+#[test_case("-2 - -3", Value::Number(1.) ; "negative")]
+#[test_case("10%", Value::Percent(10.) ; "percent")]
+#[test_case("200 - 10%", Value::Number(180.) ; "minus percent")]
+#[test_case("12 EUR", amounts(&[(&EUR, 12.)]) ; "amount")]
+#[test_case("€12 + 3eur", amounts(&[(&EUR, 15.)]) ; "same currency")]
+#[test_case("$120 + 35 € - 10%", amounts(&[(&USD, 108.), (&EUR, 31.5)]) ; "mixed currencies")]
+#[test_case("3 * 12 EUR / 4", amounts(&[(&EUR, 9.)]) ; "scalar product")]
+#[test_case("200 EUR * 10%", amounts(&[(&EUR, 20.)]) ; "percent of an amount")]
+#[test_case("(10 USD + 2 £) * 2", amounts(&[(&USD, 20.), (&GBP, 4.)]) ; "distributed")]
+#[test_case("0.5 ₿", amounts(&[(&BTC, 0.5)]) ; "crypto")]
+#[test_case("12,50 € + 3 €", amounts(&[(&EUR, 15.5)]) ; "decimal comma")]
+fn evaluate_test(input: &str, expected: Value) {
+    assert_eq!(evaluate(input).unwrap(), (expected, None));
+}
+
+#[test]
+fn target_test() {
+    assert_eq!(
+        evaluate("120 USD in GBP").unwrap(),
+        (amounts(&[(&USD, 120.)]), Some(&GBP))
+    );
+    assert_eq!(
+        evaluate("1 eur TO usd").unwrap(),
+        (amounts(&[(&EUR, 1.)]), Some(&USD))
+    );
+}
+
+#[test_case("12 EUR * 3 USD", "Can’t multiply an amount by another amount")]
+#[test_case("12 EUR / 3 EUR", "Can’t divide by an amount")]
+#[test_case(
+    "12 EUR + 3",
+    "Can’t add or subtract an amount and a number, is a currency missing?"
+)]
+#[test_case(
+    "10% + 3 EUR",
+    "Can’t add or subtract an amount and a number, is a currency missing?"
+)]
+#[test_case("3 EUR / 0", "Division by zero")]
+#[test_case("2 (3)", "Expected an operator, found '(', at character 3")]
+#[test_case(
+    "(2 + 3",
+    "Expected ')', found the end of the expression, at character 7"
+)]
+#[test_case(
+    "2 + ",
+    "Expected a number, an amount or '(', found the end of the expression, at character 5"
+)]
+#[test_case("2 XYZ", "Unknown currency 'XYZ', at character 3")]
+#[test_case("2 # 3", "Unexpected character '#', at character 3")]
+#[test_case("2 EUR in 3", "Expected a currency, found '3', at character 10")]
+#[test_case("€ + 2", "Expected an amount after EUR, found '+', at character 3")]
+fn error_test(input: &str, message: &str) {
+    assert_eq!(evaluate(input).unwrap_err().to_string(), message);
+}
+
+#[test]
+fn calc_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let ctxt = mock::context(&server);
+
+    assert_eq!(calc(&ctxt, "2 * (3 + 4)").unwrap(), vec!["14"]);
+    assert_eq!(
+        calc(&ctxt, "100 EUR - 10%").unwrap(),
+        vec!["= EUR 90.00", "= USD 97.88", "= GBP 76.79"]
+    );
+    // Converted through EUR, with the rates stored
+    assert_eq!(
+        calc(&ctxt, "10 EUR + 10.876 USD in GBP").unwrap(),
+        vec!["= GBP 17.06"]
+    );
+    assert_eq!(server.requests().len(), 1);
+}
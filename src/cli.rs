@@ -0,0 +1,400 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Command line interface of the sesters binary. Not part of the API of the library, it may change
+//! in any release
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use clap::{crate_authors, crate_description, crate_version, ArgGroup, Parser, Subcommand};
+use log::{error, info};
+
+use std::path::PathBuf;
+
+use crate::api::{RateError, DB_EXIT_CODE};
+use crate::config::Config;
+use crate::currency::{self, Currency};
+use crate::markup::Markup;
+use crate::MainContext;
+use crate::{alert, calc, convert, history, quota, repl, rpc, serve, stats, tabular};
+
+#[derive(Parser, Debug)]
+#[clap(name = "sesters",
+  version = crate_version!(),
+  author = crate_authors!(),
+  about = concat!(crate_description!(),
+    "\n",
+    "https://cj.rs/sesters/"),
+  long_about = None)]
+#[clap(dont_collapse_args_in_usage = true)]
+pub(crate) struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+
+    /// Target currency by ISO symbol, uses defaults from the configuration file if not set
+    #[clap(short = 't', long = "to", value_name = "CURRENCY", global = true)]
+    to: Vec<String>,
+    // TODO Add flag for verbosity
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Commands {
+    /// Perform currency conversion to your preferred currency,
+    /// from a price tag found in plain text
+    #[clap(infer_subcommands = true)]
+    Convert {
+        /// Read text containing price tag from stdin
+        #[clap(long = "stdin")]
+        stdin: bool,
+
+        /// Read stdin line by line as it comes, printing the conversions of each line prefixed
+        /// with its number, like in `tail -f log | sesters convert --stream`
+        #[clap(long, value_parser, conflicts_with_all = &["sum", "plain-text"])]
+        stream: bool,
+
+        /// Don’t add the conversions to the history
+        #[clap(long, value_parser)]
+        no_history: bool,
+
+        /// Format of the text: markup is removed and character references are decoded, so that
+        /// price tags split by markup are found
+        #[clap(
+            long,
+            value_enum,
+            value_parser,
+            default_value = "text",
+            conflicts_with = "stream"
+        )]
+        markup: Markup,
+
+        /// Prefix the conversions of each price tag with the byte offsets of the price tag in
+        /// the original text, like 12-27
+        #[clap(long, value_parser, conflicts_with_all = &["stream", "sum"])]
+        locate: bool,
+
+        /// Find at most n price tag in the text, i.e. 3
+        #[clap(short = 'n')]
+        findn: Option<usize>,
+
+        /// Convert with the rates in effect on that day, like 2026-03-14
+        #[clap(long, value_name = "DATE", value_parser)]
+        at: Option<NaiveDate>,
+
+        /// Fee profile from the configuration to apply, like card or wire. Overrides the one of
+        /// the configuration
+        #[clap(long, value_name = "PROFILE", value_parser)]
+        fee: Option<String>,
+
+        /// Convert all price tags with the same rates and show the totals in each currency
+        #[clap(long, value_parser)]
+        sum: bool,
+
+        /// Plain text to extract a price tag from. If not set, plain text will be read from stdin
+        plain_text: Vec<String>,
+    },
+
+    /// Show exchange rates from a currency to others
+    #[clap(infer_subcommands = true)]
+    Rate {
+        /// Source currency by ISO symbol
+        #[clap(value_parser)]
+        src: String,
+
+        /// Destination currencies by ISO symbol. Uses target currencies if not set
+        #[clap(value_parser)]
+        dst: Vec<String>,
+
+        /// Show the rates in effect on that day, like 2026-03-14
+        #[clap(long, value_name = "DATE", value_parser)]
+        at: Option<NaiveDate>,
+
+        /// Show statistics on the rates stored over a period, like 90d, 12w, 6m or 1y
+        #[clap(long, value_name = "PERIOD", value_parser = stats::parse_period, conflicts_with = "at")]
+        stats: Option<u32>,
+    },
+
+    /// Compute with amounts in several currencies, like "120 USD + 35 € - 10% in GBP"
+    #[clap(infer_subcommands = true)]
+    Calc {
+        /// Expression with numbers, amounts, percentages, + - * / and parentheses, optionally
+        /// followed by "in" or "to" and the currency of the result
+        #[clap(value_parser, required = true)]
+        expression: Vec<String>,
+    },
+
+    /// Convert interactively, line by line, with commands like :to, :rate or :offline to change
+    /// the session
+    #[clap(infer_subcommands = true)]
+    Repl,
+
+    /// Convert a column of amounts in CSV or TSV, appending a column for each target currency
+    #[clap(infer_subcommands = true)]
+    Csv {
+        /// Column with the amounts, or with whole price tags like "12,50 €" without
+        /// --currency-col
+        #[clap(long, value_name = "COLUMN", value_parser)]
+        amount_col: String,
+
+        /// Column with the currencies of the amounts, by ISO symbol or symbol
+        #[clap(long, value_name = "COLUMN", value_parser)]
+        currency_col: Option<String>,
+
+        /// Column with the day of each row, like 2026-03-14, to convert with the rates in effect
+        /// on that day
+        #[clap(long, value_name = "COLUMN", value_parser)]
+        date_col: Option<String>,
+
+        /// Read and write tab separated values
+        #[clap(long, value_parser, conflicts_with = "delimiter")]
+        tsv: bool,
+
+        /// Field delimiter
+        #[clap(short, long, default_value = ",", value_parser)]
+        delimiter: char,
+
+        /// File to read, stdin if not set. The result is written to stdout
+        #[clap(value_parser)]
+        file: Option<PathBuf>,
+    },
+
+    /// Answer conversion, rate and history requests in JSON over HTTP, see the serve module for
+    /// the endpoints
+    #[clap(infer_subcommands = true)]
+    Serve {
+        /// Address and port to listen on
+        #[clap(
+            long,
+            value_name = "ADDRESS",
+            default_value = "127.0.0.1:8080",
+            value_parser
+        )]
+        listen: String,
+    },
+
+    /// Answer extraction and conversion requests in JSON-RPC on stdin and stdout, one message per
+    /// line, see the rpc module for the methods
+    #[clap(infer_subcommands = true)]
+    Rpc,
+
+    /// Show requests sent to rate providers and how much of their quotas is left
+    #[clap(infer_subcommands = true)]
+    Status,
+
+    /// Access and manage the history of price tags extracted
+    #[clap(infer_subcommands = true)]
+    History {
+        #[clap(subcommand)]
+        command: HistoryCommands,
+    },
+
+    /// Manage alerts on rates, evaluated when new rates are stored
+    #[clap(infer_subcommands = true, alias = "watch")]
+    Alert {
+        #[clap(subcommand)]
+        command: AlertCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum AlertCommands {
+    /// Add an alert on the rate from a currency to another
+    #[clap(group(ArgGroup::new("threshold").required(true).args(&["above", "below"])))]
+    Add {
+        /// Source currency by ISO symbol
+        #[clap(value_parser)]
+        src: String,
+
+        /// Destination currency by ISO symbol
+        #[clap(value_parser)]
+        dst: String,
+
+        /// Trigger when the rate reaches that value or more, or rises by that percentage with
+        /// --days
+        #[clap(long, value_parser)]
+        above: Option<f64>,
+
+        /// Trigger when the rate reaches that value or less, or falls by that percentage with
+        /// --days
+        #[clap(long, value_parser)]
+        below: Option<f64>,
+
+        /// Compare to the rate that many days before, the threshold is then a move in percent
+        #[clap(long, value_parser)]
+        days: Option<u32>,
+    },
+
+    /// List alerts
+    List,
+
+    /// Remove an alert
+    Remove {
+        /// Id of the alert, as listed
+        #[clap(value_parser)]
+        id: u32,
+    },
+
+    /// Evaluate alerts against the latest rates stored, without retrieving rates online
+    Check {
+        /// Command run in a shell for each triggered alert instead of printing it, overrides the
+        /// one of the configuration
+        #[clap(long, value_parser)]
+        hook: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum HistoryCommands {
+    /// List entries in the history
+    #[clap(infer_subcommands = true)]
+    List {
+        /// Don’t perform conversions of the history content
+        #[clap(short = 'n', long = "noconvert")]
+        no_convert: bool,
+        /// Also show conversions with today’s rates, and the change since the entry was added
+        #[clap(short = 'c', long = "current", value_parser)]
+        current: bool,
+        /// Show at most <N> entries
+        #[clap(short = 'm', long = "max", default_value = "50")]
+        max_entries: usize,
+    },
+
+    /// Removes older entries from history
+    #[clap(infer_subcommands = true)]
+    #[clap(group(ArgGroup::new("expire").args(&["all", "days"])))]
+    Expire {
+        /// Remove without printing or confirming
+        #[clap(short = 'y')]
+        yes: bool,
+
+        /// Removes all entries from history
+        #[clap(long)]
+        all: bool,
+
+        /// Delete all entries older than the given number of days
+        #[clap(default_value = crate::history::EXPIRE_DELAY, long)]
+        days: usize,
+    },
+}
+
+/// Parse the arguments and run the subcommand
+pub fn run() -> Result<()> {
+    Config::init()?;
+    let cfg = Config::new()?;
+
+    // Argument parsing
+    let args = Cli::parse();
+
+    let txt_destination_currencies = if args.to.len() == 0 {
+        // Use configuration currency if none are specified
+        &cfg.currencies
+    } else {
+        &args.to
+    };
+    let destination_currencies: Vec<&Currency> = txt_destination_currencies
+        .iter()
+        .filter_map(|iso_name| {
+            currency::existing_from_iso(&iso_name).or_else(|| {
+                error!("Invalid currency iso symbol '{}', ignored", iso_name);
+                None
+            })
+        })
+        .collect();
+
+    let ctxt = MainContext::new(cfg, destination_currencies)?;
+
+    match args.command {
+        Commands::Convert {
+            stdin,
+            stream,
+            no_history,
+            markup,
+            locate,
+            findn,
+            at,
+            fee,
+            sum,
+            plain_text,
+        } => {
+            let input = if stream {
+                convert::Input::Stream
+            } else if stdin {
+                convert::Input::Stdin
+            } else {
+                convert::Input::Args(plain_text)
+            };
+            let options = convert::Options {
+                findn,
+                at,
+                fee,
+                sum,
+                history: !no_history,
+                markup,
+                locate,
+            };
+            convert::run(ctxt, input, options)?
+        }
+        Commands::Rate {
+            src,
+            dst,
+            at,
+            stats,
+        } => convert::run_rate(ctxt, src, dst, at, stats)?,
+        Commands::Calc { expression } => calc::run(ctxt, expression)?,
+        Commands::Repl => repl::run(ctxt)?,
+        Commands::Csv {
+            amount_col,
+            currency_col,
+            date_col,
+            tsv,
+            delimiter,
+            file,
+        } => {
+            let delimiter = if tsv { '\t' } else { delimiter };
+            if !delimiter.is_ascii() {
+                bail!("The delimiter must be an ASCII character");
+            }
+            let columns = tabular::Columns {
+                amount: amount_col,
+                currency: currency_col,
+                date: date_col,
+            };
+            tabular::run(ctxt, file, delimiter as u8, columns)?
+        }
+        Commands::Serve { listen } => serve::run(ctxt, listen)?,
+        Commands::Rpc => rpc::run(ctxt)?,
+        Commands::Status => quota::run(ctxt)?,
+        Commands::History { command } => history::run(ctxt, command)?,
+        Commands::Alert { command } => alert::run(ctxt, command)?,
+    }
+
+    info!("Exiting");
+    Ok(())
+}
+
+/// Process exit code for an error, by category of the first known cause
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if let Some(rate_error) = cause.downcast_ref::<RateError>() {
+            return rate_error.exit_code();
+        }
+        if cause.is::<rusqlite::Error>() || cause.is::<rusqlite_migration::Error>() {
+            return DB_EXIT_CODE;
+        }
+    }
+    1
+}
@@ -18,17 +18,55 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! Module for the convert subcommand
 
-use anyhow::{Context, Result};
-use log::{info, log_enabled, trace};
-use std::io::{self, BufRead};
-use ureq::Agent;
-
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{NaiveDate, Utc};
+use log::{info, log_enabled, trace, warn};
+use std::fmt;
+use std::io;
+#[cfg(feature = "cli")]
+use std::io::{BufRead, Read, Write};
+#[cfg(feature = "cli")]
+use std::ops::Range;
+use std::sync::Once;
+
+use crate::alert;
+#[cfg(feature = "cli")]
+use crate::db::history::HistoryConversion;
+use crate::fee::{self, Fee};
+#[cfg(feature = "cli")]
+use crate::markup::{self, Extracted, Markup};
+use crate::price_in_text::Engine;
+use crate::quota::within_quota;
 use crate::MainContext;
-use crate::{api::RateApi, config::CurrencyConverterApiCom};
-use crate::{currency::PriceTag, rate::Rate};
+use crate::{
+    api::{RateApi, RateError},
+    config::{CoinGecko, CurrencyConverterApiCom, Ecb, ExchangeRatesApiIo},
+};
+use crate::{
+    currency::{self, Currency, PriceTag, Quote},
+    rate::Rate,
+};
+
+#[cfg(feature = "async")]
+mod prefetch;
+#[cfg(feature = "cli")]
+mod sum;
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "async")]
+pub(crate) use prefetch::prefetch_current_rates;
+
+/// Warn at most once per run about the shared API key
+static SHARED_KEY_WARNING: Once = Once::new();
+
+/// Number of days before the requested one to look for a rate, when the exact day is missing
+/// (week-ends, bank holidays…)
+const HISTORICAL_RATE_MAX_GAP_DAYS: i64 = 4;
 
 /// Concat the args with spaces, if args are not `None`. Read text from the
 /// first line of stdin otherwise.
+#[cfg(feature = "cli")]
 fn concat_or_stdin_1_line(arg_text: Vec<String>) -> String {
     fn read_stdin() -> String {
         info!("Reading stdin…");
@@ -51,39 +89,299 @@ fn concat_or_stdin_1_line(arg_text: Vec<String>) -> String {
     }
 }
 
-/// Return content of stdin in a buffer
-fn stdin_buf() -> String {
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
-    stdin
-        .fill_buf()
-        .map(|bytes| String::from_utf8_lossy(bytes).into())
-        .unwrap_or(String::new())
+/// Return the whole content of stdin
+#[cfg(feature = "cli")]
+fn stdin_buf() -> Result<String> {
+    let mut bytes = Vec::new();
+    io::stdin().lock().read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into())
+}
+
+/// Where the text to convert comes from
+#[cfg(feature = "cli")]
+pub(crate) enum Input {
+    /// Text given as arguments, or the first line of stdin if there is none
+    Args(Vec<String>),
+    /// All of stdin, converted at once
+    Stdin,
+    /// Stdin, converted line by line as it comes
+    Stream,
+}
+
+/// Options of the convert subcommand
+#[cfg(feature = "cli")]
+pub(crate) struct Options {
+    /// Find at most that many price tags
+    pub findn: Option<usize>,
+    /// Convert with the rates in effect on that day
+    pub at: Option<NaiveDate>,
+    /// Fee profile, overrides the one of the configuration
+    pub fee: Option<String>,
+    /// Show the totals of the price tags
+    pub sum: bool,
+    /// Add the conversions to the history
+    pub history: bool,
+    /// Format of the input
+    pub markup: Markup,
+    /// Show where each price tag is in the input
+    pub locate: bool,
 }
 
 /// Parse arguments for convert subcommand and run it
-pub(crate) fn run(
-    ctxt: MainContext,
-    stdin: bool,
+#[cfg(feature = "cli")]
+pub(crate) fn run(mut ctxt: MainContext, input: Input, options: Options) -> Result<()> {
+    let Options {
+        findn,
+        at,
+        fee,
+        sum,
+        history,
+        markup,
+        locate,
+    } = options;
+    if fee.is_some() {
+        ctxt.cfg.fee = fee;
+    }
+    if let Some(name) = &ctxt.cfg.fee {
+        if !fee::profile_exists(&ctxt.cfg.fees, name) {
+            bail!("No fee profile named '{}' in the configuration", name);
+        }
+    }
+
+    let txt = match input {
+        Input::Args(plain_text) => concat_or_stdin_1_line(plain_text),
+        Input::Stdin => stdin_buf()?,
+        Input::Stream => {
+            let stdin = io::stdin();
+            return stream(&ctxt, stdin.lock(), &mut io::stdout(), findn, at, history);
+        }
+    };
+    let extracted = markup::extract(markup, &txt);
+    let txt = extracted.text();
+    trace!("plain text: {}", txt);
+
+    if locate {
+        let located = convert_located(&ctxt, &extracted, findn, at)?;
+        if history {
+            let history_conversions: Vec<HistoryConversion> = located
+                .iter()
+                .flat_map(|(_, conversions)| conversions)
+                .map(|c| c.into())
+                .collect();
+            ctxt.db.add_to_history(txt, &history_conversions)?;
+        }
+
+        let (ranges, all_conversions): (Vec<_>, Vec<_>) = located.into_iter().unzip();
+        let strings = strings_of_conversions(&all_conversions, at)
+            .into_iter()
+            .zip(ranges)
+            .map(|(group, range)| {
+                group
+                    .into_iter()
+                    .map(|s| format!("{}-{}: {}", range.start, range.end, s))
+                    .collect()
+            })
+            .collect();
+        println!("{}", conversions_to_string(strings)?);
+        return Ok(());
+    }
+
+    if sum {
+        let items = sum::items(&ctxt, sum::price_tags(txt, findn), at)?;
+        if history {
+            let history_conversions: Vec<HistoryConversion> = items
+                .iter()
+                .flat_map(|item| item.conversions())
+                .map(|c| c.into())
+                .collect();
+            ctxt.db.add_to_history(txt, &history_conversions)?;
+        }
+
+        if items.is_empty() {
+            println!("No currency found.");
+            return Ok(());
+        }
+        for warning in sum::warnings(&ctxt, &items, at, Utc::now()) {
+            eprintln!("Warning: {}", warning);
+        }
+        println!("{}", sum::table(&ctxt, &items));
+        return Ok(());
+    }
+
+    let all_conversions = convert(&ctxt, txt, findn, at)?;
+    if history {
+        let history_conversions: Vec<HistoryConversion> =
+            all_conversions.iter().flatten().map(|c| c.into()).collect();
+        ctxt.db.add_to_history(txt, &history_conversions)?;
+    }
+
+    println!(
+        "{}",
+        conversions_to_string(strings_of_conversions(&all_conversions, at))?
+    );
+
+    Ok(())
+}
+
+/// Convert input line by line as it is read, writing the conversions of each line prefixed with
+/// its number and flushing them right away. At most findn price tags are converted per line. Each
+/// line with conversions is a separate history entry. Errors on a line are reported on stderr and
+/// don’t stop the stream
+#[cfg(feature = "cli")]
+fn stream(
+    ctxt: &MainContext,
+    mut input: impl BufRead,
+    out: &mut dyn Write,
     findn: Option<usize>,
-    plain_text: Vec<String>,
+    at: Option<NaiveDate>,
+    history: bool,
 ) -> Result<()> {
-    let txt;
-    if stdin {
-        txt = stdin_buf();
-    } else {
-        txt = concat_or_stdin_1_line(plain_text);
+    let engine = Engine::new();
+    let mut bytes = Vec::new();
+    let mut line_number = 0;
+    loop {
+        bytes.clear();
+        if input.read_until(b'\n', &mut bytes)? == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+        let line = String::from_utf8_lossy(&bytes);
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+
+        let conversions = match convert_with(ctxt, &engine, line, findn, at) {
+            Ok(conversions) => conversions,
+            Err(err) => {
+                eprintln!("Error on line {}: {:#}", line_number, err);
+                continue;
+            }
+        };
+        let strings: Vec<String> = strings_of_conversions(&conversions, at)
+            .into_iter()
+            .flatten()
+            .collect();
+        if strings.is_empty() {
+            continue;
+        }
+        if history {
+            let history_conversions: Vec<HistoryConversion> =
+                conversions.iter().flatten().map(|c| c.into()).collect();
+            ctxt.db.add_to_history(line, &history_conversions)?;
+        }
+        for string in strings {
+            writeln!(out, "{}: {}", line_number, string)?;
+        }
+        out.flush()?;
     }
-    trace!("plain text: {}", &txt);
+}
 
-    ctxt.db.add_to_history(&txt)?;
+/// A price tag converted to another currency, with the rate and the fee used
+#[derive(Debug, Clone)]
+pub struct Conversion<'c> {
+    price_tag: PriceTag<'c>,
+    quote: Quote<'c>,
+    rate: Rate<'c>,
+    fee: Option<Fee>,
+}
 
-    println!("{}", convert_string(&ctxt, &txt, findn,)?);
+impl<'c> Conversion<'c> {
+    /// Convert the price tag with the rate and the fee, if any. None if the rate is not from the
+    /// currency of the price tag
+    pub fn new(price_tag: &PriceTag<'c>, rate: Rate<'c>, fee: Option<Fee>) -> Option<Self> {
+        let quote = price_tag.convert(&rate, fee.as_ref()).ok()?;
+        Some(Conversion {
+            price_tag: price_tag.clone(),
+            quote,
+            rate,
+            fee,
+        })
+    }
 
-    Ok(())
+    /// Price tag before conversion
+    pub fn price_tag(&self) -> &PriceTag<'c> {
+        &self.price_tag
+    }
+
+    /// Price tag after conversion, at the mid-market rate
+    pub fn converted(&self) -> &PriceTag<'c> {
+        self.quote.mid_market()
+    }
+
+    /// Price tag after conversion, once the fee is paid
+    pub fn charged(&self) -> &PriceTag<'c> {
+        self.quote.charged()
+    }
+
+    /// Fee applied to the conversion, if any
+    pub fn fee(&self) -> Option<&Fee> {
+        self.fee.as_ref()
+    }
+
+    /// Rate used for the conversion
+    pub fn rate(&self) -> &Rate<'c> {
+        &self.rate
+    }
+}
+
+impl<'c> fmt::Display for Conversion<'c> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ➜ {}", self.price_tag, self.converted())?;
+        if self.rate.is_derived() {
+            write!(f, " (derived)")?;
+        }
+        if let Some(fee) = &self.fee {
+            write!(
+                f,
+                " | {}: {} (cost {})",
+                fee.name(),
+                self.quote.charged(),
+                self.quote.cost()
+            )?;
+        }
+        Ok(())
+    }
 }
 
-fn conversions_to_string(all_conversions: Vec<Vec<String>>) -> Result<String> {
+#[cfg(feature = "cli")]
+impl<'c> From<&Conversion<'c>> for HistoryConversion {
+    fn from(c: &Conversion<'c>) -> Self {
+        HistoryConversion {
+            // Set when the history entry is added
+            history_rowid: 0,
+            src: c.price_tag().currency().get_main_iso().to_string(),
+            amount: c.price_tag().amount(),
+            dst: c.converted().currency().get_main_iso().to_string(),
+            converted_amount: c.converted().amount(),
+            rate: c.rate().rate(),
+            provider: c.rate().provider().to_string(),
+            rate_date: *c.rate().date(),
+        }
+    }
+}
+
+/// Format conversions, with the date of the rate when converting at a given date
+pub(crate) fn strings_of_conversions(
+    all_conversions: &[Vec<Conversion>],
+    at: Option<NaiveDate>,
+) -> Vec<Vec<String>> {
+    all_conversions
+        .iter()
+        .map(|group| {
+            group
+                .iter()
+                .map(|conversion| match at {
+                    Some(_) => format!(
+                        "{} on {}",
+                        conversion,
+                        conversion.rate().date().format("%F")
+                    ),
+                    None => conversion.to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub(crate) fn conversions_to_string(all_conversions: Vec<Vec<String>>) -> Result<String> {
     let mut string = String::new();
 
     if all_conversions.len() == 0 {
@@ -105,17 +403,37 @@ fn conversions_to_string(all_conversions: Vec<Vec<String>>) -> Result<String> {
     }
 }
 
-pub fn convert_string(ctxt: &MainContext, txt: &str, limit: Option<usize>) -> Result<String> {
-    conversions_to_string(convert(ctxt, txt, limit)?)
+pub fn convert_string(
+    ctxt: &MainContext,
+    txt: &str,
+    limit: Option<usize>,
+    at: Option<NaiveDate>,
+) -> Result<String> {
+    conversions_to_string(strings_of_conversions(&convert(ctxt, txt, limit, at)?, at))
 }
 
-pub fn convert(ctxt: &MainContext, txt: &str, limit: Option<usize>) -> Result<Vec<Vec<String>>> {
-    let engine = crate::price_in_text::Engine::new().unwrap();
-    let price_tags;
-    if let Some(l) = limit {
-        price_tags = engine.top_price_tags(l, &txt)
-    } else {
-        price_tags = engine.all_price_tags(&txt);
+pub fn convert<'c>(
+    ctxt: &MainContext<'c>,
+    txt: &str,
+    limit: Option<usize>,
+    at: Option<NaiveDate>,
+) -> Result<Vec<Vec<Conversion<'c>>>> {
+    let engine = crate::price_in_text::Engine::new();
+    convert_with(ctxt, &engine, txt, limit, at)
+}
+
+/// Same as convert, with an engine built beforehand, to convert several texts in a row
+pub(crate) fn convert_with<'c>(
+    ctxt: &MainContext<'c>,
+    engine: &Engine<'c>,
+    txt: &str,
+    limit: Option<usize>,
+    at: Option<NaiveDate>,
+) -> Result<Vec<Vec<Conversion<'c>>>> {
+    let price_tags = price_tags(engine, txt, limit);
+    if at.is_none() {
+        let srcs = price_tags.iter().map(PriceTag::currency);
+        prefetch_current_rates(ctxt, &pairs(srcs, &ctxt.destination_currencies));
     }
 
     let mut all_conversions = Vec::new();
@@ -124,58 +442,76 @@ pub fn convert(ctxt: &MainContext, txt: &str, limit: Option<usize>) -> Result<Ve
         return Ok(all_conversions);
     } else {
         for price_tag in price_tags {
-            all_conversions.push(get_conversions(&ctxt, &price_tag)?);
+            all_conversions.push(get_conversions(&ctxt, &price_tag, at)?);
         }
     }
 
     Ok(all_conversions)
 }
 
-fn get_conversions(ctxt: &MainContext, price_tag: &PriceTag) -> Result<Vec<String>> {
-    let src_currency = price_tag.currency();
-    trace!("src_currency: {}", &src_currency);
+/// Conversions of each price tag of a document, with the range of the document the price tag
+/// comes from. Each amount is converted once, in the order of the document, at most limit of them
+/// if set
+#[cfg(feature = "cli")]
+fn convert_located<'c>(
+    ctxt: &MainContext<'c>,
+    extracted: &Extracted,
+    limit: Option<usize>,
+    at: Option<NaiveDate>,
+) -> Result<Vec<(Range<usize>, Vec<Conversion<'c>>)>> {
+    let engine = Engine::new();
+    let mut located = engine.located_price_tags(extracted.text());
+    if let Some(limit) = limit {
+        located.truncate(limit);
+    }
+    if at.is_none() {
+        let srcs = located.iter().map(|(_, price_tag)| price_tag.currency());
+        prefetch_current_rates(ctxt, &pairs(srcs, &ctxt.destination_currencies));
+    }
+    located
+        .into_iter()
+        .map(|(location, price_tag)| {
+            let conversions = get_conversions(ctxt, &price_tag, at)?;
+            Ok((extracted.original(location.span()), conversions))
+        })
+        .collect()
+}
 
-    let now = chrono::offset::Utc::now();
+/// Price tags found in a text, at most limit of them if set
+fn price_tags<'c>(engine: &Engine<'c>, txt: &str, limit: Option<usize>) -> Vec<PriceTag<'c>> {
+    if let Some(l) = limit {
+        engine.top_price_tags(l, &txt)
+    } else {
+        engine.all_price_tags(&txt)
+    }
+}
 
-    // Get rate
-    let endpoint = CurrencyConverterApiCom::new(&ctxt.cfg);
-    trace!("Got API Endpoint");
-    let rate_from_db = |dst_currency| -> Option<Rate> {
-        // TODO Create transaction to keep outdated rates if the update to a new rate is unsucessful?
-        trace!("Get rate from db");
-        let uptodate_rates = ctxt
-            .db
-            .get_uptodate_rates(src_currency, dst_currency, &endpoint.provider_id(), now)
-            .context("Failed to retrieve rates from the database")
-            .ok()?;
+/// Pairs from each source currency to each destination currency
+pub(crate) fn pairs<'c>(
+    srcs: impl Iterator<Item = &'c Currency>,
+    dsts: &[&'c Currency],
+) -> Vec<(&'c Currency, &'c Currency)> {
+    srcs.flat_map(|src| dsts.iter().map(move |dst| (src, *dst)))
+        .collect()
+}
 
-        let rate = uptodate_rates.last();
-        trace!("rate_from_db: {:?}", rate);
-        rate.map(|r| r.clone())
-    };
+/// Without the async feature, rates are retrieved when each conversion needs them
+#[cfg(not(feature = "async"))]
+pub(crate) fn prefetch_current_rates(_ctxt: &MainContext, _pairs: &[(&Currency, &Currency)]) {}
 
-    let add_to_db = |rate: Rate| {
-        trace!("Set rate to db");
-        ctxt.db.set_rate(&rate).unwrap();
-    };
+fn get_conversions<'c>(
+    ctxt: &MainContext<'c>,
+    price_tag: &PriceTag<'c>,
+    at: Option<NaiveDate>,
+) -> Result<Vec<Conversion<'c>>> {
+    let src_currency = price_tag.currency();
+    trace!("src_currency: {}", &src_currency);
 
-    let rate_from_api = |dst_currency| -> Option<Rate> {
-        info!("Retrieve rate online");
-        let agent = Agent::new();
-        endpoint.rate(&agent, &src_currency, dst_currency)
+    let rates = match at {
+        Some(day) => historical_rates(ctxt, src_currency, &ctxt.destination_currencies, day)?,
+        None => current_rates(ctxt, src_currency, &ctxt.destination_currencies)?,
     };
 
-    let rates = ctxt.destination_currencies.iter().map(|dst| {
-        rate_from_db(&dst).or_else(|| {
-            let rate = rate_from_api(&dst);
-            if let Some(rate) = &rate {
-                info!("Set rate to db");
-                add_to_db(rate.clone());
-            }
-            rate
-        })
-    });
-
     let mut conversions = Vec::with_capacity(rates.len());
 
     for rate in rates {
@@ -193,42 +529,380 @@ fn get_conversions(ctxt: &MainContext, price_tag: &PriceTag) -> Result<Vec<Strin
             if price_tag.currency() == rate.dst() {
                 continue;
             }
-            conversions.push(format!(
-                "{} ➜ {}",
-                &price_tag,
-                &price_tag.convert(&rate).unwrap()
-            ));
+            let fee = fee_for(ctxt, &rate)?;
+            conversions.extend(Conversion::new(price_tag, rate, fee));
+        }
+    }
+
+    Ok(conversions)
+}
+
+/// Fee of the selected profile for a conversion with the rate, None if no profile is selected or
+/// none applies to the rate. The fixed part is converted to the source currency of the rate
+fn fee_for<'c>(ctxt: &MainContext<'c>, rate: &Rate<'c>) -> Result<Option<Fee>> {
+    let name = match &ctxt.cfg.fee {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let profile = match fee::select_profile(&ctxt.cfg.fees, name, rate) {
+        Some(profile) => profile,
+        None => {
+            info!("No '{}' fee for {}", name, rate);
+            return Ok(None);
+        }
+    };
+
+    let mut fixed = profile.fixed;
+    if fixed != 0. {
+        let currency = fee::fixed_currency(profile, rate.src()).ok_or_else(|| {
+            anyhow!(
+                "Unknown currency '{}' in fee profile '{}'",
+                profile.fixed_currency.as_deref().unwrap_or_default(),
+                name
+            )
+        })?;
+        if currency == rate.dst() {
+            fixed /= rate.rate();
+        } else if currency != rate.src() {
+            match current_rates(ctxt, currency, &[rate.src()])?
+                .pop()
+                .flatten()
+            {
+                Some(fixed_rate) => fixed *= fixed_rate.rate(),
+                None => bail!(
+                    "No rate to convert the fixed part of the '{}' fee from {}",
+                    name,
+                    currency
+                ),
+            }
+        }
+    }
+    Ok(Some(Fee::new(name, profile.percent, fixed)))
+}
+
+/// Up-to-date rates from src to each of dsts, in the same order. Rates are looked for in the
+/// database first, then derived from stored rates and finally retrieved online. Offline, the
+/// latest rates stored are used, even if they are outdated
+pub(crate) fn current_rates<'c>(
+    ctxt: &MainContext,
+    src_currency: &'c Currency,
+    dsts: &[&'c Currency],
+) -> Result<Vec<Option<Rate<'c>>>> {
+    if ctxt.offline {
+        return dsts
+            .iter()
+            .map(|dst| latest_stored_rate(ctxt, src_currency, dst))
+            .collect();
+    }
+
+    let providers: [&dyn RateApi; 2] = [
+        CurrencyConverterApiCom::new(&ctxt.cfg),
+        CoinGecko::new(&ctxt.cfg),
+    ];
+
+    let mut rates = vec![None; dsts.len()];
+    for provider in &providers {
+        let (indices, provider_dsts): (Vec<usize>, Vec<&Currency>) = dsts
+            .iter()
+            .enumerate()
+            .filter(|(_, dst)| provider.supports(src_currency, dst))
+            .map(|(i, dst)| (i, *dst))
+            .unzip();
+        if provider_dsts.is_empty() {
+            continue;
+        }
+        let provider_rates = provider_current_rates(ctxt, *provider, src_currency, &provider_dsts)?;
+        for (i, rate) in indices.into_iter().zip(provider_rates) {
+            rates[i] = rate;
+        }
+    }
+
+    Ok(rates)
+}
+
+/// Most recent rate stored from src to dst, whatever the provider and even if it is outdated
+fn latest_stored_rate<'c>(
+    ctxt: &MainContext,
+    src_currency: &'c Currency,
+    dst: &'c Currency,
+) -> Result<Option<Rate<'c>>> {
+    if src_currency == dst {
+        return Ok(Some(Rate::parity(src_currency)));
+    }
+    let rate = ctxt
+        .store()
+        .get_latest_rate(src_currency, dst)
+        .map_err(RateError::Db)?;
+    Ok(rate.map(|rate| {
+        if rate.src() == src_currency {
+            rate
+        } else {
+            rate.inverse()
+        }
+    }))
+}
+
+/// Up-to-date rates from src to each of dsts from a single provider, in the same order
+fn provider_current_rates<'c>(
+    ctxt: &MainContext,
+    endpoint: &dyn RateApi,
+    src_currency: &'c Currency,
+    dsts: &[&'c Currency],
+) -> Result<Vec<Option<Rate<'c>>>> {
+    let now = chrono::offset::Utc::now();
+    let pivots = pivots(ctxt);
+    let rate_from_db = |dst_currency| -> Option<Rate> {
+        // TODO Create transaction to keep outdated rates if the update to a new rate is unsucessful?
+        trace!("Get rate from db");
+        let uptodate_rates = ctxt
+            .store()
+            .get_uptodate_rates(src_currency, dst_currency, &endpoint.provider_id(), now)
+            .context("Failed to retrieve rates from the database")
+            .ok()?;
+
+        let rate = uptodate_rates.last().cloned().or_else(|| {
+            trace!("Get derived rate from db");
+            ctxt.store()
+                .get_uptodate_derived_rate(
+                    src_currency,
+                    dst_currency,
+                    &endpoint.provider_id(),
+                    &pivots,
+                    now,
+                )
+                .context("Failed to derive rates from the database")
+                .ok()?
+        });
+        trace!("rate_from_db: {:?}", rate);
+        rate
+    };
+
+    let mut rates: Vec<Option<Rate>> = dsts.iter().map(|dst| rate_from_db(dst)).collect();
+
+    // Currencies without up-to-date rates are all retrieved with a single request
+    let missing_currencies: Vec<&Currency> = missing(dsts, &rates);
+    if !missing_currencies.is_empty() {
+        info!("Retrieve rates online");
+        warn_shared_key(endpoint);
+        let rates_from_api = within_quota(ctxt, endpoint, |client| {
+            endpoint.rates(client, src_currency, &missing_currencies)
+        })?;
+        store_rates(ctxt, &rates_from_api)?;
+
+        for (dst, rate) in dsts.iter().zip(rates.iter_mut()) {
+            if rate.is_none() {
+                *rate = rates_from_api.iter().find(|r| r.dst() == *dst).cloned();
+            }
         }
     }
 
-    for dst in ctxt.destination_currencies.clone() {
+    for dst in dsts {
+        ctxt.store()
+            .remove_outdated_rates(src_currency, dst, &endpoint.provider_id(), now)
+            .map_err(RateError::Db)?;
+    }
+
+    Ok(rates)
+}
+
+/// Pivot currencies of the configuration, to derive rates from stored ones
+fn pivots(ctxt: &MainContext) -> Vec<&'static Currency> {
+    ctxt.cfg
+        .pivots
+        .iter()
+        .filter_map(|iso| currency::existing_from_iso(iso))
+        .collect()
+}
+
+/// Warn once, when the provider uses the API key shared by all users
+fn warn_shared_key(endpoint: &dyn RateApi) {
+    if endpoint.uses_shared_key() {
+        SHARED_KEY_WARNING.call_once(|| {
+            eprintln!(
+                "Warning: using the currencyconverterapi.com key shared by all users, its \
+                 quota may be exhausted. Please get your own key at \
+                 https://free.currencyconverterapi.com and set it in \
+                 SESTERS_CURRENCYCONVERTERAPI_KEY, a key file or the configuration file"
+            )
+        });
+    }
+}
+
+/// Store rates retrieved online, add them to the rate series and report the alerts they trigger
+fn store_rates(ctxt: &MainContext, rates: &[Rate]) -> Result<(), RateError> {
+    info!("Set rates to db");
+    let triggered = match &ctxt.store {
+        // Current rates, series and alerts all in the database, in a single transaction
+        None => ctxt.db.set_rates(rates),
+        Some(store) => store
+            .put_rates(rates)
+            .and_then(|()| ctxt.db.record_rates(rates)),
+    }
+    .map_err(RateError::Db)?;
+    // Conversions are on stdout, alerts go to stderr
+    if let Err(err) = alert::emit(
+        ctxt.cfg.alert_hook.as_deref(),
+        &triggered,
+        &mut io::stderr(),
+    ) {
+        warn!("Failed to report alerts: {:#}", err);
+    }
+    Ok(())
+}
+
+/// Rates from src to each of dsts in effect on the given day, in the same order. Rates are looked
+/// for in the rate series of the database first, and then retrieved online from providers with
+/// historical rates, unless offline
+pub(crate) fn historical_rates<'c>(
+    ctxt: &MainContext,
+    src_currency: &'c Currency,
+    dsts: &[&'c Currency],
+    day: NaiveDate,
+) -> Result<Vec<Option<Rate<'c>>>> {
+    if day > Utc::today().naive_utc() {
+        bail!("Can’t get rates in the future ({})", day);
+    }
+
+    let providers: [&dyn RateApi; 2] = [Ecb::new(&ctxt.cfg), ExchangeRatesApiIo::new(&ctxt.cfg)];
+    let rate_from_db = |dst: &'c Currency, max_gap_days| -> Result<Option<Rate<'c>>> {
+        for provider in &providers {
+            let rate = ctxt
+                .db
+                .get_rate_at(
+                    src_currency,
+                    dst,
+                    &provider.provider_id(),
+                    day,
+                    max_gap_days,
+                )
+                .map_err(RateError::Db)?;
+            if rate.is_some() {
+                return Ok(rate);
+            }
+        }
+        Ok(None)
+    };
+
+    let mut rates = dsts
+        .iter()
+        .map(|dst| rate_from_db(dst, HISTORICAL_RATE_MAX_GAP_DAYS))
+        .collect::<Result<Vec<_>>>()?;
+    if ctxt.offline {
+        return Ok(rates);
+    }
+
+    let mut provider_error = None;
+    for provider in &providers {
+        let missing_currencies: Vec<&Currency> = missing(dsts, &rates)
+            .into_iter()
+            .filter(|dst| provider.supports(src_currency, dst))
+            .collect();
+        if missing_currencies.is_empty() {
+            continue;
+        }
+        info!("Retrieve historical rates from {}", provider.provider_id());
+        let rates_from_api = match within_quota(ctxt, *provider, |client| {
+            provider.historical_rates(client, src_currency, &missing_currencies, day)
+        }) {
+            Ok(rates_from_api) if rates_from_api.is_empty() => continue,
+            Ok(rates_from_api) => rates_from_api,
+            Err(err) => {
+                // Another provider may have the rates
+                warn!("{}", err);
+                provider_error = Some(err);
+                continue;
+            }
+        };
         ctxt.db
-            .remove_outdated_rates(src_currency, dst, &endpoint.provider_id(), now)?;
+            .add_to_series(&rates_from_api)
+            .map_err(RateError::Db)?;
+
+        for (dst, rate) in dsts.iter().zip(rates.iter_mut()) {
+            if rate.is_none() {
+                *rate = ctxt
+                    .db
+                    .get_rate_at(
+                        src_currency,
+                        dst,
+                        &provider.provider_id(),
+                        day,
+                        HISTORICAL_RATE_MAX_GAP_DAYS,
+                    )
+                    .map_err(RateError::Db)?;
+            }
+        }
     }
 
-    Ok(conversions)
+    // Only report provider errors when nothing could be found
+    match provider_error {
+        Some(err) if rates.iter().all(|rate| rate.is_none()) => Err(err.into()),
+        _ => Ok(rates),
+    }
 }
 
-#[test]
-fn conversions_string_test() {
-    let multiple_groups = vec![
-        vec![
-            String::from("GBP 15.00 ➜ EUR 17.64"),
-            String::from("GBP 15.00 ➜ USD 20.42"),
-        ],
-        vec![String::from("EUR 12.00 ➜ USD 13.89")],
-        vec![String::from("USD 15.00 ➜ EUR 12.96")],
-    ];
+/// Currencies of dsts without a rate
+fn missing<'c>(dsts: &[&'c Currency], rates: &[Option<Rate>]) -> Vec<&'c Currency> {
+    dsts.iter()
+        .zip(rates.iter())
+        .filter(|(_, rate)| rate.is_none())
+        .map(|(dst, _)| *dst)
+        .collect()
+}
+
+/// Parse arguments for rate subcommand and run it
+#[cfg(feature = "cli")]
+pub(crate) fn run_rate(
+    ctxt: MainContext,
+    src: String,
+    dsts: Vec<String>,
+    at: Option<NaiveDate>,
+    stats: Option<u32>,
+) -> Result<()> {
+    let src_currency = currency_from_iso(&src)?;
+    let dst_currencies: Vec<&Currency> = if dsts.is_empty() {
+        ctxt.destination_currencies.clone()
+    } else {
+        dsts.iter()
+            .map(|iso| currency_from_iso(iso))
+            .collect::<Result<_>>()?
+    };
 
-    assert_eq!(
-        conversions_to_string(multiple_groups).unwrap(),
-        "\
-GBP 15.00 ➜ EUR 17.64
-GBP 15.00 ➜ USD 20.42
+    if let Some(days) = stats {
+        return crate::stats::run(&ctxt, src_currency, &dst_currencies, days);
+    }
 
-EUR 12.00 ➜ USD 13.89
+    println!(
+        "{}",
+        rates_to_string(&ctxt, src_currency, &dst_currencies, at)?
+    );
+    Ok(())
+}
+
+/// Currency with that iso symbol, an error if there is none
+#[cfg(feature = "cli")]
+pub(crate) fn currency_from_iso(iso: &str) -> Result<&'static Currency> {
+    currency::existing_from_iso(iso).ok_or_else(|| anyhow!("Invalid currency iso symbol '{}'", iso))
+}
 
-USD 15.00 ➜ EUR 12.96\
-    "
-    )
+/// Rates from src to each of dsts, one per line, now or on the given day
+#[cfg(feature = "cli")]
+pub(crate) fn rates_to_string(
+    ctxt: &MainContext,
+    src_currency: &Currency,
+    dst_currencies: &[&Currency],
+    at: Option<NaiveDate>,
+) -> Result<String> {
+    let rates = match at {
+        Some(day) => historical_rates(ctxt, src_currency, dst_currencies, day)?,
+        None => current_rates(ctxt, src_currency, dst_currencies)?,
+    };
+    let lines: Vec<String> = dst_currencies
+        .iter()
+        .zip(rates)
+        .map(|(dst, rate)| match rate {
+            Some(rate) => rate.to_string(),
+            None => format!("No rate found for {} ➜ {}", src_currency, dst),
+        })
+        .collect();
+    Ok(lines.join("\n"))
 }
@@ -0,0 +1,205 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Current rates of several source currencies retrieved concurrently, with the `async` feature
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use log::{info, trace, warn};
+use tokio::runtime::Builder;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use super::{pivots, store_rates, warn_shared_key};
+use crate::api::asynchronous::{AsyncRateApi, Blocking, Fetcher};
+use crate::api::{RateApi, RateError};
+use crate::config::{CoinGecko, CurrencyConverterApiCom};
+use crate::currency::{self, Currency};
+use crate::http::Client;
+use crate::quota::{available_requests, record_request};
+use crate::rate::Rate;
+use crate::MainContext;
+
+/// Provider, source currency and destination currencies of a request
+type Request = (usize, &'static Currency, Vec<&'static Currency>);
+
+/// Rate source sending its requests with its own client, which counts the attempts of a single
+/// request against the budget of the provider
+struct Counted {
+    api: Arc<dyn AsyncRateApi>,
+    client: Client,
+    attempts: Arc<AtomicU32>,
+}
+
+impl Counted {
+    fn new(api: &Arc<dyn AsyncRateApi>, client: &Client) -> Self {
+        let attempts = Arc::new(AtomicU32::new(0));
+        Counted {
+            api: Arc::clone(api),
+            client: client.counting(Arc::clone(&attempts)),
+            attempts,
+        }
+    }
+}
+
+impl AsyncRateApi for Counted {
+    fn provider_id(&self) -> String {
+        self.api.provider_id()
+    }
+
+    fn supports(&self, src: &Currency, dst: &Currency) -> bool {
+        self.api.supports(src, dst)
+    }
+
+    fn rates(
+        &self,
+        _client: &Client,
+        src: &'static Currency,
+        dsts: Vec<&'static Currency>,
+    ) -> BoxFuture<'static, Result<Vec<Rate<'static>>, RateError>> {
+        self.api.rates(&self.client, src, dsts)
+    }
+}
+
+/// Retrieve concurrently the current rates of the pairs without up-to-date rate in the store, so
+/// that conversions find them there afterwards. Failures are only logged: conversions retrieve
+/// the rates still missing one after the other and report the errors
+pub(crate) fn prefetch_current_rates(ctxt: &MainContext, pairs: &[(&Currency, &Currency)]) {
+    if ctxt.offline {
+        return;
+    }
+    if let Err(err) = prefetch(ctxt, pairs) {
+        warn!("Failed to retrieve rates concurrently: {:#}", err);
+    }
+}
+
+fn prefetch(ctxt: &MainContext, pairs: &[(&Currency, &Currency)]) -> Result<()> {
+    let currconv = CurrencyConverterApiCom::new(&ctxt.cfg);
+    let coingecko = CoinGecko::new(&ctxt.cfg);
+    let providers: [(&dyn RateApi, Arc<dyn AsyncRateApi>); 2] = [
+        (currconv, Arc::new(Blocking::new(currconv.clone()))),
+        (coingecko, Arc::new(Blocking::new(coingecko.clone()))),
+    ];
+    let now = Utc::now();
+
+    let mut requests = missing_requests(ctxt, &providers, pairs, now)?;
+    // The budget of each provider is reserved for the whole batch, requests beyond it are left to
+    // the conversions, which report it
+    let mut budgets = providers
+        .iter()
+        .map(|(provider, _)| available_requests(ctxt, *provider, now))
+        .collect::<Result<Vec<_>, _>>()?;
+    requests.retain(|(p, _, dsts)| {
+        let needed = providers[*p].0.requests_for(dsts.len()) as u32;
+        match &mut budgets[*p] {
+            None => true,
+            Some(budget) if *budget >= needed => {
+                *budget -= needed;
+                true
+            }
+            Some(_) => false,
+        }
+    });
+    if requests.len() < 2 {
+        trace!("Nothing to retrieve concurrently");
+        return Ok(());
+    }
+    for (p, _, _) in &requests {
+        warn_shared_key(providers[*p].0);
+    }
+
+    info!("Retrieve rates online, {} requests at once", requests.len());
+    let fetcher = Fetcher::new(ctxt.client.clone(), ctxt.cfg.http.concurrent_requests);
+    let counted: Vec<Arc<Counted>> = requests
+        .iter()
+        .map(|(p, _, _)| Arc::new(Counted::new(&providers[*p].1, &ctxt.client)))
+        .collect();
+    let to_fetch: Vec<_> = requests
+        .iter()
+        .zip(&counted)
+        .map(|((_, src, dsts), api)| {
+            let api: Arc<dyn AsyncRateApi> = api.clone();
+            (api, *src, dsts.clone())
+        })
+        .collect();
+    let results = Builder::new_current_thread()
+        .build()?
+        .block_on(fetcher.all_rates(&to_fetch));
+
+    // Requests have no pair in common, each result comes from its own request
+    for (((p, _, _), api), result) in requests.iter().zip(&counted).zip(results) {
+        let id = providers[*p].0.provider_id();
+        record_request(
+            ctxt,
+            &id,
+            now,
+            api.attempts.load(Ordering::Relaxed),
+            result.as_ref().err().map(|err| err.as_ref()),
+        )?;
+        match result {
+            Ok(rates) => store_rates(ctxt, &rates.into_iter().flatten().collect::<Vec<_>>())?,
+            Err(err) => warn!("{}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Requests for the pairs without up-to-date or derived rate in the store, one per provider and
+/// source currency
+fn missing_requests(
+    ctxt: &MainContext,
+    providers: &[(&dyn RateApi, Arc<dyn AsyncRateApi>)],
+    pairs: &[(&Currency, &Currency)],
+    now: DateTime<Utc>,
+) -> Result<Vec<Request>> {
+    let pivots = pivots(ctxt);
+    let mut requests: Vec<Request> = Vec::new();
+    for (src, dst) in pairs {
+        let (src, dst) = match (
+            currency::existing_from_iso(src.get_main_iso()),
+            currency::existing_from_iso(dst.get_main_iso()),
+        ) {
+            (Some(src), Some(dst)) if src != dst => (src, dst),
+            _ => continue,
+        };
+        let p = match providers.iter().position(|(p, _)| p.supports(src, dst)) {
+            Some(p) => p,
+            None => continue,
+        };
+        let id = providers[p].0.provider_id();
+        let store = ctxt.store();
+        if !store.get_uptodate_rates(src, dst, &id, now)?.is_empty()
+            || store
+                .get_uptodate_derived_rate(src, dst, &id, &pivots, now)?
+                .is_some()
+        {
+            continue;
+        }
+        match requests
+            .iter_mut()
+            .find(|(rp, rsrc, _)| *rp == p && *rsrc == src)
+        {
+            Some((_, _, dsts)) if dsts.contains(&dst) => {}
+            Some((_, _, dsts)) => dsts.push(dst),
+            None => requests.push((p, src, vec![dst])),
+        }
+    }
+    Ok(requests)
+}
@@ -0,0 +1,205 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Totals of the price tags found in a text, for convert --sum
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use term_table::{row::Row, Table};
+
+use std::collections::HashMap;
+
+use super::{current_rates, fee_for, historical_rates, Conversion};
+use crate::currency::PriceTag;
+use crate::rate::Rate;
+use crate::MainContext;
+
+/// Rates older than that, compared to the time of the conversion, are reported as stale
+const STALE_RATE_HOURS: i64 = 24;
+
+/// A price tag converted to each destination currency, None where no rate was found
+pub struct Item<'c> {
+    price_tag: PriceTag<'c>,
+    conversions: Vec<Option<Conversion<'c>>>,
+}
+
+impl<'c> Item<'c> {
+    /// Conversions to other currencies than the one of the price tag
+    pub fn conversions(&self) -> impl Iterator<Item = &Conversion<'c>> {
+        self.conversions
+            .iter()
+            .flatten()
+            .filter(move |c| c.converted().currency() != self.price_tag.currency())
+    }
+}
+
+/// Price tags to add up in a text, in the order of the text, at most limit of them if set. Each
+/// amount is counted once
+pub fn price_tags<'c>(txt: &str, limit: Option<usize>) -> Vec<PriceTag<'c>> {
+    let engine = crate::price_in_text::Engine::new();
+    let mut price_tags = engine.distinct_price_tags(txt);
+    if let Some(limit) = limit {
+        price_tags.truncate(limit);
+    }
+    price_tags
+}
+
+/// Convert every price tag to each destination currency. Rates are retrieved once per source
+/// currency, so that price tags in the same currency are converted with the same rate
+pub fn items<'c>(
+    ctxt: &MainContext<'c>,
+    price_tags: Vec<PriceTag<'c>>,
+    at: Option<NaiveDate>,
+) -> Result<Vec<Item<'c>>> {
+    let mut snapshot: HashMap<&str, Vec<Option<Rate<'c>>>> = HashMap::new();
+    let mut items = Vec::with_capacity(price_tags.len());
+    for price_tag in price_tags {
+        let src = price_tag.currency();
+        if !snapshot.contains_key(src.get_main_iso()) {
+            let rates = match at {
+                Some(day) => historical_rates(ctxt, src, &ctxt.destination_currencies, day)?,
+                None => current_rates(ctxt, src, &ctxt.destination_currencies)?,
+            };
+            snapshot.insert(src.get_main_iso(), rates);
+        }
+
+        let mut conversions = Vec::with_capacity(ctxt.destination_currencies.len());
+        for (dst, rate) in ctxt
+            .destination_currencies
+            .iter()
+            .zip(&snapshot[src.get_main_iso()])
+        {
+            // Amounts already in a destination currency count as they are, without fees
+            let rate = if src == *dst {
+                Some(Rate::parity(src))
+            } else {
+                rate.clone()
+            };
+            let conversion = match rate {
+                Some(rate) => {
+                    let fee = if rate.src() == rate.dst() {
+                        None
+                    } else {
+                        fee_for(ctxt, &rate)?
+                    };
+                    Conversion::new(&price_tag, rate, fee)
+                }
+                None => None,
+            };
+            conversions.push(conversion);
+        }
+        items.push(Item {
+            price_tag,
+            conversions,
+        });
+    }
+
+    Ok(items)
+}
+
+/// Warnings about items left out of totals for lack of a rate, or converted with a stale rate.
+/// Rates are compared to now, or to the end of the day of the conversion
+pub fn warnings(
+    ctxt: &MainContext,
+    items: &[Item],
+    at: Option<NaiveDate>,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let reference = match at {
+        Some(day) => DateTime::<Utc>::from_utc(day.and_hms(23, 59, 59), Utc),
+        None => now,
+    };
+    let mut warnings = Vec::new();
+    for item in items {
+        for (dst, conversion) in ctxt.destination_currencies.iter().zip(&item.conversions) {
+            match conversion {
+                None => warnings.push(format!(
+                    "no rate from {} to {}, {} is left out of the {} total",
+                    item.price_tag.currency(),
+                    dst,
+                    item.price_tag,
+                    dst
+                )),
+                Some(conversion)
+                    if conversion.rate().src() != conversion.rate().dst()
+                        && reference - *conversion.rate().date()
+                            > Duration::hours(STALE_RATE_HOURS) =>
+                {
+                    warnings.push(format!(
+                        "{} converted with a stale rate, from {}",
+                        conversion,
+                        conversion.rate().date().format("%F %R")
+                    ))
+                }
+                Some(_) => (),
+            }
+        }
+    }
+    warnings
+}
+
+/// Table of the items converted to each destination currency, followed by the totals
+pub fn table(ctxt: &MainContext, items: &[Item]) -> String {
+    let dsts = &ctxt.destination_currencies;
+    let mut table = Table::new();
+    let mut header = vec![String::from("Price tag")];
+    header.extend(dsts.iter().map(|dst| dst.get_main_iso().to_string()));
+    table.add_row(Row::new(header));
+
+    let mut totals = vec![0.; dsts.len()];
+    let mut charged_totals = vec![0.; dsts.len()];
+    let mut fees = Vec::new();
+    for item in items {
+        let mut row = vec![item.price_tag.to_string()];
+        for (i, conversion) in item.conversions.iter().enumerate() {
+            match conversion {
+                Some(conversion) => {
+                    row.push(conversion.converted().to_string());
+                    totals[i] += conversion.converted().amount();
+                    charged_totals[i] += conversion.charged().amount();
+                    if let Some(fee) = conversion.fee() {
+                        if !fees.contains(&fee.name()) {
+                            fees.push(fee.name());
+                        }
+                    }
+                }
+                None => row.push(String::from("-")),
+            }
+        }
+        table.add_row(Row::new(row));
+    }
+
+    let total_row = |title: String, totals: &[f64]| {
+        let mut row = vec![title];
+        row.extend(
+            dsts.iter()
+                .zip(totals)
+                .map(|(dst, total)| PriceTag::new(dst, *total).to_string()),
+        );
+        Row::new(row)
+    };
+    table.add_row(total_row(String::from("Total"), &totals));
+    if !fees.is_empty() {
+        table.add_row(total_row(
+            format!("Total with {} fees", fees.join(", ")),
+            &charged_totals,
+        ));
+    }
+
+    table.render()
+}
@@ -0,0 +1,439 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests for the convert subcommand, with providers replaced by a mock server
+
+use chrono::NaiveDate;
+
+use super::*;
+use crate::api::mock::{context, fixtures, MockServer, Route};
+use crate::config::FeeProfile;
+use crate::currency::{BTC, ETH, EUR, USD};
+
+fn conversion_strings(conversions: &[Vec<Conversion>]) -> Vec<Vec<String>> {
+    strings_of_conversions(conversions, None)
+}
+
+#[test]
+fn conversions_string_test() {
+    let multiple_groups = vec![
+        vec![
+            String::from("GBP 15.00 ➜ EUR 17.64"),
+            String::from("GBP 15.00 ➜ USD 20.42"),
+        ],
+        vec![String::from("EUR 12.00 ➜ USD 13.89")],
+        vec![String::from("USD 15.00 ➜ EUR 12.96")],
+    ];
+
+    assert_eq!(
+        conversions_to_string(multiple_groups).unwrap(),
+        "\
+GBP 15.00 ➜ EUR 17.64
+GBP 15.00 ➜ USD 20.42
+
+EUR 12.00 ➜ USD 13.89
+
+USD 15.00 ➜ EUR 12.96\
+    "
+    )
+}
+
+#[test]
+fn convert_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let ctxt = context(&server);
+
+    let conversions = convert(&ctxt, "A coffee for 2 €", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec!["EUR 2.00 ➜ USD 2.18", "EUR 2.00 ➜ GBP 1.71"]]
+    );
+    assert_eq!(server.requests().len(), 1);
+
+    // Rates are now in the database
+    let conversions = convert(&ctxt, "10 EUR", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec!["EUR 10.00 ➜ USD 10.88", "EUR 10.00 ➜ GBP 8.53"]]
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn convert_derived_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let ctxt = context(&server);
+    convert(&ctxt, "1 EUR", None, None).unwrap();
+
+    // Through EUR, without any request
+    let conversions = convert(&ctxt, "£10", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec![
+            "GBP 10.00 ➜ EUR 11.72 (derived)",
+            "GBP 10.00 ➜ USD 12.75 (derived)"
+        ]]
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn convert_fee_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let mut ctxt = context(&server);
+    ctxt.cfg.fees = vec![
+        FeeProfile {
+            name: String::from("card"),
+            percent: 2.5,
+            fixed: 0.,
+            fixed_currency: None,
+            pairs: vec![String::from("EUR/USD")],
+            providers: Vec::new(),
+        },
+        FeeProfile {
+            name: String::from("wire"),
+            percent: 0.5,
+            fixed: 15.,
+            fixed_currency: Some(String::from("EUR")),
+            pairs: Vec::new(),
+            providers: Vec::new(),
+        },
+    ];
+
+    // No fee profile selected
+    let conversions = convert(&ctxt, "100 EUR", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec!["EUR 100.00 ➜ USD 108.76", "EUR 100.00 ➜ GBP 85.32"]]
+    );
+
+    // The card profile does not apply to EUR/GBP
+    ctxt.cfg.fee = Some(String::from("card"));
+    let conversions = convert(&ctxt, "100 EUR", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec![
+            "EUR 100.00 ➜ USD 108.76 | card: USD 106.04 (cost USD 2.72)",
+            "EUR 100.00 ➜ GBP 85.32"
+        ]]
+    );
+
+    // The fixed part in EUR is converted to GBP first
+    ctxt.cfg.fee = Some(String::from("wire"));
+    let conversions = convert(&ctxt, "£100", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec![
+            "GBP 100.00 ➜ EUR 117.21 (derived) | wire: EUR 101.69 (cost EUR 15.51)",
+            "GBP 100.00 ➜ USD 127.47 (derived) | wire: USD 110.60 (cost USD 16.87)"
+        ]]
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn convert_crypto_test() {
+    let server = MockServer::start(vec![
+        Route::ok("/api/v3/simple/price", fixtures::COINGECKO_PRICES),
+        Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR),
+    ]);
+    let mut ctxt = context(&server);
+    ctxt.destination_currencies = vec![&EUR, &USD, &ETH];
+
+    let conversions = convert(&ctxt, "0.5 ₿", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec![
+            "BTC 0.50000000 ➜ EUR 30945.10",
+            "BTC 0.50000000 ➜ USD 33506.25",
+            "BTC 0.50000000 ➜ ETH 13.67183515"
+        ]]
+    );
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(
+        requests[0].contains("ids=bitcoin%2Cethereum"),
+        "{}",
+        requests[0]
+    );
+
+    // Fiat to crypto from CoinGecko, fiat to fiat from the other provider. EUR to BTC is the
+    // inverse of the stored BTC to EUR rate
+    ctxt.destination_currencies = vec![&BTC, &USD, &ETH];
+    let conversions = convert(&ctxt, "100 EUR", None, None).unwrap();
+    assert_eq!(
+        conversion_strings(&conversions),
+        vec![vec![
+            "EUR 100.00 ➜ BTC 0.00161576 (derived)",
+            "EUR 100.00 ➜ USD 108.76",
+            "EUR 100.00 ➜ ETH 0.04418132"
+        ]]
+    );
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+fn convert_provider_error_test() {
+    let server = MockServer::start(vec![Route::new(
+        "/api/v7/convert",
+        400,
+        fixtures::CURRCONV_INVALID_KEY,
+    )]);
+    let ctxt = context(&server);
+
+    let err = convert(&ctxt, "2 €", None, None).unwrap_err();
+    let err = err.downcast_ref::<RateError>().unwrap();
+    assert!(matches!(err, RateError::InvalidKey { .. }), "{:?}", err);
+}
+
+#[test]
+fn convert_at_test() {
+    let server = MockServer::start(vec![Route::ok(
+        "/stats/eurofxref/eurofxref-hist",
+        fixtures::ECB_HIST_90D,
+    )]);
+    let ctxt = context(&server);
+    let at: NaiveDate = "2026-10-15".parse().unwrap();
+
+    let conversions = convert(&ctxt, "$10", None, Some(at)).unwrap();
+    assert_eq!(
+        strings_of_conversions(&conversions, Some(at)),
+        vec![vec![
+            "USD 10.00 ➜ EUR 8.00 on 2026-10-15",
+            "USD 10.00 ➜ GBP 6.00 on 2026-10-15"
+        ]]
+    );
+    assert_eq!(server.requests().len(), 1);
+
+    // Saturday, the rate of Friday is used and is already stored
+    let saturday: NaiveDate = "2026-10-17".parse().unwrap();
+    let conversions = convert(&ctxt, "$10", None, Some(saturday)).unwrap();
+    assert_eq!(
+        strings_of_conversions(&conversions, Some(saturday)),
+        vec![vec![
+            "USD 10.00 ➜ EUR 9.19 on 2026-10-16",
+            "USD 10.00 ➜ GBP 7.84 on 2026-10-16"
+        ]]
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn sum_test() {
+    let server = MockServer::start(vec![
+        Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR),
+        Route::ok("/api/v3/simple/price", r#"{"bitcoin":{"usd":67000}}"#),
+    ]);
+    let ctxt = context(&server);
+
+    convert(&ctxt, "1 EUR", None, None).unwrap();
+
+    // Rates for GBP and USD are derived from those stored for EUR
+    let txt = "taxi 23 €, hotel £140, dinner 45 USD";
+    let items = sum::items(&ctxt, sum::price_tags(txt, None), None).unwrap();
+    assert_eq!(server.requests().len(), 1);
+    assert!(sum::warnings(&ctxt, &items, None, Utc::now()).is_empty());
+
+    let table = sum::table(&ctxt, &items);
+    let rows: Vec<Vec<&str>> = table
+        .lines()
+        .filter(|line| line.starts_with('║'))
+        .map(|line| {
+            line.trim_matches('║')
+                .split('║')
+                .map(|cell| cell.trim())
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec!["Price tag", "EUR", "USD", "GBP"],
+            vec!["EUR 23.00", "EUR 23.00", "USD 25.01", "GBP 19.62"],
+            vec!["GBP 140.00", "EUR 164.09", "USD 178.46", "GBP 140.00"],
+            vec!["USD 45.00", "EUR 41.38", "USD 45.00", "GBP 35.30"],
+            vec!["Total", "EUR 228.46", "USD 248.48", "GBP 194.93"],
+        ]
+    );
+
+    // Rates are stale a few days later
+    let warnings = sum::warnings(&ctxt, &items, None, Utc::now() + chrono::Duration::days(3));
+    assert_eq!(warnings.len(), 6, "{:?}", warnings);
+
+    // Only the rate to USD is known for BTC
+    let items = sum::items(&ctxt, sum::price_tags("100 EUR and 1 ₿", None), None).unwrap();
+    let warnings = sum::warnings(&ctxt, &items, None, Utc::now());
+    assert_eq!(
+        warnings,
+        vec![
+            "no rate from BTC to EUR, BTC 1.00000000 is left out of the EUR total",
+            "no rate from BTC to GBP, BTC 1.00000000 is left out of the GBP total",
+        ]
+    );
+    assert!(sum::table(&ctxt, &items).contains("USD 67108.76"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn stream_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let ctxt = context(&server);
+
+    // Longer than a pipe buffer, with invalid UTF-8
+    let mut input = b"A coffee for 2 \xe2\x82\xac\r\nnothing here\n".to_vec();
+    input.extend(b"filler\n".repeat(2000));
+    input.extend(b"\xff 10 EUR, then 20 EUR");
+    let mut out = Vec::new();
+    stream(&ctxt, &input[..], &mut out, Some(1), None, true).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+1: EUR 2.00 ➜ USD 2.18
+1: EUR 2.00 ➜ GBP 1.71
+2003: EUR 10.00 ➜ USD 10.88
+2003: EUR 10.00 ➜ GBP 8.53
+"
+    );
+    assert_eq!(server.requests().len(), 1);
+
+    // A history entry per line with conversions
+    let history = ctxt.db.read_from_history_max(10).unwrap();
+    assert_eq!(history.len(), 2);
+
+    let mut out = Vec::new();
+    stream(&ctxt, &b"3 EUR"[..], &mut out, None, None, false).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "1: EUR 3.00 ➜ USD 3.26\n1: EUR 3.00 ➜ GBP 2.56\n"
+    );
+    assert_eq!(ctxt.db.read_from_history_max(10).unwrap().len(), 2);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn convert_located_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let ctxt = context(&server);
+
+    let html = "<li>Mug <span class=\"price\">19<sup>99</sup></span>&nbsp;&euro;</li><li>2 €</li>";
+    let extracted = markup::extract(Markup::Html, html);
+    let located = convert_located(&ctxt, &extracted, None, None).unwrap();
+    let located: Vec<(&str, Vec<String>)> = located
+        .iter()
+        .map(|(range, conversions)| {
+            let conversions = conversions.iter().map(|c| c.to_string()).collect();
+            (&html[range.clone()], conversions)
+        })
+        .collect();
+    assert_eq!(
+        located,
+        vec![
+            (
+                "19<sup>99</sup></span>&nbsp;&euro;",
+                vec![
+                    String::from("EUR 19.99 ➜ USD 21.74"),
+                    String::from("EUR 19.99 ➜ GBP 17.06")
+                ]
+            ),
+            (
+                "2 €",
+                vec![
+                    String::from("EUR 2.00 ➜ USD 2.18"),
+                    String::from("EUR 2.00 ➜ GBP 1.71")
+                ]
+            ),
+        ]
+    );
+
+    let located = convert_located(&ctxt, &extracted, Some(1), None).unwrap();
+    assert_eq!(located.len(), 1);
+}
+
+#[cfg(feature = "async")]
+mod prefetch {
+    use super::*;
+    use crate::api::mock;
+    use crate::config::Quota;
+    use crate::currency::GBP;
+
+    #[test]
+    fn prefetch_test() {
+        let server = MockServer::start(vec![
+            Route::ok("/api/v3/simple/price", fixtures::COINGECKO_PRICES),
+            Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR),
+        ]);
+        let mut ctxt = context(&server);
+        ctxt.destination_currencies = vec![&USD, &GBP, &ETH];
+        let pairs = [(&EUR, &USD), (&EUR, &GBP), (&EUR, &ETH), (&EUR, &EUR)];
+
+        ctxt.offline = true;
+        prefetch_current_rates(&ctxt, &pairs);
+        assert!(server.requests().is_empty());
+
+        // One request per provider, recorded against their quota
+        ctxt.offline = false;
+        prefetch_current_rates(&ctxt, &pairs);
+        assert_eq!(server.requests().len(), 2);
+        let day_before = Utc::now() - chrono::Duration::days(1);
+        for provider in ["currencyconverterapi.com", "coingecko.com"] {
+            assert_eq!(ctxt.db.count_requests(provider, day_before).unwrap().0, 1);
+        }
+
+        // Conversions find the rates in the store
+        let conversions = convert(&ctxt, "100 EUR", None, None).unwrap();
+        assert_eq!(conversions[0].len(), 3);
+        prefetch_current_rates(&ctxt, &pairs);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn prefetch_budget_test() {
+        let server = MockServer::start(vec![
+            Route::ok("/api/v3/simple/price", fixtures::COINGECKO_PRICES),
+            Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR),
+        ]);
+        let mut cfg = mock::config(&server);
+        cfg.apis.currency_converter_api_com.quota = Quota {
+            per_hour: Some(1),
+            per_month: None,
+        };
+        let ctxt = mock::context_with(cfg);
+        let pairs = [(&EUR, &USD), (&GBP, &USD), (&USD, &EUR), (&EUR, &ETH)];
+
+        // A single request fits in the budget of currencyconverterapi.com, for all source currencies
+        prefetch_current_rates(&ctxt, &pairs);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|r| r.starts_with("/api/v7/convert"))
+                .count(),
+            1
+        );
+        let hour_before = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(
+            ctxt.db
+                .count_requests("currencyconverterapi.com", hour_before)
+                .unwrap()
+                .0,
+            1
+        );
+    }
+}
@@ -21,6 +21,7 @@ use serde_derive::{Deserialize, Serialize};
 
 use std::fmt;
 
+use crate::fee::Fee;
 use crate::rate::Rate;
 
 #[cfg(test)]
@@ -35,6 +36,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn convert_test() {
+        let rate = Rate::now(&EUR, &USD, 1.1, String::from("test"), None);
+        let price_tag = PriceTag::new(&EUR, 200.);
+
+        let quote = price_tag.convert(&rate, None).unwrap();
+        assert_eq!(quote.mid_market().to_string(), "USD 220.00");
+        assert_eq!(quote.charged(), quote.mid_market());
+
+        let fee = Fee::new("wire", 0.5, 15.);
+        let quote = price_tag.convert(&rate, Some(&fee)).unwrap();
+        assert_eq!(quote.mid_market().to_string(), "USD 220.00");
+        assert_eq!(quote.charged().to_string(), "USD 202.48");
+        assert_eq!(quote.cost().to_string(), "USD 17.52");
+
+        assert!(PriceTag::new(&USD, 1.).convert(&rate, None).is_err());
+    }
+
+    #[test]
+    fn decimals_test() {
+        assert_eq!(PriceTag::new(&EUR, 1.5).to_string(), "EUR 1.50");
+        assert_eq!(PriceTag::new(&BTC, 0.000123).to_string(), "BTC 0.00012300");
+        assert!(ETH.is_crypto() && !USD.is_crypto());
+    }
+
     #[test]
     fn static_currency_iso() {
         for c in ALL_CURRENCIES.iter() {
@@ -73,29 +99,77 @@ impl<'c> PriceTag<'c> {
     }
 
     /// Get currency of the amount
-    pub fn currency(&self) -> &Currency {
-        &self.currency
+    pub fn currency(&self) -> &'c Currency {
+        self.currency
+    }
+
+    /// Get the amount, in the currency of the price tag
+    pub fn amount(&self) -> f64 {
+        self.amount
     }
 
     // TODO Place this method with Rate structure to avoid having a rate method
     /// Convert the amount (in src currency) to an amount (in a dest currency).
-    /// The relation from the currency to the other is given by a rate.
+    /// The relation from the currency to the other is given by a rate. The fee, if any, gives
+    /// the amount actually received on top of the mid-market one.
     pub fn convert<'a, 'r>(
         &'a self,
         rate: &'r Rate<'c>,
-    ) -> Result<PriceTag<'r>, ConversionError<'a, 'c, 'r>> {
+        fee: Option<&Fee>,
+    ) -> Result<Quote<'c>, ConversionError<'a, 'c, 'r>> {
         if self.currency != rate.src() {
             Err(ConversionError::new(rate, &self))
         } else {
-            Ok(PriceTag::new(rate.dst(), rate.rate() * self.amount))
+            let mid_market = PriceTag::new(rate.dst(), rate.rate() * self.amount);
+            let charged = match fee {
+                Some(fee) => PriceTag::new(rate.dst(), fee.apply(self.amount, rate.rate())),
+                None => mid_market.clone(),
+            };
+            Ok(Quote {
+                mid_market,
+                charged,
+            })
         }
     }
 }
 
+/// Amounts resulting from a conversion, at the mid-market rate and once fees are paid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote<'c> {
+    mid_market: PriceTag<'c>,
+    charged: PriceTag<'c>,
+}
+
+impl<'c> Quote<'c> {
+    /// Amount at the mid-market rate
+    pub fn mid_market(&self) -> &PriceTag<'c> {
+        &self.mid_market
+    }
+
+    /// Amount received once fees are paid, the mid-market one without fees
+    pub fn charged(&self) -> &PriceTag<'c> {
+        &self.charged
+    }
+
+    /// Cost of the conversion, in the destination currency
+    pub fn cost(&self) -> PriceTag<'c> {
+        PriceTag::new(
+            self.mid_market.currency,
+            self.mid_market.amount - self.charged.amount,
+        )
+    }
+}
+
 impl<'c> fmt::Display for PriceTag<'c> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // TODO Use symbol, proper separator (, or .), proper number of cents (usually 2 or 3)
-        write!(f, "{} {:.*}", self.currency.get_main_iso(), 2, self.amount)
+        write!(
+            f,
+            "{} {:.*}",
+            self.currency.get_main_iso(),
+            self.currency.decimals(),
+            self.amount
+        )
     }
 }
 
@@ -125,6 +199,8 @@ pub struct Currency {
     names: &'static [&'static str],
     /// Position to display symbols
     pos: Pos,
+    /// Cryptocurrency, with rates from dedicated providers
+    crypto: bool,
 }
 
 impl Currency {
@@ -149,6 +225,20 @@ impl Currency {
         self.pos
     }
 
+    pub fn is_crypto(&self) -> bool {
+        self.crypto
+    }
+
+    /// Number of decimals to display amounts with. Cryptocurrencies are often exchanged in
+    /// small fractions
+    pub fn decimals(&self) -> usize {
+        if self.crypto {
+            8
+        } else {
+            2
+        }
+    }
+
     /// Constructor, copies the &str given. Panics if vectors are empty TODO Use Result type instead
     pub fn new(
         symbols: &'static [&'static str],
@@ -161,6 +251,7 @@ impl Currency {
             isos,
             names,
             pos,
+            crypto: false,
         };
         assert!(c.check());
         c
@@ -189,6 +280,7 @@ pub const BTC: Currency = Currency {
     isos: &["BTC", "XBT"],
     names: &["Bitcoin"],
     pos: Pos::After,
+    crypto: true,
 };
 
 /// <https://en.wikipedia.org/wiki/United_States_dollar>
@@ -197,6 +289,7 @@ pub const USD: Currency = Currency {
     isos: &["USD"],
     names: &["United States dollar"],
     pos: Pos::Before,
+    crypto: false,
 };
 
 /// <https://en.wikipedia.org/wiki/Euro>
@@ -205,6 +298,7 @@ pub const EUR: Currency = Currency {
     isos: &["EUR"],
     names: &["Euro"],
     pos: Pos::After,
+    crypto: false,
 };
 
 /// <https://en.wikipedia.org/wiki/Pound_sterling>
@@ -213,6 +307,7 @@ pub const GBP: Currency = Currency {
     isos: &["GBP"],
     names: &["Pound sterling"],
     pos: Pos::Before,
+    crypto: false,
 };
 
 /// <https://en.wikipedia.org/wiki/Swiss_franc>
@@ -221,6 +316,7 @@ pub const CHF: Currency = Currency {
     isos: &["CHF"],
     names: &["Swiss Franc"],
     pos: Pos::Before,
+    crypto: false,
 };
 
 /// <https://en.wikipedia.org/wiki/Japanese_yen>
@@ -229,15 +325,89 @@ pub const JPY: Currency = Currency {
     isos: &["JPY"],
     names: &["Yen"],
     pos: Pos::Before,
+    crypto: false,
+};
+
+/// <https://en.wikipedia.org/wiki/Ethereum>
+pub const ETH: Currency = Currency {
+    symbols: &["Ξ"],
+    isos: &["ETH"],
+    names: &["Ether"],
+    pos: Pos::After,
+    crypto: true,
+};
+
+/// <https://en.wikipedia.org/wiki/Tether_(cryptocurrency)>
+pub const USDT: Currency = Currency {
+    symbols: &["₮"],
+    isos: &["USDT"],
+    names: &["Tether"],
+    pos: Pos::After,
+    crypto: true,
+};
+
+/// <https://en.wikipedia.org/wiki/USD_Coin>
+pub const USDC: Currency = Currency {
+    symbols: &["USDC"],
+    isos: &["USDC"],
+    names: &["USD Coin"],
+    pos: Pos::After,
+    crypto: true,
+};
+
+/// <https://en.wikipedia.org/wiki/Binance#BNB>
+pub const BNB: Currency = Currency {
+    symbols: &["BNB"],
+    isos: &["BNB"],
+    names: &["BNB"],
+    pos: Pos::After,
+    crypto: true,
+};
+
+/// <https://en.wikipedia.org/wiki/Ripple_Labs#XRP>
+pub const XRP: Currency = Currency {
+    symbols: &["XRP"],
+    isos: &["XRP"],
+    names: &["XRP"],
+    pos: Pos::After,
+    crypto: true,
+};
+
+/// <https://en.wikipedia.org/wiki/Litecoin>
+pub const LTC: Currency = Currency {
+    symbols: &["LTC"],
+    isos: &["LTC"],
+    names: &["Litecoin"],
+    pos: Pos::After,
+    crypto: true,
+};
+
+/// <https://en.wikipedia.org/wiki/Dogecoin>
+pub const DOGE: Currency = Currency {
+    symbols: &["DOGE"],
+    isos: &["DOGE"],
+    names: &["Dogecoin"],
+    pos: Pos::After,
+    crypto: true,
 };
 
 lazy_static! {
     /// All currencies registered
     pub static ref ALL_CURRENCIES: Vec<Currency> = vec![
-        BTC, USD, EUR, GBP, CHF, JPY
+        BTC, USD, EUR, GBP, CHF, JPY, ETH, USDT, USDC, BNB, XRP, LTC, DOGE
     ];
 }
 
+/// Get an existing currency from any of its ISO codes or symbols, ignoring case
+pub fn from_iso_or_symbol(word: &str) -> Option<&'static Currency> {
+    let word = word.to_uppercase();
+    let all: &'static [Currency] = &ALL_CURRENCIES;
+    all.iter().find(|c| {
+        c.isos().iter().any(|iso| *iso == word)
+            || c.symbols().iter().any(|s| s.to_uppercase() == word)
+    })
+}
+
 /// Get an existing currency from ISO code
 pub fn existing_from_iso(code: &str) -> Option<&'static Currency> {
     match code {
@@ -247,6 +417,13 @@ pub fn existing_from_iso(code: &str) -> Option<&'static Currency> {
         "GBP" => Some(&GBP),
         "CHF" => Some(&CHF),
         "JPY" => Some(&JPY),
+        "ETH" => Some(&ETH),
+        "USDT" => Some(&USDT),
+        "USDC" => Some(&USDC),
+        "BNB" => Some(&BNB),
+        "XRP" => Some(&XRP),
+        "LTC" => Some(&LTC),
+        "DOGE" => Some(&DOGE),
         _ => None,
     }
 }
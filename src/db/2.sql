@@ -0,0 +1,13 @@
+-- Keep one rate per day, to convert at a given date
+CREATE TABLE rates_series(
+    src TEXT NOT NULL,
+    dst TEXT NOT NULL,
+    day TEXT NOT NULL, -- ISO date
+    rate REAL NOT NULL,
+    provider TEXT NOT NULL,
+    PRIMARY KEY (src, dst, day, provider),
+    CHECK (src <> dst)
+);
+
+INSERT INTO rates_series (src, dst, day, rate, provider)
+    SELECT src, dst, substr(date, 1, 10), rate, provider FROM rates WHERE date IS NOT NULL;
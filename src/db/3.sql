@@ -0,0 +1,15 @@
+-- Conversions performed when an entry was added to the history
+CREATE TABLE history_conversions(
+    history_rowid INTEGER NOT NULL, -- rowid of the history entry
+    src TEXT NOT NULL,
+    amount REAL NOT NULL,
+    dst TEXT NOT NULL,
+    converted_amount REAL NOT NULL,
+    rate REAL NOT NULL,
+    provider TEXT NOT NULL,
+    rate_date TEXT NOT NULL -- ISO datetime
+);
+CREATE INDEX history_conversions_rowid ON history_conversions(history_rowid);
+-- Whether the conversions of a history entry were stored when it was added, even if there were
+-- none. Entries already in the history have none and are converted again when listed
+ALTER TABLE history ADD COLUMN conversions_stored INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,13 @@
+-- Requests sent to rate providers, to keep track of their quotas
+CREATE TABLE api_requests(
+    provider TEXT NOT NULL,
+    datetime TEXT NOT NULL, -- ISO datetime
+    success INTEGER NOT NULL -- 1 if rates were returned, 0 otherwise
+);
+CREATE INDEX api_requests_provider_datetime ON api_requests(provider, datetime);
+
+-- Providers that asked not to be called again before some time
+CREATE TABLE api_blocks(
+    provider TEXT PRIMARY KEY NOT NULL,
+    until TEXT NOT NULL -- ISO datetime
+);
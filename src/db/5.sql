@@ -0,0 +1,10 @@
+-- Alerts on the rate of a pair, evaluated when rates are stored or checked
+CREATE TABLE alerts(
+    src TEXT NOT NULL,
+    dst TEXT NOT NULL,
+    direction TEXT NOT NULL, -- above or below
+    threshold REAL NOT NULL, -- a rate, or a move in percent if days is set
+    days INTEGER, -- period of the move
+    triggered INTEGER NOT NULL DEFAULT 0 -- 1 if the condition held at the last evaluation
+);
+CREATE INDEX alerts_src_dst ON alerts(src, dst);
@@ -0,0 +1,130 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Alerts table of the database
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+/// Side of the threshold a rate has to reach for an alert to trigger
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+impl Direction {
+    /// Name of the direction, as stored in the database
+    #[cfg(feature = "cli")]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Above => "above",
+            Direction::Below => "below",
+        }
+    }
+}
+
+/// Alert on the rate of a pair, maps to the db schema. Without days, the threshold is a rate.
+/// With days, it is a move of the rate over that many days, in percent: a rise for Above, a fall
+/// for Below
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Alert {
+    /// Set when the alert is added
+    pub rowid: u32,
+    /// Source currency, by main iso
+    pub src: String,
+    /// Destination currency, by main iso
+    pub dst: String,
+    pub direction: Direction,
+    pub threshold: f64,
+    /// Period of the move, for alerts on a move
+    pub days: Option<u32>,
+    /// Whether the condition held the last time the alert was evaluated. Alerts trigger only
+    /// when the condition starts to hold
+    pub triggered: bool,
+}
+
+impl Alert {
+    /// Whether the condition holds for a rate, given the rate days ago for alerts on a move
+    pub fn holds(&self, rate: f64, reference: Option<f64>) -> bool {
+        match (self.days, reference) {
+            (None, _) => match self.direction {
+                Direction::Above => rate >= self.threshold,
+                Direction::Below => rate <= self.threshold,
+            },
+            (Some(_), Some(reference)) if reference != 0. => {
+                let change = (rate - reference) / reference * 100.;
+                match self.direction {
+                    Direction::Above => change >= self.threshold,
+                    Direction::Below => change <= -self.threshold,
+                }
+            }
+            // Nothing to compare to
+            (Some(_), _) => false,
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ➜ {} ", self.src, self.dst)?;
+        match (self.days, self.direction) {
+            (None, Direction::Above) => write!(f, "above {}", self.threshold),
+            (None, Direction::Below) => write!(f, "below {}", self.threshold),
+            (Some(days), Direction::Above) => {
+                write!(f, "up {}% over {} days", self.threshold, days)
+            }
+            (Some(days), Direction::Below) => {
+                write!(f, "down {}% over {} days", self.threshold, days)
+            }
+        }
+    }
+}
+
+/// Alert whose condition started to hold with a new rate
+#[derive(Clone, PartialEq, Debug)]
+pub struct Triggered {
+    pub alert: Alert,
+    /// Rate of the pair of the alert
+    pub rate: f64,
+    /// Rate days ago, for alerts on a move
+    pub reference: Option<f64>,
+    /// Provider of the rate
+    pub provider: String,
+    /// Date of the rate
+    pub date: DateTime<Utc>,
+}
+
+impl fmt::Display for Triggered {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:.4}", self.alert, self.rate)?;
+        if let Some(reference) = self.reference {
+            write!(f, ", was {:.4}", reference)?;
+        }
+        write!(
+            f,
+            " on {} ({})",
+            self.date.format("%Y-%m-%d %H:%M"),
+            self.provider
+        )
+    }
+}
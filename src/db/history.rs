@@ -29,4 +29,28 @@ pub struct History {
     pub rowid: u32,
     pub datetime: DateTime<Utc>,
     pub content: String,
+    /// Whether the conversions performed when the entry was added are stored, even if there were
+    /// none. Entries added before conversions were stored have to be converted again
+    pub conversions_stored: bool,
+}
+
+/// Conversion performed when a history entry was added, maps to the db schema
+#[derive(Clone, PartialOrd, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HistoryConversion {
+    /// Rowid of the history entry
+    pub history_rowid: u32,
+    /// Source currency, by main iso
+    pub src: String,
+    /// Amount, in the source currency
+    pub amount: f64,
+    /// Destination currency, by main iso
+    pub dst: String,
+    /// Amount, in the destination currency
+    pub converted_amount: f64,
+    /// Rate used for the conversion
+    pub rate: f64,
+    /// Provider of the rate
+    pub provider: String,
+    /// Date of the rate
+    pub rate_date: DateTime<Utc>,
 }
@@ -22,6 +22,11 @@ use lazy_static::lazy_static;
 use rusqlite_migration::{Migrations, M};
 
 lazy_static! {
-    pub static ref MIGRATIONS: Migrations<'static> =
-        Migrations::new(vec![M::up(include_str!("1.sql"))]);
+    pub static ref MIGRATIONS: Migrations<'static> = Migrations::new(vec![
+        M::up(include_str!("1.sql")),
+        M::up(include_str!("2.sql")),
+        M::up(include_str!("3.sql")),
+        M::up(include_str!("4.sql")),
+        M::up(include_str!("5.sql")),
+    ]);
 }
@@ -22,6 +22,7 @@ use std::convert::TryInto;
 
 use anyhow::Result;
 use chrono::DateTime;
+use chrono::NaiveDate;
 use chrono::Utc;
 use log::{debug, trace, warn};
 use rusqlite::named_params;
@@ -32,21 +33,33 @@ use serde_rusqlite::from_row_with_columns;
 use serde_rusqlite::from_rows;
 use serde_rusqlite::to_params_named;
 
+pub mod alert;
+#[cfg(feature = "cli")]
 pub mod history;
 mod migrations;
-mod rate;
+pub(crate) mod rate;
 
-use self::history::History;
+use self::alert::{Alert, Triggered};
+#[cfg(feature = "cli")]
+use self::history::{History, HistoryConversion};
 use migrations::MIGRATIONS;
-use rate::RateInternal;
+use rate::{DatedRateInternal, RateInternal};
 
 use crate::config::Config;
 use crate::currency::Currency;
 use crate::rate::Rate;
+use crate::store::RateStore;
 
 #[cfg(test)]
 mod tests;
 
+/// Requests sent to providers are kept that many days, enough to count them over a month
+const REQUESTS_RETENTION_DAYS: i64 = 62;
+
+/// Number of days before the start of the period of an alert on a move to look for a rate, when
+/// the exact day is missing
+const ALERT_REFERENCE_MAX_GAP_DAYS: i64 = 4;
+
 /// Store and bucket, represent the whole database
 pub struct Db {
     conn: Connection,
@@ -56,7 +69,7 @@ impl Db {
     /// Initialize the rate database
     pub fn new(cfg: &Config) -> Result<Self> {
         trace!("Initialize database");
-        let mut conn = Connection::open(cfg.db_path())?;
+        let mut conn = Connection::open(&cfg.db_path)?;
 
         conn.pragma_update(None, "journal_mode", &"WAL").unwrap();
 
@@ -76,114 +89,242 @@ impl Db {
         Ok(Db { conn })
     }
 
-    /// In memory database, mainly for testing
-    #[cfg(test)]
-    fn new_in_memory() -> Result<Self> {
+    /// In memory database, lost when dropped
+    pub fn new_in_memory() -> Result<Self> {
         let conn = Connection::open_in_memory()?;
         Db::init(conn)
     }
 }
 
 impl Db {
-    /// Retrieve rates from a currency to another. Returns a vector of up-to-date rates
-    pub fn get_uptodate_rates<'c>(
+    /// Set rate from a currency to another, mainly for testing
+    #[cfg(test)]
+    pub fn set_rate(&self, rate: &Rate) -> Result<Vec<Triggered>> {
+        self.set_rates(std::slice::from_ref(rate))
+    }
+
+    /// Set several rates at once, in a single transaction, adding them to the rate series.
+    /// Returns the alerts triggered by the new rates
+    pub fn set_rates(&self, rates: &[Rate]) -> Result<Vec<Triggered>> {
+        let tx = self.conn.unchecked_transaction()?;
+        for rate in rates {
+            self.insert_rate(rate)?;
+        }
+        let triggered = self.insert_and_check(rates)?;
+        tx.commit()?;
+
+        Ok(triggered)
+    }
+
+    /// Add rates kept in another rate store to the rate series, to convert at a given date later,
+    /// and evaluate alerts, in a single transaction. Returns the alerts triggered by the new rates
+    pub fn record_rates(&self, rates: &[Rate]) -> Result<Vec<Triggered>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let triggered = self.insert_and_check(rates)?;
+        tx.commit()?;
+
+        Ok(triggered)
+    }
+
+    /// Insert rates into the series and evaluate alerts, outside of any transaction
+    fn insert_and_check(&self, rates: &[Rate]) -> Result<Vec<Triggered>> {
+        self.insert_into_series(rates)?;
+        let mut triggered = Vec::new();
+        for rate in rates {
+            triggered.append(&mut self.check_alerts(rate)?);
+        }
+        Ok(triggered)
+    }
+
+    /// Insert or replace a rate, outside of any transaction
+    fn insert_rate(&self, rate: &Rate) -> Result<()> {
+        if rate.src() == rate.dst() {
+            warn!("Same source and destination currency, don’t store");
+            return Ok(());
+        }
+
+        let ri: RateInternal = rate.try_into()?;
+
+        let n = self.conn.execute_named(
+            "INSERT OR REPLACE INTO rates (src, dst, date, rate, provider, cache_until) VALUES (:src, :dst, :date, :rate, :provider, :cache_until)",
+            &to_params_named(ri)?.to_slice(),
+        )?;
+
+        debug!("Upserted {} rows.", n);
+        Ok(())
+    }
+
+    /// Add dated rates to the rate series, in a single transaction. Only the last rate of a day is
+    /// kept for a given provider
+    pub fn add_to_series(&self, rates: &[Rate]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.insert_into_series(rates)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Insert or replace dated rates in the series, outside of any transaction
+    fn insert_into_series(&self, rates: &[Rate]) -> Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO rates_series (src, dst, day, rate, provider) \
+             VALUES (:src, :dst, :day, :rate, :provider)",
+        )?;
+        for rate in rates {
+            if rate.src() == rate.dst() {
+                continue;
+            }
+            let dri: DatedRateInternal = rate.into();
+            stmt.execute_named(&to_params_named(dri)?.to_slice())?;
+        }
+
+        Ok(())
+    }
+
+    /// Retrieve the rate in effect on a given day: the rate of that day or, when it is missing
+    /// (week-ends, bank holidays…), of the closest prior day, at most max_gap_days before. The
+    /// inverse of the reverse pair is used if the pair itself is not found.
+    pub fn get_rate_at<'c>(
         &self,
         src: &'c Currency,
         dst: &'c Currency,
         provider: &str,
-        now: DateTime<Utc>,
-    ) -> Result<Vec<Rate<'c>>> {
-        trace!("get_rates_uptodate_rates({}, {}, {:?})", src, dst, provider);
-        // Hard code this to limit storage overhead
+        day: NaiveDate,
+        max_gap_days: i64,
+    ) -> Result<Option<Rate<'c>>> {
+        trace!("get_rate_at({}, {}, {:?}, {})", src, dst, provider, day);
         if src == dst {
-            warn!("Same source and destination currency, don’t store");
-            return Ok(vec![Rate::parity(src)]);
+            return Ok(Some(Rate::parity(src)));
         }
 
         let mut stmt = self.conn.prepare_cached(
-            "SELECT * FROM rates \
+            "SELECT * FROM rates_series \
              WHERE src = :src AND dst = :dst
-             AND cache_until > :now
-             AND provider = :provider",
+             AND provider = :provider
+             AND day <= :day AND day >= :oldest
+             ORDER BY day DESC LIMIT 1",
         )?;
         let columns = columns_from_statement(&stmt);
-        let mut rows = stmt.query_named(named_params! {
-            ":src": src.get_main_iso(),
-            ":dst": dst.get_main_iso(),
-            ":now": now,
-            ":provider": provider,
-        })?;
-
-        let mut uptodate_rates: Vec<Rate> = Vec::new();
-        while let Some(row) = rows.next()? {
-            let rate_internal = from_row_with_columns::<RateInternal>(row, &columns)?;
-            let rate: Rate = rate_internal.try_into()?;
-            uptodate_rates.push(rate);
-        }
-
-        trace!("uptodate_rates: {:?}", uptodate_rates);
-        Ok(uptodate_rates)
+        let oldest = day - chrono::Duration::days(max_gap_days);
+        let mut dated_rate = |a: &'c Currency, b: &'c Currency| -> Result<Option<Rate<'c>>> {
+            let mut rows = stmt.query_named(named_params! {
+                ":src": a.get_main_iso(),
+                ":dst": b.get_main_iso(),
+                ":provider": provider,
+                ":day": day,
+                ":oldest": oldest,
+            })?;
+            match rows.next()? {
+                Some(row) => {
+                    let dri = from_row_with_columns::<DatedRateInternal>(row, &columns)?;
+                    Ok(Some(dri.try_into()?))
+                }
+                None => Ok(None),
+            }
+        };
+
+        let rate = match dated_rate(src, dst)? {
+            Some(rate) => Some(rate),
+            None => dated_rate(dst, src)?.map(|rate: Rate| rate.inverse()),
+        };
+        trace!("rate at {}: {:?}", day, rate);
+        Ok(rate)
     }
 
-    /// Removes outdated rates. Returns the number of rates deleted
-    pub fn remove_outdated_rates<'c>(
+    /// Daily rates from a currency to another between two days, included, oldest first. Rates of
+    /// the reverse pair are inverted for the days the pair itself is missing. When several
+    /// providers have a rate for a day, one of them is picked
+    #[cfg(feature = "cli")]
+    pub fn get_rate_series<'c>(
         &self,
         src: &'c Currency,
         dst: &'c Currency,
-        provider: &str,
-        now: DateTime<Utc>,
-    ) -> Result<usize> {
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<Rate<'c>>> {
+        trace!("get_rate_series({}, {}, {}, {})", src, dst, from, to);
         let mut stmt = self.conn.prepare_cached(
-            "DELETE FROM rates \
-             WHERE src = :src AND dst = :dst
-             AND cache_until <= :now
-             AND provider = :provider",
+            "SELECT * FROM rates_series \
+             WHERE ((src = :src AND dst = :dst) OR (src = :dst AND dst = :src))
+             AND day >= :from AND day <= :to
+             ORDER BY day ASC, src = :src DESC, provider ASC",
         )?;
-
-        let deleted = stmt.execute_named(named_params! {
+        let columns = columns_from_statement(&stmt);
+        let mut rows = stmt.query_named(named_params! {
             ":src": src.get_main_iso(),
             ":dst": dst.get_main_iso(),
-            ":now": now,
-            ":provider": provider,
+            ":from": from,
+            ":to": to,
         })?;
 
-        trace!("deleted rates: {:?}", deleted);
-        Ok(deleted)
-    }
-
-    /// Set rate from a currency to another
-    pub fn set_rate(&self, rate: &Rate) -> Result<()> {
-        if rate.src() == rate.dst() {
-            warn!("Same source and destination currency, don’t store");
-            return Ok(());
+        let mut series: Vec<Rate<'c>> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let dri = from_row_with_columns::<DatedRateInternal>(row, &columns)?;
+            let rate: Rate<'c> = dri.try_into()?;
+            if series.last().map(|last| last.date()) == Some(rate.date()) {
+                continue;
+            }
+            if rate.src() == src {
+                series.push(rate);
+            } else {
+                series.push(rate.inverse());
+            }
         }
 
-        let ri: RateInternal = rate.try_into()?;
-
-        let n = self.conn.execute_named(
-            "INSERT OR REPLACE INTO rates (src, dst, date, rate, provider, cache_until) VALUES (:src, :dst, :date, :rate, :provider, :cache_until)",
-            &to_params_named(ri)?.to_slice(),
-        )?;
-
-        debug!("Upserted {} rows.", n);
-        Ok(())
+        trace!("series: {} rates", series.len());
+        Ok(series)
     }
 
-    /// Add an entry to history
-    pub fn add_to_history(&self, entry: &str) -> Result<()> {
+    /// Add an entry to history, with the conversions performed on it. Returns the rowid of the
+    /// new entry
+    #[cfg(feature = "cli")]
+    pub fn add_to_history(&self, entry: &str, conversions: &[HistoryConversion]) -> Result<u32> {
+        let tx = self.conn.unchecked_transaction()?;
         self.conn.execute_named(
-            "INSERT INTO history (datetime, content) VALUES (:datetime, :content)",
+            "INSERT INTO history (datetime, content, conversions_stored) \
+             VALUES (:datetime, :content, 1)",
             named_params! {
                 ":datetime": Utc::now(),
                 ":content": entry,
             },
         )?;
+        let rowid = self.conn.last_insert_rowid().try_into()?;
 
-        Ok(())
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO history_conversions \
+             (history_rowid, src, amount, dst, converted_amount, rate, provider, rate_date) \
+             VALUES (:history_rowid, :src, :amount, :dst, :converted_amount, :rate, :provider, :rate_date)",
+        )?;
+        for conversion in conversions {
+            let conversion = HistoryConversion {
+                history_rowid: rowid,
+                ..conversion.clone()
+            };
+            stmt.execute_named(&to_params_named(conversion)?.to_slice())?;
+        }
+        drop(stmt);
+        tx.commit()?;
+
+        Ok(rowid)
+    }
+
+    /// Read conversions stored with a history entry, in the order they were added
+    #[cfg(feature = "cli")]
+    pub fn read_history_conversions(&self, history_rowid: u32) -> Result<Vec<HistoryConversion>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM history_conversions \
+             WHERE history_rowid = ?1 \
+             ORDER BY rowid ASC",
+        )?;
+        let rows = from_rows::<HistoryConversion>(stmt.query(params! {history_rowid})?)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(rows)
     }
 
     /// Read entries from history. Returns at most <limit> recent history entry. Note that if limit
     /// is strictly lower than 0, then there is no limit
+    #[cfg(feature = "cli")]
     pub fn read_from_history_max(&self, limit: i32) -> Result<Vec<History>> {
         let mut stmt = self
             .conn
@@ -195,7 +336,19 @@ impl Db {
         Ok(rows)
     }
 
+    /// Latest entries of history, most recent first, at most limit of them
+    #[cfg(feature = "cli")]
+    pub fn read_latest_history(&self, limit: u32) -> Result<Vec<History>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT rowid, * FROM history ORDER BY datetime DESC, rowid DESC LIMIT ?1",
+        )?;
+        let rows = from_rows::<History>(stmt.query(params! {limit})?).collect::<Result<_, _>>()?;
+
+        Ok(rows)
+    }
+
     /// Remove old entries from history. Returns the number of deleted entries
+    #[cfg(feature = "cli")]
     pub fn read_from_history_before(&self, before_date: &DateTime<Utc>) -> Result<Vec<History>> {
         let mut stmt = self.conn.prepare_cached(
             "SELECT rowid, * \
@@ -211,7 +364,17 @@ impl Db {
     }
 
     /// Remove old entries from history. Returns the number of deleted entries
+    #[cfg(feature = "cli")]
     pub fn remove_from_history(&self, before_date: &DateTime<Utc>) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.conn.execute_named(
+            "DELETE FROM history_conversions \
+             WHERE history_rowid IN (SELECT rowid FROM history WHERE datetime <= :before_date)",
+            named_params! {
+                ":before_date": before_date,
+            },
+        )?;
+
         let mut stmt = self.conn.prepare_cached(
             "DELETE FROM history \
              WHERE datetime <= :before_date",
@@ -220,6 +383,288 @@ impl Db {
         let deleted = stmt.execute_named(named_params! {
             ":before_date": before_date,
         })?;
+        drop(stmt);
+        tx.commit()?;
+
+        trace!("deleted rates: {:?}", deleted);
+        Ok(deleted)
+    }
+
+    /// Record a request sent to a provider at the given time. Requests older than the retention
+    /// period are removed
+    pub fn record_request(
+        &self,
+        provider: &str,
+        datetime: DateTime<Utc>,
+        success: bool,
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.conn.execute_named(
+            "INSERT INTO api_requests (provider, datetime, success) \
+             VALUES (:provider, :datetime, :success)",
+            named_params! {
+                ":provider": provider,
+                ":datetime": datetime,
+                ":success": success,
+            },
+        )?;
+        self.conn.execute_named(
+            "DELETE FROM api_requests WHERE datetime < :oldest",
+            named_params! {
+                ":oldest": datetime - chrono::Duration::days(REQUESTS_RETENTION_DAYS),
+            },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Requests sent to a provider since the given time: number of requests, number of failed
+    /// ones and time of the oldest
+    pub fn count_requests(
+        &self,
+        provider: &str,
+        since: DateTime<Utc>,
+    ) -> Result<(u32, u32, Option<DateTime<Utc>>)> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT COUNT(*), COUNT(*) - IFNULL(SUM(success), 0), MIN(datetime) FROM api_requests \
+             WHERE provider = :provider AND datetime >= :since",
+        )?;
+        let count = stmt.query_row_named(
+            named_params! {
+                ":provider": provider,
+                ":since": since,
+            },
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        trace!("requests to {} since {}: {:?}", provider, since, count);
+        Ok(count)
+    }
+
+    /// Don’t call a provider before the given time
+    pub fn block_provider(&self, provider: &str, until: DateTime<Utc>) -> Result<()> {
+        self.conn.execute_named(
+            "INSERT OR REPLACE INTO api_blocks (provider, until) VALUES (:provider, :until)",
+            named_params! {
+                ":provider": provider,
+                ":until": until,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Time before which a provider must not be called, if it is after now
+    pub fn provider_blocked_until(
+        &self,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT until FROM api_blocks WHERE provider = :provider AND until > :now",
+        )?;
+        let mut rows = stmt.query_named(named_params! {
+            ":provider": provider,
+            ":now": now,
+        })?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Add an alert. Returns its rowid
+    #[cfg(feature = "cli")]
+    pub fn add_alert(&self, alert: &Alert) -> Result<u32> {
+        self.conn.execute_named(
+            "INSERT INTO alerts (src, dst, direction, threshold, days, triggered) \
+             VALUES (:src, :dst, :direction, :threshold, :days, :triggered)",
+            named_params! {
+                ":src": alert.src,
+                ":dst": alert.dst,
+                ":direction": alert.direction.as_str(),
+                ":threshold": alert.threshold,
+                ":days": alert.days,
+                ":triggered": false,
+            },
+        )?;
+        Ok(self.conn.last_insert_rowid().try_into()?)
+    }
+
+    /// Read all alerts, in the order they were added
+    #[cfg(feature = "cli")]
+    pub fn read_alerts(&self) -> Result<Vec<Alert>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT rowid, * FROM alerts ORDER BY rowid ASC")?;
+        let rows = from_rows::<Alert>(stmt.query(params![])?).collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Remove an alert. Returns the number of alerts deleted
+    #[cfg(feature = "cli")]
+    pub fn remove_alert(&self, rowid: u32) -> Result<usize> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM alerts WHERE rowid = ?1", params![rowid])?;
+        Ok(deleted)
+    }
+
+    /// Evaluate the alerts on the pair of a rate or on the reverse pair, recording whether their
+    /// condition holds. Returns the alerts whose condition started to hold
+    pub fn check_alerts(&self, rate: &Rate) -> Result<Vec<Triggered>> {
+        if rate.src() == rate.dst() {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT rowid, * FROM alerts WHERE src = :src AND dst = :dst ORDER BY rowid ASC",
+        )?;
+        let day = rate.date().naive_utc().date();
+        let mut triggered = Vec::new();
+        for (src, dst, value) in [
+            (rate.src(), rate.dst(), rate.rate()),
+            (rate.dst(), rate.src(), 1. / rate.rate()),
+        ]
+        .iter()
+        .copied()
+        {
+            let alerts = from_rows::<Alert>(stmt.query_named(named_params! {
+                ":src": src.get_main_iso(),
+                ":dst": dst.get_main_iso(),
+            })?)
+            .collect::<Result<Vec<_>, _>>()?;
+
+            for alert in alerts {
+                let reference = match alert.days {
+                    Some(days) => self
+                        .get_rate_at(
+                            src,
+                            dst,
+                            rate.provider(),
+                            day - chrono::Duration::days(days.into()),
+                            ALERT_REFERENCE_MAX_GAP_DAYS,
+                        )?
+                        .map(|rate| rate.rate()),
+                    None => None,
+                };
+                let holds = alert.holds(value, reference);
+                trace!("alert {} holds: {}", alert, holds);
+                if holds != alert.triggered {
+                    self.conn.execute(
+                        "UPDATE alerts SET triggered = ?1 WHERE rowid = ?2",
+                        params![holds, alert.rowid],
+                    )?;
+                }
+                if holds && !alert.triggered {
+                    triggered.push(Triggered {
+                        alert,
+                        rate: value,
+                        reference,
+                        provider: rate.provider().to_string(),
+                        date: *rate.date(),
+                    });
+                }
+            }
+        }
+
+        Ok(triggered)
+    }
+}
+
+/// Current rates in the rates table
+impl RateStore for Db {
+    fn get_uptodate_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Rate<'c>>> {
+        trace!("get_rates_uptodate_rates({}, {}, {:?})", src, dst, provider);
+        // Hard code this to limit storage overhead
+        if src == dst {
+            warn!("Same source and destination currency, don’t store");
+            return Ok(vec![Rate::parity(src)]);
+        }
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM rates \
+             WHERE src = :src AND dst = :dst
+             AND cache_until > :now
+             AND provider = :provider",
+        )?;
+        let columns = columns_from_statement(&stmt);
+        let mut rows = stmt.query_named(named_params! {
+            ":src": src.get_main_iso(),
+            ":dst": dst.get_main_iso(),
+            ":now": now,
+            ":provider": provider,
+        })?;
+
+        let mut uptodate_rates: Vec<Rate> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let rate_internal = from_row_with_columns::<RateInternal>(row, &columns)?;
+            let rate: Rate = rate_internal.try_into()?;
+            uptodate_rates.push(rate);
+        }
+
+        trace!("uptodate_rates: {:?}", uptodate_rates);
+        Ok(uptodate_rates)
+    }
+
+    fn get_latest_rate<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+    ) -> Result<Option<Rate<'c>>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT * FROM rates \
+             WHERE (src = :src AND dst = :dst) OR (src = :dst AND dst = :src)
+             ORDER BY date DESC LIMIT 1",
+        )?;
+        let columns = columns_from_statement(&stmt);
+        let mut rows = stmt.query_named(named_params! {
+            ":src": src.get_main_iso(),
+            ":dst": dst.get_main_iso(),
+        })?;
+        match rows.next()? {
+            Some(row) => {
+                let rate_internal = from_row_with_columns::<RateInternal>(row, &columns)?;
+                Ok(Some(rate_internal.try_into()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_rates(&self, rates: &[Rate]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for rate in rates {
+            self.insert_rate(rate)?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn remove_outdated_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        let mut stmt = self.conn.prepare_cached(
+            "DELETE FROM rates \
+             WHERE src = :src AND dst = :dst
+             AND cache_until <= :now
+             AND provider = :provider",
+        )?;
+
+        let deleted = stmt.execute_named(named_params! {
+            ":src": src.get_main_iso(),
+            ":dst": dst.get_main_iso(),
+            ":now": now,
+            ":provider": provider,
+        })?;
 
         trace!("deleted rates: {:?}", deleted);
         Ok(deleted)
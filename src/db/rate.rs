@@ -31,13 +31,13 @@ use crate::rate::Rate;
 /// RateInternal is a trimmed down version of Rate to map to the db schema.
 /// Fields have the same meaning as Rate
 #[derive(Clone, PartialOrd, PartialEq, Debug, Serialize, Deserialize)]
-pub(super) struct RateInternal {
-    pub(super) src: String,
-    pub(super) dst: String,
-    pub(super) date: DateTime<Utc>,
-    pub(super) rate: f64,
-    pub(super) provider: String,
-    pub(super) cache_until: DateTime<Utc>,
+pub(crate) struct RateInternal {
+    pub(crate) src: String,
+    pub(crate) dst: String,
+    pub(crate) date: DateTime<Utc>,
+    pub(crate) rate: f64,
+    pub(crate) provider: String,
+    pub(crate) cache_until: DateTime<Utc>,
 }
 
 impl TryFrom<RateInternal> for Rate<'static> {
@@ -80,6 +80,41 @@ impl<'r> TryFrom<&Rate<'r>> for RateInternal {
     }
 }
 
+/// DatedRateInternal maps to the rate series in the db schema, with one rate per day
+#[derive(Clone, PartialOrd, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct DatedRateInternal {
+    pub(crate) src: String,
+    pub(crate) dst: String,
+    pub(crate) day: NaiveDate,
+    pub(crate) rate: f64,
+    pub(crate) provider: String,
+}
+
+impl TryFrom<DatedRateInternal> for Rate<'static> {
+    type Error = RateInternalConversionError;
+
+    fn try_from(value: DatedRateInternal) -> Result<Rate<'static>, Self::Error> {
+        let src = currency::existing_from_iso(&value.src)
+            .ok_or(RateInternalConversionError::CurrencyNotFound)?;
+        let dst = currency::existing_from_iso(&value.dst)
+            .ok_or(RateInternalConversionError::CurrencyNotFound)?;
+        let date = Utc.from_utc_date(&value.day).and_hms(0, 0, 0);
+        Ok(Rate::new(src, dst, date, value.rate, value.provider, None))
+    }
+}
+
+impl<'r> From<&Rate<'r>> for DatedRateInternal {
+    fn from(value: &Rate<'r>) -> Self {
+        DatedRateInternal {
+            src: value.src().get_main_iso().to_string(),
+            dst: value.dst().get_main_iso().to_string(),
+            day: value.date().naive_utc().date(),
+            rate: value.rate(),
+            provider: value.provider().to_string(),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum RateInternalConversionError {
     MissingCacheUntil,
@@ -19,10 +19,10 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 //! Tests for the database
 
 use chrono::offset::Utc;
-use chrono::Duration;
+use chrono::{Duration, NaiveDate, TimeZone};
 
 use crate::{
-    currency::{BTC, CHF, EUR, JPY},
+    currency::{BTC, CHF, EUR, GBP, JPY, USD},
     db::rate::RateInternalConversionError::MissingCacheUntil,
 };
 
@@ -118,3 +118,356 @@ fn uptodate_test() {
         }
     }
 }
+
+// All rates given at once are stored
+#[test]
+fn set_rates_test() {
+    let now = Utc::now();
+    let db = Db::new_in_memory().unwrap();
+
+    let rates = vec![
+        Rate::now(
+            &EUR,
+            &CHF,
+            1.1,
+            String::from("ecb"),
+            Some(Duration::hours(1)),
+        ),
+        Rate::now(
+            &EUR,
+            &JPY,
+            130.,
+            String::from("ecb"),
+            Some(Duration::hours(1)),
+        ),
+        Rate::now(
+            &EUR,
+            &BTC,
+            0.00003,
+            String::from("ecb"),
+            Some(Duration::hours(1)),
+        ),
+    ];
+    assert!(db.set_rates(&rates).is_ok());
+
+    for rate in &rates {
+        let retrieved = db
+            .get_uptodate_rates(rate.src(), rate.dst(), rate.provider(), now)
+            .unwrap();
+        assert_eq!(retrieved, vec![rate.clone()]);
+    }
+}
+
+// A rate that can’t be stored rolls back the whole batch
+#[test]
+fn set_rates_rollback_test() {
+    let now = Utc::now();
+    let db = Db::new_in_memory().unwrap();
+
+    let rates = vec![rate_cus_future(), rate_cun()];
+    assert!(db.set_rates(&rates).is_err());
+
+    let rate = &rates[0];
+    assert!(db
+        .get_uptodate_rates(rate.src(), rate.dst(), rate.provider(), now)
+        .unwrap()
+        .is_empty());
+}
+
+fn fresh_rate(src: &'static Currency, dst: &'static Currency, rate: f64) -> Rate<'static> {
+    Rate::now(
+        src,
+        dst,
+        rate,
+        String::from("ecb"),
+        Some(Duration::hours(1)),
+    )
+}
+
+// Rates are derived from the reverse pair or through pivots
+#[test]
+fn derived_rate_test() {
+    let now = Utc::now();
+    let db = Db::new_in_memory().unwrap();
+    let eur_usd = fresh_rate(&EUR, &USD, 1.25);
+    let eur_gbp = fresh_rate(&EUR, &GBP, 0.75);
+    let usd_jpy = fresh_rate(&USD, &JPY, 100.);
+    db.set_rates(&[eur_usd.clone(), eur_gbp.clone(), usd_jpy.clone()])
+        .unwrap();
+    let pivots = [&EUR, &USD];
+
+    // Inverse
+    let usd_eur = db
+        .get_uptodate_derived_rate(&USD, &EUR, "ecb", &pivots, now)
+        .unwrap()
+        .unwrap();
+    assert_eq!(usd_eur.rate(), 0.8);
+    assert_eq!(usd_eur.constituents(), std::slice::from_ref(&eur_usd));
+
+    // One pivot
+    let usd_gbp = db
+        .get_uptodate_derived_rate(&USD, &GBP, "ecb", &pivots, now)
+        .unwrap()
+        .unwrap();
+    assert!((usd_gbp.rate() - 0.6).abs() < 1e-9);
+    assert_eq!(usd_gbp.constituents(), &[eur_usd.clone(), eur_gbp.clone()]);
+
+    // Two pivots
+    let gbp_jpy = db
+        .get_uptodate_derived_rate(&GBP, &JPY, "ecb", &pivots, now)
+        .unwrap()
+        .unwrap();
+    assert!((gbp_jpy.rate() - 125. / 0.75).abs() < 1e-9);
+    assert_eq!(gbp_jpy.constituents(), &[eur_gbp, eur_usd, usd_jpy]);
+
+    // Unknown currency, or no pivot allowed
+    assert_eq!(
+        db.get_uptodate_derived_rate(&CHF, &JPY, "ecb", &pivots, now)
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        db.get_uptodate_derived_rate(&USD, &GBP, "ecb", &[], now)
+            .unwrap(),
+        None
+    );
+}
+
+// Derived rates are not built from outdated rates
+#[test]
+fn derived_rate_outdated_test() {
+    let now = Utc::now();
+    let db = Db::new_in_memory().unwrap();
+    let rate = rate_cus_past();
+    db.set_rate(&rate).unwrap();
+
+    assert_eq!(
+        db.get_uptodate_derived_rate(rate.dst(), rate.src(), rate.provider(), &[&EUR], now)
+            .unwrap(),
+        None
+    );
+}
+
+fn dated_rate(
+    src: &'static Currency,
+    dst: &'static Currency,
+    day: &str,
+    rate: f64,
+) -> Rate<'static> {
+    let day: NaiveDate = day.parse().unwrap();
+    Rate::new(
+        src,
+        dst,
+        Utc.from_utc_date(&day).and_hms(0, 0, 0),
+        rate,
+        String::from("ecb"),
+        None,
+    )
+}
+
+// The rate of the day, or of the closest prior day, is used
+#[test]
+fn rate_at_test() {
+    let db = Db::new_in_memory().unwrap();
+    let thursday = dated_rate(&EUR, &USD, "2026-03-12", 1.08);
+    let friday = dated_rate(&EUR, &USD, "2026-03-13", 1.09);
+    db.add_to_series(&[thursday.clone(), friday.clone()])
+        .unwrap();
+    let day = |d: &str| -> NaiveDate { d.parse().unwrap() };
+
+    let rate_at = |src, dst, d| db.get_rate_at(src, dst, "ecb", day(d), 4).unwrap();
+    assert_eq!(rate_at(&EUR, &USD, "2026-03-12"), Some(thursday));
+    // Week-end
+    assert_eq!(rate_at(&EUR, &USD, "2026-03-15"), Some(friday.clone()));
+    // Too far from a known rate
+    assert_eq!(rate_at(&EUR, &USD, "2026-03-20"), None);
+    assert_eq!(rate_at(&EUR, &USD, "2026-03-01"), None);
+    // Inverse
+    assert_eq!(rate_at(&USD, &EUR, "2026-03-14"), Some(friday.inverse()));
+    assert_eq!(rate_at(&USD, &USD, "2026-03-14").unwrap().rate(), 1.);
+}
+
+// Current rates are also kept in the series
+#[test]
+fn set_rate_series_test() {
+    let db = Db::new_in_memory().unwrap();
+    let rate = rate_cus_future();
+    db.set_rate(&rate).unwrap();
+
+    let today = Utc::today().naive_utc();
+    let rate_at = db
+        .get_rate_at(rate.src(), rate.dst(), rate.provider(), today, 0)
+        .unwrap()
+        .unwrap();
+    assert_eq!(rate_at.rate(), rate.rate());
+    assert_eq!(rate_at.date().naive_utc().date(), today);
+}
+
+#[cfg(feature = "cli")]
+fn history_conversion(src: &str, amount: f64, dst: &str, rate: f64) -> HistoryConversion {
+    HistoryConversion {
+        history_rowid: 0,
+        src: src.to_string(),
+        amount,
+        dst: dst.to_string(),
+        converted_amount: amount * rate,
+        rate,
+        provider: String::from("ecb"),
+        rate_date: Utc::now(),
+    }
+}
+
+// Conversions are stored along history entries, and removed with them
+#[cfg(feature = "cli")]
+#[test]
+fn history_conversions_test() {
+    let db = Db::new_in_memory().unwrap();
+    let conversions = vec![
+        history_conversion("EUR", 2., "USD", 1.25),
+        history_conversion("EUR", 2., "GBP", 0.75),
+    ];
+    let first = db.add_to_history("2 €", &conversions).unwrap();
+    let second = db.add_to_history("nothing", &[]).unwrap();
+    assert_ne!(first, second);
+
+    let stored = db.read_history_conversions(first).unwrap();
+    assert_eq!(stored.len(), 2);
+    for (stored, expected) in stored.iter().zip(&conversions) {
+        assert_eq!(stored.history_rowid, first);
+        assert_eq!(stored.dst, expected.dst);
+        assert_eq!(stored.converted_amount, expected.converted_amount);
+    }
+    assert!(db.read_history_conversions(second).unwrap().is_empty());
+
+    assert_eq!(db.remove_from_history(&Utc::now()).unwrap(), 2);
+    assert!(db.read_history_conversions(first).unwrap().is_empty());
+}
+
+// Entries without conversions are told apart from the ones added before conversions were stored
+#[cfg(feature = "cli")]
+#[test]
+fn history_conversions_stored_test() {
+    let db = Db::new_in_memory().unwrap();
+    db.conn
+        .execute(
+            "INSERT INTO history (datetime, content) VALUES (?1, '3 €')",
+            params![Utc::now()],
+        )
+        .unwrap();
+    db.add_to_history("nothing", &[]).unwrap();
+
+    let entries = db.read_from_history_max(-1).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(!entries[0].conversions_stored);
+    assert!(entries[1].conversions_stored);
+}
+
+#[test]
+fn api_requests_test() {
+    let db = Db::new_in_memory().unwrap();
+    let now = Utc.ymd(2026, 10, 18).and_hms(10, 0, 0);
+
+    assert_eq!(
+        db.count_requests("p", now - Duration::days(1)).unwrap(),
+        (0, 0, None)
+    );
+    db.record_request("p", now - Duration::days(100), true)
+        .unwrap();
+    db.record_request("p", now - Duration::hours(2), false)
+        .unwrap();
+    db.record_request("p", now, true).unwrap();
+    db.record_request("q", now, true).unwrap();
+
+    assert_eq!(
+        db.count_requests("p", now - Duration::days(1)).unwrap(),
+        (2, 1, Some(now - Duration::hours(2)))
+    );
+    // Old requests are not kept
+    assert_eq!(
+        db.count_requests("p", now - Duration::days(365)).unwrap().0,
+        2
+    );
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn alerts_test() {
+    let db = Db::new_in_memory().unwrap();
+    let alert = Alert {
+        rowid: 0,
+        src: String::from("GBP"),
+        dst: String::from("EUR"),
+        direction: alert::Direction::Below,
+        threshold: 3.,
+        days: Some(7),
+        triggered: true,
+    };
+    let rowid = db.add_alert(&alert).unwrap();
+
+    // Alerts start untriggered
+    assert_eq!(
+        db.read_alerts().unwrap(),
+        vec![Alert {
+            rowid,
+            triggered: false,
+            ..alert
+        }]
+    );
+    assert_eq!(db.remove_alert(rowid).unwrap(), 1);
+    assert_eq!(db.remove_alert(rowid).unwrap(), 0);
+    assert!(db.read_alerts().unwrap().is_empty());
+}
+
+#[test]
+fn get_latest_rate_test() {
+    let db = Db::new_in_memory().unwrap();
+    assert_eq!(db.get_latest_rate(&EUR, &USD).unwrap(), None);
+
+    let now = Utc::now();
+    let old = Rate::new(
+        &EUR,
+        &USD,
+        now - Duration::days(3),
+        1.1,
+        "p".into(),
+        Some(now),
+    );
+    let new = Rate::new(&USD, &EUR, now, 0.8, "q".into(), Some(now));
+    db.set_rates(&[old, new.clone()]).unwrap();
+
+    // Outdated rates and rates of the reverse pair count
+    assert_eq!(db.get_latest_rate(&EUR, &USD).unwrap(), Some(new));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn get_rate_series_test() {
+    let db = Db::new_in_memory().unwrap();
+    let day = |d| Utc.ymd(2026, 10, d).and_hms(12, 0, 0);
+    let rate = |src, dst, d, rate, provider: &str| {
+        Rate::new(src, dst, day(d), rate, provider.into(), Some(day(d)))
+    };
+    db.add_to_series(&[
+        rate(&EUR, &USD, 1, 1.1, "b"),
+        rate(&EUR, &USD, 2, 1.2, "b"),
+        rate(&EUR, &USD, 2, 1.3, "a"),
+        rate(&USD, &EUR, 2, 0.5, "a"),
+        rate(&USD, &EUR, 3, 0.8, "a"),
+        rate(&EUR, &USD, 9, 1.4, "a"),
+    ])
+    .unwrap();
+
+    let series: Vec<f64> = db
+        .get_rate_series(
+            &EUR,
+            &USD,
+            NaiveDate::from_ymd(2026, 10, 1),
+            NaiveDate::from_ymd(2026, 10, 5),
+        )
+        .unwrap()
+        .iter()
+        .map(|r| r.rate())
+        .collect();
+    // The pair is preferred over the reverse one, then providers by name
+    assert_eq!(series, vec![1.1, 1.3, 1.25]);
+}
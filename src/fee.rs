@@ -0,0 +1,142 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Fees charged on conversions by banks and card issuers, on top of the mid-market rate
+
+use crate::config::FeeProfile;
+use crate::currency::Currency;
+use crate::rate::Rate;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::{EUR, GBP, USD};
+
+    fn profile(name: &str, percent: f64, pairs: &[&str], providers: &[&str]) -> FeeProfile {
+        FeeProfile {
+            name: name.to_string(),
+            percent,
+            fixed: 0.,
+            fixed_currency: None,
+            pairs: pairs.iter().map(|p| p.to_string()).collect(),
+            providers: providers.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn select_profile_test() {
+        let profiles = vec![
+            profile("card", 1., &["EUR/GBP"], &[]),
+            profile("card", 2.5, &[], &["currencyconverterapi.com"]),
+            profile("wire", 0.5, &[], &[]),
+        ];
+        let rate = |src, dst, value, provider: &str| {
+            Rate::now(src, dst, value, provider.to_string(), None)
+        };
+
+        let select = |name, rate: &Rate| select_profile(&profiles, name, rate).map(|p| p.percent);
+        assert_eq!(
+            select("card", &rate(&EUR, &GBP, 1., "currencyconverterapi.com")),
+            Some(1.)
+        );
+        assert_eq!(
+            select("card", &rate(&EUR, &USD, 1., "currencyconverterapi.com")),
+            Some(2.5)
+        );
+        assert_eq!(select("card", &rate(&EUR, &USD, 1., "ecb.europa.eu")), None);
+        assert_eq!(
+            select("wire", &rate(&EUR, &USD, 1., "ecb.europa.eu")),
+            Some(0.5)
+        );
+        assert_eq!(select("cash", &rate(&EUR, &USD, 1., "ecb.europa.eu")), None);
+
+        // Derived rates match the providers of the rates they are derived from
+        let derived = rate(&USD, &EUR, 0.9, "currencyconverterapi.com").inverse();
+        assert_eq!(select("card", &derived), Some(2.5));
+    }
+
+    #[test]
+    fn fee_test() {
+        let fee = Fee::new("wire", 0.5, 15.);
+        assert_eq!(fee.apply(1015., 2.), 1990.);
+        assert_eq!(Fee::new("free", 0., 0.).apply(10., 1.5), 15.);
+    }
+}
+
+/// Fee charged on a conversion, as a percentage of the amount and a fixed part
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fee {
+    /// Name of the profile the fee comes from
+    name: String,
+    /// Markup over the mid-market rate, in percent
+    percent: f64,
+    /// Fixed part, in the currency of the amount converted
+    fixed: f64,
+}
+
+impl Fee {
+    pub fn new(name: &str, percent: f64, fixed: f64) -> Self {
+        Fee {
+            name: name.to_string(),
+            percent,
+            fixed,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Amount received when converting amount with a rate, once the fee is paid
+    pub fn apply(&self, amount: f64, rate: f64) -> f64 {
+        (amount - self.fixed) * rate * (1. - self.percent / 100.)
+    }
+}
+
+/// First profile with the given name that applies to a rate, by pair and provider. Profiles
+/// without pairs or providers apply to all of them
+pub fn select_profile<'p>(
+    profiles: &'p [FeeProfile],
+    name: &str,
+    rate: &Rate,
+) -> Option<&'p FeeProfile> {
+    let pair = format!("{}/{}", rate.src(), rate.dst());
+    let providers = rate.providers();
+    profiles.iter().find(|profile| {
+        profile.name == name
+            && (profile.pairs.is_empty() || profile.pairs.iter().any(|p| p == &pair))
+            && (profile.providers.is_empty()
+                || providers
+                    .iter()
+                    .all(|provider| profile.providers.iter().any(|p| p == provider)))
+    })
+}
+
+/// Whether a profile has the given name
+pub fn profile_exists(profiles: &[FeeProfile], name: &str) -> bool {
+    profiles.iter().any(|profile| profile.name == name)
+}
+
+/// Currency of the fixed part of a profile, the currency converted from if not set. None if the
+/// currency is unknown
+pub fn fixed_currency<'c>(profile: &FeeProfile, src: &'c Currency) -> Option<&'c Currency> {
+    match &profile.fixed_currency {
+        Some(iso) => crate::currency::existing_from_iso(iso),
+        None => Some(src),
+    }
+}
@@ -22,10 +22,14 @@ use anyhow::Result;
 use chrono::{Duration, Utc};
 use term_table::{row::Row, Table};
 
-use crate::convert::convert_string;
-use crate::db::history::History;
+use crate::api::RateError;
+use crate::cli::HistoryCommands;
+use crate::convert::{self, convert_string, current_rates, prefetch_current_rates};
+use crate::currency::{self, Currency, PriceTag};
+use crate::db::history::{History, HistoryConversion};
+use crate::price_in_text::Engine;
 use crate::tools::yes_or_no;
-use crate::{HistoryCommands, MainContext};
+use crate::MainContext;
 
 // Default time in days before history entries are expireed
 pub(crate) static EXPIRE_DELAY: &'static str = "30";
@@ -38,9 +42,10 @@ pub(crate) fn run(ctxt: MainContext, subcommand: HistoryCommands) -> Result<()>
         }
         HistoryCommands::List {
             no_convert,
+            current,
             max_entries,
         } => {
-            list(&ctxt, max_entries as i32, no_convert)?;
+            list(&ctxt, max_entries as i32, no_convert, current)?;
             auto_expire(&ctxt)?
         }
     }
@@ -48,29 +53,136 @@ pub(crate) fn run(ctxt: MainContext, subcommand: HistoryCommands) -> Result<()>
     Ok(())
 }
 
-fn list(ctxt: &MainContext, limit: i32, no_convert: bool) -> Result<()> {
+fn list(ctxt: &MainContext, limit: i32, no_convert: bool, current: bool) -> Result<()> {
     // TODO
     // - delete an entry
     let rows = ctxt.db.read_from_history_max(limit)?;
 
-    return print(ctxt, &rows, no_convert);
+    return print(ctxt, &rows, no_convert, current);
 }
 
-fn print(ctxt: &MainContext, histories: &[History], no_convert: bool) -> Result<()> {
+/// Relative change from then to now, in percent. None if then is zero
+fn delta_percent(then: f64, now: f64) -> Option<f64> {
+    if then == 0. {
+        None
+    } else {
+        Some((now - then) / then.abs() * 100.)
+    }
+}
+
+/// Note in place of conversions that could not be performed because a provider is out of its
+/// request budget, so that listing goes on. Other errors are returned
+fn quota_note(err: anyhow::Error) -> Result<String> {
+    match err.downcast_ref::<RateError>() {
+        Some(quota_err @ RateError::QuotaExceeded { .. }) => {
+            Ok(format!("not converted: {}", quota_err))
+        }
+        _ => Err(err),
+    }
+}
+
+/// Rate, provider and date of the rate of a stored conversion, with a given number of decimals for
+/// the rate
+fn stored_rate_string(conversion: &HistoryConversion, decimals: usize) -> String {
+    format!(
+        "(@ {:.*}, {}, {})",
+        decimals,
+        conversion.rate,
+        conversion.provider,
+        conversion.rate_date.format("%F")
+    )
+}
+
+/// Format a conversion stored in the history with its rate, followed by the conversion with
+/// today’s rate if current is true
+fn stored_conversion_string(
+    ctxt: &MainContext,
+    conversion: &HistoryConversion,
+    current: bool,
+) -> Result<String> {
+    let (src, dst) = match (
+        currency::existing_from_iso(&conversion.src),
+        currency::existing_from_iso(&conversion.dst),
+    ) {
+        (Some(src), Some(dst)) => (src, dst),
+        // Currency removed since the conversion was stored
+        _ => {
+            return Ok(format!(
+                "{} {:.2} ➜ {} {:.2} {}",
+                conversion.src,
+                conversion.amount,
+                conversion.dst,
+                conversion.converted_amount,
+                stored_rate_string(conversion, 3)
+            ))
+        }
+    };
+    let price_tag = PriceTag::new(src, conversion.amount);
+    let mut string = format!(
+        "{} ➜ {} {}",
+        price_tag,
+        PriceTag::new(dst, conversion.converted_amount),
+        stored_rate_string(conversion, dst.decimals().max(3))
+    );
+
+    if current {
+        let rate = match current_rates(ctxt, src, &[dst]) {
+            Ok(mut rates) => rates.pop().flatten(),
+            Err(err) => {
+                string.push_str(&format!(" | {}", quota_note(err)?));
+                return Ok(string);
+            }
+        };
+        match rate.and_then(|rate| price_tag.convert(&rate, None).ok()) {
+            Some(quote) => {
+                let now = quote.mid_market();
+                string.push_str(&format!(" | now {}", now));
+                if let Some(delta) = delta_percent(conversion.converted_amount, now.amount()) {
+                    string.push_str(&format!(" ({:+.2}%)", delta));
+                }
+            }
+            None => string.push_str(" | no current rate"),
+        }
+    }
+
+    Ok(string)
+}
+
+fn print(ctxt: &MainContext, histories: &[History], no_convert: bool, current: bool) -> Result<()> {
     if histories.len() == 0 {
         println!("History is empty for now");
         return Ok(());
     }
 
+    let stored = if no_convert {
+        Vec::new()
+    } else {
+        stored_conversions(ctxt, histories)?
+    };
+    if !no_convert {
+        prefetch_current_rates(ctxt, &current_pairs(ctxt, histories, &stored, current));
+    }
+
     let mut table = Table::new();
-    for history_entry in histories {
+    for (i, history_entry) in histories.iter().enumerate() {
         let mut v = Vec::with_capacity(4);
         v.push(format!("{}", history_entry.rowid));
         v.push(format!("{}", history_entry.datetime.to_rfc2822()));
         v.push(format!("{}", history_entry.content));
 
-        if !no_convert {
-            v.push(convert_string(&ctxt, &history_entry.content, Some(3))?);
+        match stored.get(i) {
+            None => {}
+            // Entry added before conversions were stored, use today’s rates
+            Some(None) => v.push(
+                convert_string(ctxt, &history_entry.content, Some(3), None).or_else(quota_note)?,
+            ),
+            Some(Some(stored)) => {
+                let conversions = stored
+                    .iter()
+                    .map(|c| stored_conversion_string(ctxt, c, current))
+                    .collect::<Result<Vec<_>>>()?;
+                v.push(conversions.join("\n"));
+            }
         }
 
         table.add_row(Row::new(v))
@@ -81,6 +193,53 @@ fn print(ctxt: &MainContext, histories: &[History], no_convert: bool) -> Result<
     Ok(())
 }
 
+/// Conversions stored with each entry, None for entries added before conversions were stored
+fn stored_conversions(
+    ctxt: &MainContext,
+    histories: &[History],
+) -> Result<Vec<Option<Vec<HistoryConversion>>>> {
+    histories
+        .iter()
+        .map(|history_entry| {
+            if history_entry.conversions_stored {
+                Ok(Some(ctxt.db.read_history_conversions(history_entry.rowid)?))
+            } else {
+                Ok(None)
+            }
+        })
+        .collect()
+}
+
+/// Pairs of the current rates needed to print the entries, to retrieve them all beforehand
+fn current_pairs<'c>(
+    ctxt: &MainContext<'c>,
+    histories: &[History],
+    stored: &[Option<Vec<HistoryConversion>>],
+    current: bool,
+) -> Vec<(&'c Currency, &'c Currency)> {
+    let engine = Engine::new();
+    let mut pairs = Vec::new();
+    for (history_entry, stored) in histories.iter().zip(stored) {
+        match stored {
+            None => {
+                let price_tags = engine.top_price_tags(3, &history_entry.content);
+                let srcs = price_tags.iter().map(PriceTag::currency);
+                pairs.extend(convert::pairs(srcs, ctxt.destination_currencies()));
+            }
+            Some(stored) if current => {
+                pairs.extend(stored.iter().filter_map(|c| {
+                    Some((
+                        currency::existing_from_iso(&c.src)?,
+                        currency::existing_from_iso(&c.dst)?,
+                    ))
+                }));
+            }
+            Some(_) => {}
+        }
+    }
+    pairs
+}
+
 fn expire(ctxt: &MainContext, expire_delay_days: usize, silent: bool) -> Result<()> {
     let now = Utc::now();
     let remove_before = now
@@ -89,7 +248,7 @@ fn expire(ctxt: &MainContext, expire_delay_days: usize, silent: bool) -> Result<
 
     if !silent {
         let history = ctxt.db.read_from_history_before(&remove_before)?;
-        print(ctxt, &history, true)?;
+        print(ctxt, &history, true, false)?;
 
         if history.len() == 0 {
             println!("Nothing to delete");
@@ -118,3 +277,49 @@ fn expire(ctxt: &MainContext, expire_delay_days: usize, silent: bool) -> Result<
 fn auto_expire(ctxt: &MainContext) -> Result<()> {
     expire(ctxt, EXPIRE_DELAY.parse().unwrap(), true)
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn delta_percent_test() {
+        assert_eq!(delta_percent(100., 110.), Some(10.));
+        assert_eq!(delta_percent(-100., -110.), Some(-10.));
+        assert_eq!(delta_percent(50., 25.), Some(-50.));
+        assert_eq!(delta_percent(0., 25.), None);
+    }
+
+    #[test]
+    fn stored_conversion_string_test() {
+        let ctxt = MainContext::new_in_memory(Config::default(), Vec::new()).unwrap();
+        let mut conversion = HistoryConversion {
+            history_rowid: 1,
+            src: String::from("EUR"),
+            amount: 12.,
+            dst: String::from("USD"),
+            converted_amount: 13.0512,
+            rate: 1.0876,
+            provider: String::from("ECB"),
+            rate_date: Utc.ymd(2026, 3, 13).and_hms(16, 0, 0),
+        };
+        assert_eq!(
+            stored_conversion_string(&ctxt, &conversion, false).unwrap(),
+            format!(
+                "{} ➜ {} (@ 1.088, ECB, 2026-03-13)",
+                PriceTag::new(&currency::EUR, 12.),
+                PriceTag::new(&currency::USD, 13.0512)
+            )
+        );
+
+        // Currency removed since
+        conversion.dst = String::from("XYZ");
+        assert_eq!(
+            stored_conversion_string(&ctxt, &conversion, false).unwrap(),
+            "EUR 12.00 ➜ XYZ 13.05 (@ 1.088, ECB, 2026-03-13)"
+        );
+    }
+}
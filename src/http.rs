@@ -0,0 +1,273 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! HTTP client shared by every request to the APIs during a run
+
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use ureq::{Agent, AgentBuilder, Proxy, Request, Response};
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config;
+
+/// Longest wait before retrying a request. Servers asking for a longer wait are not retried
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Agent configured with timeouts, proxy, certificates and user agent, along with the retry
+/// policy
+#[derive(Clone)]
+pub struct Client {
+    agent: Agent,
+    retries: u32,
+    backoff: Duration,
+    attempts: Option<Arc<AtomicU32>>,
+}
+
+impl Client {
+    /// Build the client from the configuration
+    pub fn new(cfg: &config::Http) -> Result<Self> {
+        let mut builder = AgentBuilder::new().user_agent(&cfg.user_agent);
+        if cfg.connect_timeout > 0 {
+            builder = builder.timeout_connect(Duration::from_secs(cfg.connect_timeout));
+        }
+        if cfg.read_timeout > 0 {
+            builder = builder.timeout_read(Duration::from_secs(cfg.read_timeout));
+        }
+        if let Some(proxy) = &cfg.proxy {
+            // The proxy url may contain credentials, it is not logged
+            debug!("Using a proxy");
+            let proxy = Proxy::new(proxy).context("Invalid proxy url")?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &cfg.ca_bundle {
+            let tls_config = tls_config(path)
+                .with_context(|| format!("Failed to load CA bundle {}", path.display()))?;
+            builder = builder.tls_config(Arc::new(tls_config));
+        }
+
+        Ok(Client {
+            agent: builder.build(),
+            retries: cfg.retries,
+            backoff: Duration::from_millis(cfg.retry_backoff),
+            attempts: None,
+        })
+    }
+
+    /// Same client, adding to attempts every request it sends, retries included
+    pub fn counting(&self, attempts: Arc<AtomicU32>) -> Self {
+        Client {
+            attempts: Some(attempts),
+            ..self.clone()
+        }
+    }
+
+    /// Agent to build requests with
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    /// Send a request, retrying with exponential backoff on server errors and rate limits
+    // Same error as Request::call
+    #[allow(clippy::result_large_err)]
+    pub fn call(&self, request: Request) -> Result<Response, ureq::Error> {
+        let mut attempt = 0;
+        loop {
+            if let Some(attempts) = &self.attempts {
+                attempts.fetch_add(1, Ordering::Relaxed);
+            }
+            let result = request.clone().call();
+            let retry = match &result {
+                Err(ureq::Error::Status(status, res))
+                    if attempt < self.retries && (*status == 429 || *status >= 500) =>
+                {
+                    self.delay(attempt, res).map(|delay| (*status, delay))
+                }
+                _ => None,
+            };
+            match retry {
+                Some((status, delay)) => {
+                    // The query may contain an API key
+                    let url = request.url();
+                    let url = url.split('?').next().unwrap_or(url);
+                    warn!(
+                        "Request to {} failed with status {}, retrying in {:.1}s",
+                        url,
+                        status,
+                        delay.as_secs_f64()
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return result,
+            }
+        }
+    }
+
+    /// Wait before the next attempt, None if it is too long to be worth waiting
+    fn delay(&self, attempt: u32, res: &Response) -> Option<Duration> {
+        let delay = retry_after(res)
+            .unwrap_or_else(|| self.backoff.saturating_mul(2u32.saturating_pow(attempt)));
+        if delay > MAX_RETRY_DELAY {
+            None
+        } else {
+            Some(delay)
+        }
+    }
+}
+
+/// Delay given by the Retry-After header of a response, if it is a number of seconds
+pub fn retry_after(res: &Response) -> Option<Duration> {
+    res.header("Retry-After")
+        .and_then(|s| s.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// TLS configuration trusting the usual certificate authorities and those of a PEM file
+fn tls_config(path: &Path) -> Result<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    let (added, ignored) = roots.add_parsable_certificates(&certs);
+    debug!("{} certificate(s) added, {} ignored", added, ignored);
+    if added == 0 {
+        bail!("No valid certificate found");
+    }
+
+    Ok(rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::{MockServer, Route};
+
+    fn client(cfg: config::Http) -> Client {
+        Client::new(&config::Http {
+            retry_backoff: 1,
+            ..cfg
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn retry_test() {
+        let server = MockServer::start(vec![
+            Route::new("/rates", 503, "Unavailable").times(1),
+            Route::new("/rates", 429, "Slow down").times(1),
+            Route::ok("/rates", "{}"),
+        ]);
+        let client = client(config::Http::default());
+
+        let res = client
+            .call(client.agent().get(&format!("{}/rates", server.url())))
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn retries_exhausted_test() {
+        let server = MockServer::start(vec![Route::new("/rates", 500, "Oops")]);
+        let client = client(config::Http {
+            retries: 1,
+            ..config::Http::default()
+        });
+
+        let err = client
+            .call(client.agent().get(&format!("{}/rates", server.url())))
+            .unwrap_err();
+        assert!(matches!(err, ureq::Error::Status(500, _)), "{:?}", err);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn no_retry_test() {
+        let server = MockServer::start(vec![
+            Route::new("/key", 401, "Invalid key"),
+            // Too long to wait for
+            Route::new("/quota", 429, "Quota exceeded").header("Retry-After", "3600"),
+        ]);
+        let client = client(config::Http::default());
+
+        for path in &["/key", "/quota"] {
+            assert!(client
+                .call(client.agent().get(&format!("{}{}", server.url(), path)))
+                .is_err());
+        }
+        assert_eq!(server.requests(), vec!["/key", "/quota"]);
+    }
+
+    #[test]
+    fn user_agent_test() {
+        let server = MockServer::start(vec![Route::ok("/", "")]);
+        let client = client(config::Http {
+            user_agent: String::from("corp-agent/1.0"),
+            ..config::Http::default()
+        });
+
+        client.call(client.agent().get(&server.url())).unwrap();
+        let headers = server.headers();
+        assert!(headers[0].contains(&(String::from("user-agent"), String::from("corp-agent/1.0"))));
+    }
+
+    #[test]
+    fn proxy_test() {
+        let server = MockServer::start(vec![]);
+        let client = client(config::Http {
+            proxy: Some(server.url()),
+            ..config::Http::default()
+        });
+
+        // The mock refuses to tunnel, but the request went through it
+        assert!(client
+            .call(client.agent().get("http://rates.example.com/latest"))
+            .is_err());
+        assert_eq!(server.requests(), vec!["rates.example.com:80"]);
+    }
+
+    #[test]
+    fn invalid_settings_test() {
+        assert!(Client::new(&config::Http {
+            proxy: Some(String::from("gopher://proxy")),
+            ..config::Http::default()
+        })
+        .is_err());
+        assert!(Client::new(&config::Http {
+            ca_bundle: Some("/nonexistent/ca.pem".into()),
+            ..config::Http::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,175 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Find price tags in text and convert them to other currencies
+//!
+//! The library is made of:
+//! - [`currency`]: the currencies known and price tags,
+//! - [`price_in_text`]: the [`Engine`] finding price tags in plain text, and [`markup`] to get
+//!   plain text out of HTML or Markdown,
+//! - [`api`]: the rate sources, each implementing [`RateApi`] and reached with a [`Client`],
+//! - [`store`]: the stores caching rates, each implementing [`RateStore`], in memory, in an
+//!   SQLite file or read from a snapshot,
+//! - [`convert`]: conversions of the price tags of a text, with rates from the store or from the
+//!   rate sources, in a [`MainContext`].
+//!
+//! A context needs neither the configuration file nor the filesystem:
+//!
+//! ```
+//! use chrono::Duration;
+//! use sesters::config::Config;
+//! use sesters::currency::{EUR, USD};
+//! use sesters::rate::Rate;
+//! use sesters::MainContext;
+//!
+//! let mut ctxt = MainContext::new_in_memory(Config::default(), vec![&USD])?;
+//! // Use only the rates stored, instead of retrieving them from rate sources
+//! ctxt.set_offline(true);
+//! let rate = Rate::now(&EUR, &USD, 1.5, String::from("example"), Some(Duration::hours(1)));
+//! ctxt.store().put_rates(&[rate])?;
+//!
+//! let conversions = sesters::convert::convert(&ctxt, "A coffee for 2 €", None, None)?;
+//! assert_eq!(conversions[0][0].converted().amount(), 3.);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::Result;
+
+pub mod api;
+pub mod config;
+pub mod convert;
+pub mod currency;
+pub mod fee;
+pub mod markup;
+pub mod price_format;
+pub mod price_in_text;
+pub mod rate;
+pub mod store;
+
+mod alert;
+#[cfg(feature = "cli")]
+mod calc;
+#[cfg(feature = "cli")]
+mod cli;
+pub(crate) mod db;
+#[cfg(feature = "cli")]
+mod history;
+pub(crate) mod http;
+mod quota;
+#[cfg(feature = "cli")]
+mod repl;
+#[cfg(feature = "cli")]
+mod rpc;
+#[cfg(feature = "cli")]
+mod serve;
+#[cfg(feature = "cli")]
+mod stats;
+#[cfg(feature = "cli")]
+mod tabular;
+#[cfg(feature = "cli")]
+mod tools;
+
+/// Entry point and exit code of the sesters command, for the binary only
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub use crate::cli::{exit_code, run};
+
+pub use crate::api::{RateApi, RateError};
+pub use crate::config::Config;
+pub use crate::convert::Conversion;
+pub use crate::currency::{Currency, PriceTag};
+pub use crate::http::Client;
+pub use crate::price_in_text::Engine;
+pub use crate::rate::Rate;
+pub use crate::store::RateStore;
+
+use crate::db::Db;
+
+/// Context of conversions: configuration, store of the rates, HTTP client for the rate sources
+/// and target currencies
+pub struct MainContext<'mc> {
+    db: Db,
+    /// Cache of the current rates, the database if not set
+    store: Option<Box<dyn RateStore>>,
+    destination_currencies: Vec<&'mc Currency>,
+    cfg: Config,
+    /// Shared by all requests of the run
+    client: Client,
+    /// Use only the rates stored, without retrieving any online
+    offline: bool,
+}
+
+impl<'mc> MainContext<'mc> {
+    /// Context with the database file and the rate store of the configuration
+    pub fn new(cfg: Config, destination_currencies: Vec<&'mc Currency>) -> Result<Self> {
+        let db = Db::new(&cfg)?;
+        Self::with_db(cfg, db, destination_currencies)
+    }
+
+    /// Context with an in memory database, lost when the context is dropped
+    pub fn new_in_memory(cfg: Config, destination_currencies: Vec<&'mc Currency>) -> Result<Self> {
+        Self::with_db(cfg, Db::new_in_memory()?, destination_currencies)
+    }
+
+    /// Context with a database opened beforehand, and the rate store of the configuration
+    fn with_db(cfg: Config, db: Db, destination_currencies: Vec<&'mc Currency>) -> Result<Self> {
+        Ok(MainContext {
+            client: Client::new(&cfg.http)?,
+            store: store::from_config(&cfg.rate_store)?,
+            cfg,
+            db,
+            destination_currencies,
+            offline: false,
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Cache of the current rates
+    pub fn store(&self) -> &dyn RateStore {
+        match &self.store {
+            Some(store) => store.as_ref(),
+            None => &self.db,
+        }
+    }
+
+    /// Cache the current rates in store instead of the one of the configuration
+    pub fn set_store(&mut self, store: Box<dyn RateStore>) {
+        self.store = Some(store);
+    }
+
+    /// Currencies price tags are converted to
+    pub fn destination_currencies(&self) -> &[&'mc Currency] {
+        &self.destination_currencies
+    }
+
+    pub fn set_destination_currencies(&mut self, currencies: Vec<&'mc Currency>) {
+        self.destination_currencies = currencies;
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Use only the rates stored, without retrieving any from the rate sources
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+}
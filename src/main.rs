@@ -16,163 +16,19 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use anyhow::Result;
-use clap::{crate_authors, crate_description, crate_version, ArgGroup, Parser, Subcommand};
-use log::{error, info};
-
-mod api;
-mod config;
-mod convert;
-pub mod currency;
-mod db;
-mod history;
-mod price_format;
-pub mod price_in_text;
-mod rate;
-mod tools;
-
-use crate::config::Config;
-use crate::currency::Currency;
-use crate::db::Db;
-
-/// Main context to pass what is initiliazed in this module and what is parsed
-/// in global tags
-pub struct MainContext<'mc> {
-    db: Db,
-    destination_currencies: Vec<&'mc Currency>,
-    cfg: Config,
-}
-
-impl<'mc> MainContext<'mc> {
-    pub(crate) fn new(cfg: Config, destination_currencies: Vec<&'mc Currency>) -> Result<Self> {
-        let db = Db::new(&cfg).unwrap();
-
-        Ok(MainContext {
-            cfg,
-            db,
-            destination_currencies,
-        })
-    }
-}
-
-#[derive(Parser, Debug)]
-#[clap(name = "sesters",
-  version = crate_version!(),
-  author = crate_authors!(),
-  about = concat!(crate_description!(),
-    "\n",
-    "https://cj.rs/sesters/"),
-  long_about = None)]
-#[clap(dont_collapse_args_in_usage = true)]
-pub(crate) struct Cli {
-    #[clap(subcommand)]
-    command: Commands,
-
-    /// Target currency by ISO symbol, uses defaults from the configuration file if not set
-    #[clap(short = 't', value_name = "CURRENCY")]
-    to: Vec<String>,
-    // TODO Add flag for verbosity
-}
-
-#[derive(Subcommand, Debug)]
-pub(crate) enum Commands {
-    /// Perform currency conversion to your preferred currency,
-    /// from a price tag found in plain text
-    #[clap(infer_subcommands = true)]
-    Convert {
-        /// Read text containing price tag from stdin
-        #[clap(long = "stdin")]
-        stdin: bool,
-
-        /// Find at most n price tag in the text, i.e. 3
-        #[clap(short = 'n')]
-        findn: Option<usize>,
-
-        /// Plain text to extract a price tag from. If not set, plain text will be read from stdin
-        plain_text: Vec<String>,
-    },
-
-    /// Access and manage the history of price tags extracted
-    #[clap(infer_subcommands = true)]
-    History {
-        #[clap(subcommand)]
-        command: HistoryCommands,
-    },
-}
-
-#[derive(Subcommand, Debug)]
-pub(crate) enum HistoryCommands {
-    /// List entries in the history
-    #[clap(infer_subcommands = true)]
-    List {
-        /// Don’t perform conversions of the history content
-        #[clap(short = 'n', long = "noconvert")]
-        no_convert: bool,
-        /// Show at most <N> entries
-        #[clap(short = 'm', long = "max", default_value = "50")]
-        max_entries: usize,
-    },
-
-    /// Removes older entries from history
-    #[clap(infer_subcommands = true)]
-    #[clap(group(ArgGroup::new("expire").args(&["all", "days"])))]
-    Expire {
-        /// Remove without printing or confirming
-        #[clap(short = 'y')]
-        yes: bool,
-
-        /// Removes all entries from history
-        #[clap(long)]
-        all: bool,
-
-        /// Delete all entries older than the given number of days
-        #[clap(default_value = crate::history::EXPIRE_DELAY, long)]
-        days: usize,
-    },
-}
-
-fn from_args() -> Result<()> {
-    Config::init()?;
-    let cfg = Config::new()?;
-
-    // Argument parsing
-    let args = Cli::parse();
-
-    let txt_destination_currencies = if args.to.len() == 0 {
-        // Use configuration currency if none are specified
-        cfg.currencies()
-    } else {
-        &args.to
-    };
-    let destination_currencies: Vec<&Currency> = txt_destination_currencies
-        .iter()
-        .filter_map(|iso_name| {
-            currency::existing_from_iso(&iso_name).or_else(|| {
-                error!("Invalid currency iso symbol '{}', ignored", iso_name);
-                None
-            })
-        })
-        .collect();
-
-    let ctxt = MainContext::new(cfg, destination_currencies)?;
-
-    match args.command {
-        Commands::Convert {
-            stdin,
-            findn,
-            plain_text,
-        } => convert::run(ctxt, stdin, findn, plain_text)?,
-        Commands::History { command } => history::run(ctxt, command)?,
-    }
-
-    info!("Exiting");
-    Ok(())
-}
+use log::{debug, info};
 
 fn main() {
     log::set_max_level(log::LevelFilter::Info);
-    env_logger::init();
+    env_logger::Builder::from_default_env()
+        // Debug logs of ureq contain full urls, with API keys
+        .filter_module("ureq", log::LevelFilter::Info)
+        .init();
     info!("Starting up");
 
-    from_args().unwrap();
+    if let Err(err) = sesters::run() {
+        debug!("{:?}", err);
+        eprintln!("Error: {:#}", err);
+        std::process::exit(sesters::exit_code(&err));
+    }
 }
@@ -0,0 +1,472 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Plain text of HTML and Markdown documents, where price tags are often split by markup like
+//! in `<span>19<sup>99</sup></span>&nbsp;&euro;`. The plain text keeps track of where each of its
+//! parts comes from in the document
+
+use std::ops::Range;
+
+/// Format of a text to find price tags in
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Markup {
+    Text,
+    Html,
+    Markdown,
+}
+
+/// Elements that separate their content from what is around, like paragraphs
+const BLOCK_ELEMENTS: [&str; 27] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Table cells and rows, separated like blocks to keep numbers of adjacent cells apart
+const CELL_ELEMENTS: [&str; 4] = ["td", "th", "tr", "option"];
+
+/// Elements whose content is not text
+const SKIPPED_ELEMENTS: [&str; 3] = ["script", "style", "template"];
+
+/// Plain text extracted from a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extracted {
+    text: String,
+    /// Part of the document each byte of text comes from
+    sources: Vec<Range<usize>>,
+    /// Length of the document
+    len: usize,
+}
+
+impl Extracted {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Range of the document a range of the text comes from
+    pub fn original(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.sources.get(range.start).map_or(self.len, |s| s.start);
+        if range.end <= range.start {
+            return start..start;
+        }
+        let end = self.sources.get(range.end - 1).map_or(self.len, |s| s.end);
+        start..end
+    }
+}
+
+/// Plain text of a document in the markup
+pub fn extract(markup: Markup, document: &str) -> Extracted {
+    let mut extractor = Extractor::new(document);
+    match markup {
+        Markup::Text => {
+            return Extracted {
+                text: document.to_string(),
+                sources: document
+                    .char_indices()
+                    .flat_map(|(i, c)| (0..c.len_utf8()).map(move |_| i..i + c.len_utf8()))
+                    .collect(),
+                len: document.len(),
+            }
+        }
+        Markup::Html => extractor.html(),
+        Markup::Markdown => extractor.markdown(),
+    }
+    extractor.finish()
+}
+
+struct Extractor<'d> {
+    document: &'d str,
+    text: String,
+    sources: Vec<Range<usize>>,
+}
+
+impl<'d> Extractor<'d> {
+    fn new(document: &'d str) -> Self {
+        Extractor {
+            document,
+            text: String::with_capacity(document.len()),
+            sources: Vec::with_capacity(document.len()),
+        }
+    }
+
+    /// Extracted text, without trailing separators
+    fn finish(mut self) -> Extracted {
+        while self.text.ends_with(&[' ', '\n'][..]) {
+            self.text.pop();
+            self.sources.pop();
+        }
+        Extracted {
+            text: self.text,
+            sources: self.sources,
+            len: self.document.len(),
+        }
+    }
+
+    fn push(&mut self, c: char, source: Range<usize>) {
+        for _ in 0..c.len_utf8() {
+            self.sources.push(source.clone());
+        }
+        self.text.push(c);
+    }
+
+    /// Add a space, unless the text is empty or already ends with a separator
+    fn push_space(&mut self, source: Range<usize>) {
+        if !self.text.is_empty() && !self.text.ends_with(&[' ', '\n'][..]) {
+            self.push(' ', source);
+        }
+    }
+
+    /// Add a line break, replacing a trailing space
+    fn push_break(&mut self, source: Range<usize>) {
+        if self.text.ends_with(' ') {
+            self.text.pop();
+            self.sources.pop();
+        }
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.push('\n', source);
+        }
+    }
+
+    /// Character at i in the document
+    fn char_at(&self, i: usize) -> Option<char> {
+        self.document[i..].chars().next()
+    }
+
+    fn html(&mut self) {
+        let mut i = 0;
+        while let Some(c) = self.char_at(i) {
+            i = match c {
+                '<' => self.tag(i),
+                '&' => self.entity(i, true),
+                c if c.is_whitespace() => {
+                    self.push_space(i..i + c.len_utf8());
+                    i + c.len_utf8()
+                }
+                c => {
+                    self.push(c, i..i + c.len_utf8());
+                    i + c.len_utf8()
+                }
+            };
+        }
+    }
+
+    fn markdown(&mut self) {
+        let mut i = 0;
+        let mut line_start = true;
+        while let Some(c) = self.char_at(i) {
+            if line_start {
+                line_start = false;
+                i = self.line_prefix(i);
+                continue;
+            }
+            let next = self.char_at(i + c.len_utf8());
+            i = match c {
+                '<' => self.tag(i),
+                '&' => self.entity(i, false),
+                '\\' => match next {
+                    Some(escaped) if escaped.is_ascii_punctuation() => {
+                        self.push(escaped, i..i + 1 + escaped.len_utf8());
+                        i + 1 + escaped.len_utf8()
+                    }
+                    _ => {
+                        self.push(c, i..i + 1);
+                        i + 1
+                    }
+                },
+                // Emphasis, code and the start of links and images
+                '*' | '_' | '~' | '`' | '[' => i + 1,
+                '!' if next == Some('[') => i + 1,
+                // End of the text of a link, followed by its url
+                ']' if next == Some('(') => self.link_end(i + 1).unwrap_or(i + 1),
+                ']' => i + 1,
+                '|' => {
+                    self.push_space(i..i + 1);
+                    i + 1
+                }
+                '\n' => {
+                    line_start = true;
+                    self.push(c, i..i + 1);
+                    i + 1
+                }
+                c if c.is_whitespace() => {
+                    self.push_space(i..i + c.len_utf8());
+                    i + c.len_utf8()
+                }
+                c => {
+                    self.push(c, i..i + c.len_utf8());
+                    i + c.len_utf8()
+                }
+            };
+        }
+    }
+
+    /// End of the url of a Markdown link starting with the parenthesis at start, just after its
+    /// closing parenthesis. Parentheses in the url are balanced, like in
+    /// `https://en.wikipedia.org/wiki/Euro_(currency)`. None if the url is not closed on the line
+    fn link_end(&self, start: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut escaped = false;
+        for (j, c) in self.document[start..].char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(start + j + 1);
+                    }
+                }
+                '\n' => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Skip headings, quotes and list markers at the start of a Markdown line, returning where
+    /// the content starts. Ordered list markers would otherwise be taken for amounts
+    fn line_prefix(&self, start: usize) -> usize {
+        let line = &self.document[start..];
+        let indented = line.trim_start_matches(&[' ', '\t'][..]);
+        let mut i = start + line.len() - indented.len();
+        let marker_len = if indented.starts_with('#') || indented.starts_with('>') {
+            indented.len() - indented.trim_start_matches(&['#', '>'][..]).len()
+        } else if indented.starts_with("- ")
+            || indented.starts_with("* ")
+            || indented.starts_with("+ ")
+        {
+            1
+        } else {
+            let digits = indented.len()
+                - indented
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .len();
+            match indented[digits..].chars().next() {
+                Some('.') | Some(')') if digits > 0 && indented[digits + 1..].starts_with(' ') => {
+                    digits + 1
+                }
+                _ => 0,
+            }
+        };
+        i += marker_len;
+        // Spaces after the marker
+        i + self.document[i..].len()
+            - self.document[i..]
+                .trim_start_matches(&[' ', '\t'][..])
+                .len()
+    }
+
+    /// Handle the tag or comment starting at i, returning where it ends. A lone '<' is text
+    fn tag(&mut self, i: usize) -> usize {
+        let rest = &self.document[i..];
+        if rest.starts_with("<!--") {
+            return rest
+                .find("-->")
+                .map_or(self.document.len(), |end| i + end + 3);
+        }
+        let is_tag = rest[1..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        let end = match rest.find('>') {
+            Some(end) if is_tag => i + end + 1,
+            _ => {
+                self.push('<', i..i + 1);
+                return i + 1;
+            }
+        };
+
+        let tag = &self.document[i + 1..end - 1];
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let name = name.as_str();
+
+        if !closing && SKIPPED_ELEMENTS.contains(&name) {
+            let closing_tag = format!("</{}", name);
+            return match self.document[end..].to_ascii_lowercase().find(&closing_tag) {
+                Some(close) => self.document[end + close..]
+                    .find('>')
+                    .map_or(self.document.len(), |gt| end + close + gt + 1),
+                None => self.document.len(),
+            };
+        }
+        if !closing && name == "sup" {
+            // Cents in superscript right after the units, like 19<sup>99</sup>
+            let after_digit = self.text.ends_with(|c: char| c.is_ascii_digit());
+            let before_digit = self.document[end..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_digit());
+            if after_digit && before_digit {
+                self.push('.', i..end);
+            }
+        } else if BLOCK_ELEMENTS.contains(&name) || CELL_ELEMENTS.contains(&name) {
+            self.push_break(i..end);
+        }
+        end
+    }
+
+    /// Handle the character reference starting at i, returning where it ends. Unknown references
+    /// are text. Spaces are collapsed if collapse is set
+    fn entity(&mut self, i: usize, collapse: bool) -> usize {
+        let rest = &self.document[i..];
+        let decoded = rest
+            .char_indices()
+            .take(12)
+            .find(|(_, c)| *c == ';')
+            .and_then(|(semicolon, _)| Some((decode(&rest[1..semicolon])?, i + semicolon + 1)));
+        match decoded {
+            Some((c, end)) if c.is_whitespace() && collapse => {
+                self.push_space(i..end);
+                end
+            }
+            Some((c, end)) if c.is_whitespace() => {
+                self.push(' ', i..end);
+                end
+            }
+            Some((c, end)) => {
+                self.push(c, i..end);
+                end
+            }
+            None => {
+                self.push('&', i..i + 1);
+                i + 1
+            }
+        }
+    }
+}
+
+/// Character of a reference like euro, #36 or #x20AC, without & and ;
+fn decode(reference: &str) -> Option<char> {
+    if let Some(code) = reference.strip_prefix('#') {
+        let code = match code.strip_prefix(|c| c == 'x' || c == 'X') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => code.parse().ok()?,
+        };
+        return std::char::from_u32(code);
+    }
+    let c = match reference {
+        "nbsp" | "ensp" | "emsp" | "thinsp" | "nnbsp" => ' ',
+        "euro" => '€',
+        "pound" => '£',
+        "yen" => '¥',
+        "cent" => '¢',
+        "dollar" => '$',
+        "curren" => '¤',
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "minus" => '-',
+        "ndash" => '–',
+        "mdash" => '—',
+        "comma" => ',',
+        "period" => '.',
+        _ => return None,
+    };
+    Some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("<span class=\"price\">19<sup>99</sup></span>&nbsp;&euro;", "19.99 €")]
+    #[test_case("<p>Total:</p>\n<p>  &#36;12  </p>", "Total:\n$12")]
+    #[test_case("<td>2</td><td>19.99&#x20AC;</td>", "2\n19.99€")]
+    #[test_case("<style>p { width: 12px }</style><b>3 &pound;</b><!-- 4 € -->", "3 £")]
+    #[test_case("<SCRIPT>var p = '9 €';</SCRIPT>5 €", "5 €")]
+    #[test_case("1 < 2 &amp; 3 &unknown; <br/>4", "1 < 2 & 3 &unknown;\n4")]
+    fn html_test(html: &str, text: &str) {
+        assert_eq!(extract(Markup::Html, html).text(), text);
+    }
+
+    #[test_case(
+        "# Prices\n\n1. **Coffee**: 2 €\n2) _Tea_ \\$3",
+        "Prices\n\nCoffee: 2 €\nTea $3"
+    )]
+    #[test_case(
+        "> See [the shop](https://example.com/9) for `10 EUR`",
+        "See the shop for 10 EUR"
+    )]
+    #[test_case(
+        "| Item | Price |\n|---|---|\n| Book | 12&nbsp;€ |",
+        "Item Price \n--- --- \nBook 12 €"
+    )]
+    #[test_case("![19 €](img/5.png) 2.5 USD", "19 € 2.5 USD")]
+    #[test_case(
+        "[Euro](https://en.wikipedia.org/wiki/Euro_(currency)) 3 €",
+        "Euro 3 €"
+    )]
+    #[test_case("[a](b\\)c) 4 € [d](e", "a 4 € d(e")]
+    fn markdown_test(markdown: &str, text: &str) {
+        assert_eq!(extract(Markup::Markdown, markdown).text(), text);
+    }
+
+    #[test]
+    fn original_test() {
+        let html = "<p>Now <b>19<sup>99</sup>&nbsp;&euro;</b></p>";
+        let extracted = extract(Markup::Html, html);
+        assert_eq!(extracted.text(), "Now 19.99 €");
+
+        let original = |range: Range<usize>| &html[extracted.original(range)];
+        assert_eq!(original(0..3), "Now");
+        assert_eq!(original(4..9), "19<sup>99");
+        assert_eq!(
+            original(4..extracted.text().len()),
+            "19<sup>99</sup>&nbsp;&euro;"
+        );
+        assert_eq!(extracted.original(20..20), html.len()..html.len());
+
+        let text = "2 € 😀";
+        assert_eq!(extract(Markup::Text, text).original(2..5), 2..5);
+    }
+}
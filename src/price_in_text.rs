@@ -18,17 +18,20 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 //! A module to find currency unit with amount (a **price tag**) in raw text
 
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
 use itertools::Itertools;
+use lazy_static::lazy_static;
 use log::{debug, trace};
+use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap};
-use std::convert::TryInto;
+use std::collections::{BTreeMap, HashSet};
 use std::ops::Bound::Included;
+use std::ops::Range;
+use std::sync::Arc;
 
 use crate::currency;
 use crate::currency::{Currency, PriceTag};
-use crate::price_format::PriceFormat;
-use regex::{Regex, RegexBuilder};
+use crate::price_format::{self, PriceFormat};
 
 #[cfg(test)]
 mod tests;
@@ -105,6 +108,22 @@ impl<'c> PriceTagMatch<'c> {
     }
 }
 
+/// Where a price tag is in a text, as byte offsets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The amount, like 12.50
+    pub amount: Range<usize>,
+    /// The currency symbol or iso, like €
+    pub currency: Range<usize>,
+}
+
+impl Location {
+    /// Smallest range with both the amount and the currency
+    pub fn span(&self) -> Range<usize> {
+        self.amount.start.min(self.currency.start)..self.amount.end.max(self.currency.end)
+    }
+}
+
 impl<'c> From<PriceTagMatch<'c>> for PriceTag<'c> {
     fn from(cm: PriceTagMatch<'c>) -> Self {
         Self::new(cm.currency, cm.amount)
@@ -118,34 +137,62 @@ impl<'c> From<PriceTagMatch<'c>> for PriceTag<'c> {
 ///    forward and backward in a certain distance (name *window*). A
 ///    probability of “matching” is computed for each.
 /// 3. Return N topmost matches or all of them
+///
+/// Compiling an engine is costly, so clones share the compiled engine and [`Engine::new`] compiles
+/// the default one only once.
+#[derive(Debug, Clone)]
+pub struct Engine<'c>(Arc<Compiled<'c>>);
+
 #[derive(Debug)]
-pub struct Engine<'c> {
-    options: EngineOptions<'c>,
+struct Compiled<'c> {
+    window_size: usize,
     /// To match and extract prices in plain text format
-    price_match: PriceFormat,
-    /// Regular expression to match currency symbol or iso in plain text format, by currency main iso
-    currency_matches: HashMap<&'c str, Regex>,
+    price_match: Cow<'c, PriceFormat>,
+    /// Symbols and isos of all the currencies, matched in a single pass over plain text
+    currency_match: AhoCorasick,
+    /// Currency of each pattern of currency_match, by pattern index
+    pattern_currencies: Vec<&'c Currency>,
+}
+
+lazy_static! {
+    /// Engine with the default options
+    static ref DEFAULT_ENGINE: Engine<'static> = EngineBuilder::new().fire();
+}
+
+impl<'c> Default for Engine<'c> {
+    fn default() -> Self {
+        Engine::new()
+    }
 }
 
 impl<'c> Engine<'c> {
-    pub fn new() -> Result<Engine<'c>, EngineError> {
-        let e = EngineBuilder::new().fire();
-        trace!("Engine::new {:?}", e);
-        e
+    /// Engine with the default options, compiled on first use and shared afterwards
+    pub fn new() -> Engine<'c> {
+        DEFAULT_ENGINE.clone()
     }
 
     // TODO Return an iterator to lazily cut evaluation
     /// Return all price tag matches found in plain_text
-    fn find<'txt>(&self, plain_text: &'txt str) -> Vec<PriceTagMatch> {
+    fn find<'txt>(&self, plain_text: &'txt str) -> Vec<PriceTagMatch<'c>> {
+        self.find_located(plain_text)
+            .into_iter()
+            .map(|(_, ptm)| ptm)
+            .collect()
+    }
+
+    /// Return all price tag matches found in plain_text, best first, along with their location
+    /// in plain_text
+    fn find_located<'txt>(&self, plain_text: &'txt str) -> Vec<(Location, PriceTagMatch<'c>)> {
         // Record locations of price ends in price tags
         let price_locations = || {
             debug!("computing price_locations…");
             let mut price_loc_start = BTreeMap::new();
             let mut price_loc_end = BTreeMap::new();
 
-            for price_match in &self.price_match.captures_iter(plain_text) {
-                price_loc_start.insert(price_match.start(), price_match.price());
-                price_loc_end.insert(price_match.end(), price_match.price());
+            for price_match in &self.0.price_match.captures_iter(plain_text) {
+                let located_price = (price_match.start()..price_match.end(), price_match.price());
+                price_loc_start.insert(price_match.start(), located_price.clone());
+                price_loc_end.insert(price_match.end(), located_price);
             }
             debug!("price_loc_start: {:?}", price_loc_start);
             trace!("price_loc_end: {:?}", price_loc_end);
@@ -154,31 +201,56 @@ impl<'c> Engine<'c> {
 
         let (price_loc_start, price_loc_end) = price_locations();
 
+        // Locations of currencies in the text, as (start, end, currency), by start and then
+        // longest first. Overlapping ones are all there, like USD and USDT in “USDT”
+        let engine = &self.0;
+        let mut currency_locations: Vec<(usize, usize, &'c Currency)> = engine
+            .currency_match
+            .find_overlapping_iter(plain_text)
+            .map(|m| (m.start(), m.end(), engine.pattern_currencies[m.pattern()]))
+            .collect();
+        currency_locations.sort_by(|(s1, e1, c1), (s2, e2, c2)| {
+            (s1, e2, c1.get_main_iso()).cmp(&(s2, e1, c2.get_main_iso()))
+        });
+        // Several patterns of a currency may match the same text, like “usd” and “USD”
+        currency_locations.dedup();
+        // When a currency is found inside another one, like USD in USDT, only keep the longest.
+        // Locations before the current ones start earlier or are longer
+        let mut max_end = None;
+        let mut longest = Vec::with_capacity(currency_locations.len());
+        for ((_, end), same_span) in &currency_locations
+            .into_iter()
+            .group_by(|&(start, end, _)| (start, end))
+        {
+            // None is less than any end
+            if max_end < Some(end) {
+                longest.extend(same_span);
+            }
+            max_end = max_end.max(Some(end));
+        }
+        let currency_locations = longest;
+        trace!("currency_locations: {:?}", currency_locations);
+
         let mut pricetag_matches = Vec::new();
-        for (currency_main_iso, currency_match) in &self.currency_matches {
-            debug!("Matches for {}", currency_main_iso);
-            let currency = currency::existing_from_iso(currency_main_iso).unwrap();
-            for cap in currency_match.captures_iter(plain_text) {
-                let m = cap.get(0);
-                trace!("m, before unwrapping: {:?}", m);
-                let m = cap.get(0).unwrap(); // 0 is the whole pattern, always present
-                let (start, end, win) = (m.start(), m.end(), self.options.window_size);
-                trace!("start, end, win: {}, {}, {}", start, end, win);
-                let win_before_start = if win > start { 0 } else { start - win };
-                trace!("win_before_start: {}", win_before_start);
-                // Look backward, for the end of the price. If we were looking
-                // from the start of the price, we would miss some corner
-                // cases, like this one:
-                //     window_size
-                //   /-------------\
-                //133  Lorem ipsumm USD
-                // Perform backward or forward look, depending of the parameters
-                use currency::Pos;
-                trace!(
-                    "before forward look, pricetag_matches: {:?}",
-                    pricetag_matches
-                );
-                let mut look = |location: usize, price: f64, expected_position: Pos| {
+        for (start, end, currency) in currency_locations {
+            let win = self.0.window_size;
+            trace!("start, end, win: {}, {}, {}", start, end, win);
+            let win_before_start = start.saturating_sub(win);
+            trace!("win_before_start: {}", win_before_start);
+            // Look backward, for the end of the price. If we were looking
+            // from the start of the price, we would miss some corner
+            // cases, like this one:
+            //     window_size
+            //   /-------------\
+            //133  Lorem ipsumm USD
+            // Perform backward or forward look, depending of the parameters
+            use currency::Pos;
+            trace!(
+                "before forward look, pricetag_matches: {:?}",
+                pricetag_matches
+            );
+            let mut look =
+                |location: usize, (amount, price): (Range<usize>, f64), expected_position: Pos| {
                     trace!("&location, &price: {:?}, {:?}", &location, &price);
                     let distance = if expected_position == Pos::Before {
                         (start - location) as i32
@@ -188,44 +260,70 @@ impl<'c> Engine<'c> {
                     let ptm = PriceTagMatch::new(
                         price,
                         currency,
-                        distance.try_into().unwrap(),
+                        distance,
                         currency.pos() == expected_position,
                     );
-                    pricetag_matches.push(ptm);
+                    let location = Location {
+                        amount,
+                        currency: start..end,
+                    };
+                    pricetag_matches.push((location, ptm));
                 };
-                for (location, price) in
-                    price_loc_end.range((Included(&win_before_start), Included(&start)))
-                {
-                    look(*location, *price, Pos::Before);
-                }
-                trace!("Looking backward now…");
-                // Idem, but with the start of the number when looking forward
-                for (location, price) in
-                    price_loc_start.range((Included(&end), Included(&(end + win))))
-                {
-                    look(*location, *price, Pos::After);
-                }
-                debug!(
-                    "after forward and backward look, pricetag_matches: {:?}",
-                    pricetag_matches
-                );
+            for (location, price) in
+                price_loc_end.range((Included(&win_before_start), Included(&start)))
+            {
+                look(*location, price.clone(), Pos::Before);
             }
+            trace!("Looking backward now…");
+            // Idem, but with the start of the number when looking forward
+            for (location, price) in price_loc_start.range((Included(&end), Included(&(end + win))))
+            {
+                look(*location, price.clone(), Pos::After);
+            }
+            debug!(
+                "after forward and backward look, pricetag_matches: {:?}",
+                pricetag_matches
+            );
         }
 
-        pricetag_matches.sort_by_key(|ptm| (ptm.distance, ptm.correct_symbol_order));
+        pricetag_matches.sort_by_key(|(_, ptm)| (ptm.distance, ptm.correct_symbol_order));
         pricetag_matches
     }
 
     /// Return all price tag found in plain_text
-    pub fn all_price_tags<'txt>(&self, plain_text: &'txt str) -> Vec<PriceTag> {
+    pub fn all_price_tags<'txt>(&self, plain_text: &'txt str) -> Vec<PriceTag<'c>> {
         self.find(plain_text)
             .into_iter()
             .map(|ptm| ptm.into())
             .collect()
     }
 
+    /// Return price tags found in plain_text in the order of the text, each amount being used
+    /// once with the currency it matches best. Useful to add up the price tags of a text
+    pub fn distinct_price_tags(&self, plain_text: &str) -> Vec<PriceTag<'c>> {
+        self.located_price_tags(plain_text)
+            .into_iter()
+            .map(|(_, price_tag)| price_tag)
+            .collect()
+    }
+
+    /// Same as distinct_price_tags, with the location of each price tag in plain_text
+    pub fn located_price_tags(&self, plain_text: &str) -> Vec<(Location, PriceTag<'c>)> {
+        let mut used = HashSet::new();
+        let mut matches: Vec<(Location, PriceTagMatch<'c>)> = self
+            .find_located(plain_text)
+            .into_iter()
+            .filter(|(location, _)| used.insert(location.amount.start))
+            .collect();
+        matches.sort_by_key(|(location, _)| location.amount.start);
+        matches
+            .into_iter()
+            .map(|(location, ptm)| (location, ptm.into()))
+            .collect()
+    }
+
     /// Return the top `n` price tags
-    pub fn top_price_tags(&self, n: usize, plain_text: &str) -> Vec<PriceTag> {
+    pub fn top_price_tags(&self, n: usize, plain_text: &str) -> Vec<PriceTag<'c>> {
         self.find(plain_text)
             .into_iter()
             .take(n)
@@ -241,7 +339,7 @@ pub struct EngineOptions<'c> {
     by_symbol: bool,
     by_iso: bool,
     case_insensitive: bool,
-    price_format: PriceFormat,
+    price_format: Cow<'c, PriceFormat>,
 }
 
 impl<'c> Default for EngineOptions<'c> {
@@ -253,8 +351,7 @@ impl<'c> Default for EngineOptions<'c> {
             by_symbol: true,
             by_iso: true,
             case_insensitive: true,
-            // TODO Try to avoid clone call here
-            price_format: crate::price_format::COMMON.clone(),
+            price_format: Cow::Borrowed(&price_format::COMMON),
         }
     }
 }
@@ -269,59 +366,53 @@ impl<'c> EngineBuilder<'c> {
     }
 
     /// Consume Builder and fire the Engine, so that it be used to match text
-    pub fn fire(self) -> Result<Engine<'c>, EngineError> {
-        let price_match = self.0.price_format.clone();
-
-        let mut currency_matches = HashMap::new();
-        for currency in self.0.currencies {
-            let mut alternatives_slices: Vec<&[&str]> = Vec::new();
-            let mut currency_match_string = String::new();
-            let mut add_regex_altenatives = |new_alternatives: &'static [&'static str]| {
-                trace!(
-                    "add_regex_altenatives: alternatives (before): {:?}",
-                    &alternatives_slices
-                );
-                trace!(
-                    "add_regex_altenatives: new_alternatives (after): {:?}",
-                    &new_alternatives
-                );
-                alternatives_slices.push(new_alternatives);
-                trace!(
-                    "add_regex_altenatives: alternatives (after): {:?}",
-                    &alternatives_slices
-                );
-            };
-            if self.0.by_iso {
-                add_regex_altenatives(currency.isos());
+    pub fn fire(self) -> Engine<'c> {
+        let options = self.0;
+        let mut patterns: Vec<String> = Vec::new();
+        let mut pattern_currencies = Vec::new();
+        for currency in options.currencies {
+            let mut alternatives: Vec<&str> = Vec::new();
+            if options.by_iso {
+                alternatives.extend(currency.isos());
             }
-            if self.0.by_symbol {
-                add_regex_altenatives(currency.symbols());
+            if options.by_symbol {
+                alternatives.extend(currency.symbols());
             }
-            // Escape currency symbols and iso by inserting litteral unicode in the regex
-            let alternatives_unicode_escaped = alternatives_slices
+            // The automaton ignores the case of ASCII letters only, other letters are added in
+            // upper and lower case
+            let mut variants: Vec<String> = alternatives
                 .into_iter()
-                .map(|a| a.iter().map(|s| format!("{}", s.escape_unicode())));
-            for s in Itertools::intersperse(alternatives_unicode_escaped.flatten(), "|".to_owned())
-            {
-                currency_match_string.push_str(&s);
-            }
-            let currency_match_err = RegexBuilder::new(currency_match_string.as_str())
-                .case_insensitive(self.0.case_insensitive)
-                .build();
-            match currency_match_err {
-                Ok(currency_match) => {
-                    currency_matches.insert(currency.get_main_iso(), currency_match)
-                }
-                Err(err) => return Err(EngineError::CurrencyMatchRegex(err)),
-            };
-            debug!("currency_matches: {:?}", currency_matches)
+                .filter(|alternative| !alternative.is_empty())
+                .flat_map(|alternative| {
+                    if options.case_insensitive {
+                        vec![alternative.to_lowercase(), alternative.to_uppercase()]
+                    } else {
+                        vec![alternative.to_string()]
+                    }
+                })
+                .collect();
+            variants.sort();
+            variants.dedup();
+            pattern_currencies.extend(variants.iter().map(|_| currency));
+            patterns.extend(variants);
         }
-
-        Ok(Engine {
-            options: self.0,
-            price_match,
-            currency_matches,
-        })
+        debug!(
+            "{} patterns for {} currencies",
+            patterns.len(),
+            options.currencies.len()
+        );
+
+        let currency_match = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(options.case_insensitive)
+            .match_kind(MatchKind::Standard)
+            .build(&patterns);
+
+        Engine(Arc::new(Compiled {
+            window_size: options.window_size,
+            price_match: options.price_format,
+            currency_match,
+            pattern_currencies,
+        }))
     }
 
     /// Set the size of the window used as the distance between a price and a
@@ -357,15 +448,7 @@ impl<'c> EngineBuilder<'c> {
 
     /// Set the PriceFormat used to match and extract prices in plain text
     pub fn price(&mut self, format: PriceFormat) -> &mut EngineBuilder<'c> {
-        self.0.price_format = format;
+        self.0.price_format = Cow::Owned(format);
         self
     }
 }
-
-/// Error that occured while building and firing the engine
-#[derive(Clone, PartialEq, Debug)]
-#[non_exhaustive]
-pub enum EngineError {
-    /// Invalid regex for currency_match
-    CurrencyMatchRegex(regex::Error),
-}
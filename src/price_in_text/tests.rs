@@ -47,12 +47,7 @@ mod iso {
             engine_builder: &EngineBuilder<'static>,
             currencies: &'static [Currency],
         ) -> Engine<'static> {
-            engine_builder
-                .clone()
-                .currencies(currencies)
-                .clone()
-                .fire()
-                .unwrap()
+            engine_builder.clone().currencies(currencies).clone().fire()
         }
         println!("===============================");
         assert_eq!(
@@ -122,7 +117,7 @@ mod iso {
     #[test_case("1234€" ; "Symbol, no space")]
     fn spaces(txt: &str) {
         let pt = PriceTag::new(&EUR, 1234.);
-        let engine = Engine::new().unwrap();
+        let engine = Engine::new();
         assert_eq!(
             *engine.all_price_tags(&txt.to_uppercase()).first().unwrap(),
             pt
@@ -144,7 +139,7 @@ mod iso {
     #[test_case("1234,5678€" ; "Symbol, no space")]
     fn spaces_comma(txt: &str) {
         let pt = PriceTag::new(&EUR, 1234.5678);
-        let engine = Engine::new().unwrap();
+        let engine = Engine::new();
         assert_eq!(*engine.all_price_tags(txt).first().unwrap(), pt);
     }
 
@@ -157,7 +152,7 @@ mod iso {
     #[test_case("€\n1234,5678" ; "Symbol, no space")]
     fn multiline(txt: &str) {
         let pt = PriceTag::new(&EUR, 1234.5678);
-        let engine = Engine::new().unwrap();
+        let engine = Engine::new();
         assert_eq!(*engine.all_price_tags(txt).first().unwrap(), pt);
     }
 
@@ -167,10 +162,36 @@ mod iso {
     #[test_case("€ 32", PriceTag::new(&EUR, 32.))]
     #[test_case("EUR 4", PriceTag::new(&EUR, 4.))]
     fn gh_issue1_various_format(txt: &str, pt: PriceTag) {
-        let engine = Engine::new().unwrap();
+        let engine = Engine::new();
         assert_eq!(*engine.all_price_tags(txt).first().unwrap(), pt);
     }
 
+    #[test_case("0.5 ₿", PriceTag::new(&BTC, 0.5))]
+    #[test_case("1.25 ETH", PriceTag::new(&ETH, 1.25))]
+    // Not USD, found inside USDT
+    #[test_case("100 USDT", PriceTag::new(&USDT, 100.))]
+    #[test_case("USDC 20", PriceTag::new(&USDC, 20.))]
+    fn crypto(txt: &str, pt: PriceTag) {
+        let engine = Engine::new();
+        let tags = engine.all_price_tags(txt);
+        assert_eq!(tags, vec![pt]);
+    }
+
+    // Symbols found inside a longer symbol of the same currency count once
+    #[test_case("SFr. 12")]
+    #[test_case("sfr. 12" ; "Lower case")]
+    #[test_case("12 Fr.sv.")]
+    fn nested_symbols(txt: &str) {
+        let engine = Engine::new();
+        assert_eq!(engine.all_price_tags(txt), vec![PriceTag::new(&CHF, 12.)]);
+    }
+
+    #[test]
+    fn default_engine_compiled_once() {
+        let (engine, other) = (Engine::new(), Engine::new());
+        assert!(std::sync::Arc::ptr_eq(&engine.0, &other.0));
+    }
+
     #[test_case("12 usd")]
     #[test_case("12 eur")]
     #[test_case("usd 38")]
@@ -180,7 +201,7 @@ mod iso {
     fn case_sensitive_no_lowercase_iso(txt: &str) {
         let mut engine_builder = EngineBuilder::new();
         engine_builder.case_insensitive(false);
-        let engine = engine_builder.fire().unwrap();
+        let engine = engine_builder.fire();
         assert_eq!(engine.all_price_tags(txt), vec![]);
     }
 
@@ -190,7 +211,7 @@ mod iso {
     fn multiple_pricetags(txt: &str) {
         let mut engine_builder = EngineBuilder::new();
         engine_builder.window(30);
-        let engine = engine_builder.fire().unwrap();
+        let engine = engine_builder.fire();
         // Equality without Ord
         let tags = vec![
             PriceTag::new(&USD, 12.),
@@ -205,11 +226,38 @@ mod iso {
         }
     }
 
+    #[test_case("$12 and then 10 EUR")]
+    #[test_case("$  12, 10   EUR" ; "More spaces")]
+    #[test_case("$12\n\n10 EUR" ; "Line return")]
+    fn distinct_pricetags(txt: &str) {
+        let mut engine_builder = EngineBuilder::new();
+        engine_builder.window(30);
+        let engine = engine_builder.fire();
+        // Each amount once, with its closest currency, in the order of the text
+        assert_eq!(
+            engine.distinct_price_tags(txt),
+            vec![PriceTag::new(&USD, 12.), PriceTag::new(&EUR, 10.)]
+        );
+    }
+
+    #[test]
+    fn distinct_pricetags_expenses() {
+        let engine = Engine::new();
+        assert_eq!(
+            engine.distinct_price_tags("taxi 23 €, hotel £140, dinner 45 USD"),
+            vec![
+                PriceTag::new(&EUR, 23.),
+                PriceTag::new(&GBP, 140.),
+                PriceTag::new(&USD, 45.)
+            ]
+        );
+    }
+
     // https://github.com/cljoly/sesters/issues/2
     #[test]
     fn gh_issue1_ambiguous() {
         let pts = vec![PriceTag::new(&EUR, 12.), PriceTag::new(&USD, 12.)];
-        let engine = Engine::new().unwrap();
+        let engine = Engine::new();
         assert_eq!(engine.all_price_tags("$ 12 €"), pts);
     }
 
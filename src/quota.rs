@@ -0,0 +1,440 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Request budgets of the rate providers and the status subcommand
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use log::{info, warn};
+#[cfg(feature = "cli")]
+use term_table::{row::Row, Table};
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::api::{RateApi, RateError};
+use crate::config::Quota;
+#[cfg(feature = "cli")]
+use crate::config::{CoinGecko, CurrencyConverterApiCom, Ecb, ExchangeRatesApiIo};
+use crate::db::Db;
+use crate::http::Client;
+use crate::rate::Rate;
+use crate::MainContext;
+
+/// How long a provider is left alone when it reports an exceeded quota without saying when to
+/// come back
+const DEFAULT_BLOCK_HOURS: i64 = 1;
+
+#[cfg(feature = "cli")]
+pub(crate) fn run(ctxt: MainContext) -> Result<()> {
+    let providers: [&dyn RateApi; 4] = [
+        CurrencyConverterApiCom::new(&ctxt.cfg),
+        ExchangeRatesApiIo::new(&ctxt.cfg),
+        Ecb::new(&ctxt.cfg),
+        CoinGecko::new(&ctxt.cfg),
+    ];
+    let now = Utc::now();
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        "Provider",
+        "Last hour",
+        "This month",
+        "Failed this month",
+        "Next request",
+    ]));
+    for provider in &providers {
+        let id = provider.provider_id();
+        let quota = provider.quota();
+        let (hour, _, _) = ctxt.db.count_requests(&id, now - Duration::hours(1))?;
+        let (month, failed, _) = ctxt.db.count_requests(&id, month_start(now))?;
+        let next = match wait(&ctxt.db, &id, &quota, now)? {
+            None => String::from("now"),
+            Some(wait) => format!("in {}", duration_string(wait)),
+        };
+        table.add_row(Row::new(vec![
+            id,
+            usage_string(hour, quota.per_hour),
+            usage_string(month, quota.per_month),
+            failed.to_string(),
+            next,
+        ]));
+    }
+
+    println!("{}", table.render());
+    Ok(())
+}
+
+/// Number of requests, out of the budget if there is one
+#[cfg(feature = "cli")]
+fn usage_string(count: u32, budget: Option<u32>) -> String {
+    match budget {
+        Some(budget) => format!("{} / {}", count, budget),
+        None => count.to_string(),
+    }
+}
+
+/// Human readable duration, rounded up to the minute
+fn duration_string(duration: Duration) -> String {
+    let minutes = (duration.num_seconds() + 59) / 60;
+    match minutes {
+        m if m < 60 => format!("{} min", m),
+        m if m < 48 * 60 => format!("{} h {} min", m / 60, m % 60),
+        m => format!("{} days", m / (24 * 60)),
+    }
+}
+
+/// Start of the calendar month of a time
+fn month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.ymd(now.year(), now.month(), 1).and_hms(0, 0, 0)
+}
+
+/// Start of the calendar month following the one of a time
+fn next_month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    if now.month() == 12 {
+        Utc.ymd(now.year() + 1, 1, 1).and_hms(0, 0, 0)
+    } else {
+        Utc.ymd(now.year(), now.month() + 1, 1).and_hms(0, 0, 0)
+    }
+}
+
+/// Time to wait before a request to a provider fits in its budget, None if it can be sent now
+fn wait(db: &Db, provider: &str, quota: &Quota, now: DateTime<Utc>) -> Result<Option<Duration>> {
+    if let Some(until) = db.provider_blocked_until(provider, now)? {
+        return Ok(Some(until - now));
+    }
+    if let Some(per_hour) = quota.per_hour {
+        let hour = Duration::hours(1);
+        let (count, _, oldest) = db.count_requests(provider, now - hour)?;
+        if count >= per_hour {
+            // A request is freed when the oldest one gets older than an hour
+            return Ok(Some(oldest.map_or(hour, |oldest| oldest + hour - now)));
+        }
+    }
+    if let Some(per_month) = quota.per_month {
+        let (count, _, _) = db.count_requests(provider, month_start(now))?;
+        if count >= per_month {
+            return Ok(Some(next_month_start(now) - now));
+        }
+    }
+    Ok(None)
+}
+
+/// Number of requests that fit in the budget of a provider now, None if unlimited
+#[cfg(feature = "async")]
+fn available(db: &Db, provider: &str, quota: &Quota, now: DateTime<Utc>) -> Result<Option<u32>> {
+    if db.provider_blocked_until(provider, now)?.is_some() {
+        return Ok(Some(0));
+    }
+    let mut available = None;
+    if let Some(per_hour) = quota.per_hour {
+        let (count, _, _) = db.count_requests(provider, now - Duration::hours(1))?;
+        available = Some(per_hour.saturating_sub(count));
+    }
+    if let Some(per_month) = quota.per_month {
+        let (count, _, _) = db.count_requests(provider, month_start(now))?;
+        let left = per_month.saturating_sub(count);
+        available = Some(available.map_or(left, |available: u32| available.min(left)));
+    }
+    Ok(available)
+}
+
+/// Send requests to a provider with the request function and the client it is given, unless it
+/// would exceed the budget of the provider. Every request sent, retries included, is recorded in
+/// the database, and providers that reported an exceeded quota are not called again before the
+/// time they gave
+pub(crate) fn within_quota<'c, F>(
+    ctxt: &MainContext,
+    provider: &dyn RateApi,
+    request: F,
+) -> Result<Vec<Rate<'c>>, RateError>
+where
+    F: FnOnce(&Client) -> Result<Vec<Rate<'c>>, RateError>,
+{
+    let now = Utc::now();
+    check_quota(ctxt, provider, now)?;
+    let attempts = Arc::new(AtomicU32::new(0));
+    let result = request(&ctxt.client.counting(Arc::clone(&attempts)));
+    record_request(
+        ctxt,
+        &provider.provider_id(),
+        now,
+        attempts.load(Ordering::Relaxed),
+        result.as_ref().err(),
+    )?;
+    result
+}
+
+/// Error if a request sent to the provider now would exceed its budget
+fn check_quota(
+    ctxt: &MainContext,
+    provider: &dyn RateApi,
+    now: DateTime<Utc>,
+) -> Result<(), RateError> {
+    let id = provider.provider_id();
+    if let Some(wait) = wait(&ctxt.db, &id, &provider.quota(), now).map_err(RateError::Db)? {
+        info!("Not calling {}, out of its request budget", id);
+        return Err(RateError::QuotaExceeded {
+            provider: id,
+            retry_after: Some(wait),
+        });
+    }
+    Ok(())
+}
+
+/// Number of requests that can be sent to the provider now without exceeding its budget, None
+/// if it is unlimited. Requests sent together are checked against it as a whole
+#[cfg(feature = "async")]
+pub(crate) fn available_requests(
+    ctxt: &MainContext,
+    provider: &dyn RateApi,
+    now: DateTime<Utc>,
+) -> Result<Option<u32>, RateError> {
+    available(&ctxt.db, &provider.provider_id(), &provider.quota(), now).map_err(RateError::Db)
+}
+
+/// Record a request sent at some time to a provider in a number of attempts, with its error if it
+/// failed. Attempts before the last one failed, since only failures are retried. A provider
+/// reporting an exceeded quota is blocked until the time it gave
+pub(crate) fn record_request(
+    ctxt: &MainContext,
+    id: &str,
+    sent: DateTime<Utc>,
+    attempts: u32,
+    error: Option<&RateError>,
+) -> Result<(), RateError> {
+    for attempt in 1..=attempts {
+        let success = attempt == attempts && error.is_none();
+        ctxt.db
+            .record_request(id, sent, success)
+            .map_err(RateError::Db)?;
+    }
+    if let Some(RateError::QuotaExceeded { retry_after, .. }) = error {
+        let retry_after = retry_after.unwrap_or_else(|| Duration::hours(DEFAULT_BLOCK_HOURS));
+        warn!("{} asked to wait {}", id, duration_string(retry_after));
+        ctxt.db
+            .block_provider(id, Utc::now() + retry_after)
+            .map_err(RateError::Db)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::{fixtures, MockServer, Route};
+    use crate::config::{Config, CurrencyConverterApiCom, Ecb, Http};
+    use crate::currency::{EUR, USD};
+
+    fn time(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn month_test() {
+        let now = time("2026-12-18T10:00:00Z");
+        assert_eq!(month_start(now), time("2026-12-01T00:00:00Z"));
+        assert_eq!(next_month_start(now), time("2027-01-01T00:00:00Z"));
+        assert_eq!(
+            next_month_start(time("2026-10-31T23:59:59Z")),
+            time("2026-11-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn duration_string_test() {
+        assert_eq!(duration_string(Duration::seconds(1)), "1 min");
+        assert_eq!(duration_string(Duration::minutes(75)), "1 h 15 min");
+        assert_eq!(duration_string(Duration::days(12)), "12 days");
+    }
+
+    #[test]
+    fn wait_test() {
+        let db = Db::new_in_memory().unwrap();
+        let now = time("2026-10-18T10:00:00Z");
+        let quota = Quota {
+            per_hour: Some(2),
+            per_month: Some(3),
+        };
+
+        assert_eq!(wait(&db, "p", &quota, now).unwrap(), None);
+        db.record_request("p", now - Duration::minutes(50), true)
+            .unwrap();
+        db.record_request("p", now - Duration::minutes(10), false)
+            .unwrap();
+        // Hourly budget used, the oldest request frees a slot in 10 minutes
+        assert_eq!(
+            wait(&db, "p", &quota, now).unwrap(),
+            Some(Duration::minutes(10))
+        );
+        // Other providers are not affected
+        assert_eq!(wait(&db, "q", &quota, now).unwrap(), None);
+
+        // Monthly budget used, until November
+        let later = now + Duration::hours(2);
+        db.record_request("p", later, true).unwrap();
+        assert_eq!(
+            wait(&db, "p", &quota, later).unwrap(),
+            Some(time("2026-11-01T00:00:00Z") - later)
+        );
+        assert_eq!(wait(&db, "p", &Quota::default(), later).unwrap(), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn available_test() {
+        let db = Db::new_in_memory().unwrap();
+        let now = time("2026-10-18T10:00:00Z");
+        let quota = Quota {
+            per_hour: Some(3),
+            per_month: Some(4),
+        };
+
+        assert_eq!(available(&db, "p", &Quota::default(), now).unwrap(), None);
+        assert_eq!(available(&db, "p", &quota, now).unwrap(), Some(3));
+        db.record_request("p", now - Duration::days(2), true)
+            .unwrap();
+        db.record_request("p", now - Duration::minutes(10), false)
+            .unwrap();
+        // The monthly budget is the tightest
+        assert_eq!(available(&db, "p", &quota, now).unwrap(), Some(2));
+        db.record_request("p", now, true).unwrap();
+        assert_eq!(available(&db, "p", &quota, now).unwrap(), Some(1));
+
+        db.block_provider("p", now + Duration::minutes(30)).unwrap();
+        assert_eq!(available(&db, "p", &quota, now).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn block_test() {
+        let db = Db::new_in_memory().unwrap();
+        let now = time("2026-10-18T10:00:00Z");
+        db.block_provider("p", now + Duration::minutes(30)).unwrap();
+
+        assert_eq!(
+            wait(&db, "p", &Quota::default(), now).unwrap(),
+            Some(Duration::minutes(30))
+        );
+        assert_eq!(
+            wait(&db, "p", &Quota::default(), now + Duration::hours(1)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn within_quota_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let mut cfg = Config::default();
+        cfg.apis.currency_converter_api_com.base_url = server.url();
+        cfg.apis.currency_converter_api_com.quota = Quota {
+            per_hour: Some(1),
+            per_month: None,
+        };
+        let ctxt = MainContext::new_in_memory(cfg, vec![]).unwrap();
+        let provider = CurrencyConverterApiCom::new(&ctxt.cfg);
+
+        assert!(within_quota(&ctxt, provider, |client| provider.rates(
+            client,
+            &EUR,
+            &[&USD]
+        ))
+        .is_ok());
+        let err = within_quota(&ctxt, provider, |_| {
+            panic!("Budget exceeded, must not be called")
+        })
+        .unwrap_err();
+        assert!(matches!(err, RateError::QuotaExceeded { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn quota_exceeded_blocks_test() {
+        // Too long a wait to be retried
+        let server = MockServer::start(vec![Route::new(
+            "/stats/eurofxref/",
+            429,
+            "Too many requests",
+        )
+        .header("Retry-After", "7200")]);
+        let mut cfg = Config::default();
+        cfg.apis.ecb.base_url = server.url();
+        let ctxt = MainContext::new_in_memory(cfg, vec![]).unwrap();
+        let provider = Ecb::new(&ctxt.cfg);
+
+        let err = within_quota(&ctxt, provider, |client| {
+            provider.rates(client, &EUR, &[&USD])
+        })
+        .unwrap_err();
+        assert!(matches!(err, RateError::QuotaExceeded { .. }));
+
+        // The provider is not called until the time it gave
+        let err =
+            within_quota(&ctxt, provider, |_| panic!("Blocked, must not be called")).unwrap_err();
+        assert!(
+            matches!(err, RateError::QuotaExceeded { retry_after: Some(d), .. } if d > Duration::minutes(119)),
+            "{:?}",
+            err
+        );
+        let (count, failed, _) = ctxt
+            .db
+            .count_requests(&provider.provider_id(), Utc::now() - Duration::hours(1))
+            .unwrap();
+        assert_eq!((count, failed), (1, 1));
+    }
+
+    #[test]
+    fn retries_use_budget_test() {
+        let server = MockServer::start(vec![
+            Route::new("/api/v7/convert", 503, "Unavailable").times(1),
+            Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR),
+        ]);
+        let mut cfg = Config {
+            http: Http {
+                retries: 1,
+                retry_backoff: 1,
+                ..Http::default()
+            },
+            ..Config::default()
+        };
+        cfg.apis.currency_converter_api_com.base_url = server.url();
+        cfg.apis.currency_converter_api_com.quota = Quota {
+            per_hour: Some(2),
+            per_month: None,
+        };
+        let ctxt = MainContext::new_in_memory(cfg, vec![]).unwrap();
+        let provider = CurrencyConverterApiCom::new(&ctxt.cfg);
+
+        let rates = within_quota(&ctxt, provider, |client| {
+            provider.rates(client, &EUR, &[&USD])
+        })
+        .unwrap();
+        assert_eq!(rates.len(), 1);
+        assert_eq!(server.requests().len(), 2);
+
+        // The failed attempt and the retry both count
+        let (count, failed, _) = ctxt
+            .db
+            .count_requests(&provider.provider_id(), Utc::now() - Duration::hours(1))
+            .unwrap();
+        assert_eq!((count, failed), (2, 1));
+        let err = within_quota(&ctxt, provider, |_| {
+            panic!("Budget exceeded, must not be called")
+        })
+        .unwrap_err();
+        assert!(matches!(err, RateError::QuotaExceeded { .. }), "{:?}", err);
+    }
+}
@@ -28,7 +28,66 @@ use std::fmt;
 use crate::currency::{Currency, GBP, USD};
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::currency::{EUR, JPY};
+
+    fn rate(src: &'static Currency, dst: &'static Currency, rate: f64, age: i64) -> Rate<'static> {
+        let date = Utc::now() - Duration::hours(age);
+        Rate::new(
+            src,
+            dst,
+            date,
+            rate,
+            String::from("test"),
+            Some(date + Duration::hours(12)),
+        )
+    }
+
+    #[test]
+    fn inverse_test() {
+        let eur_usd = rate(&EUR, &USD, 1.25, 1);
+        let usd_eur = eur_usd.inverse();
+
+        assert_eq!(usd_eur.src(), &USD);
+        assert_eq!(usd_eur.dst(), &EUR);
+        assert_eq!(usd_eur.rate(), 0.8);
+        assert_eq!(usd_eur.date(), eur_usd.date());
+        assert!(usd_eur.is_derived());
+        assert_eq!(usd_eur.constituents(), &[eur_usd]);
+    }
+
+    #[test]
+    fn through_test() {
+        let eur_usd = rate(&EUR, &USD, 1.25, 1);
+        let usd_jpy = rate(&USD, &JPY, 100., 3);
+        let eur_jpy = eur_usd.through(&usd_jpy).unwrap();
+
+        assert_eq!(eur_jpy.src(), &EUR);
+        assert_eq!(eur_jpy.dst(), &JPY);
+        assert_eq!(eur_jpy.rate(), 125.);
+        // Oldest constituent date and earliest expiry are kept
+        assert_eq!(eur_jpy.date(), usd_jpy.date());
+        assert_eq!(eur_jpy.cache_until(), usd_jpy.cache_until());
+        assert_eq!(eur_jpy.constituents(), &[eur_usd.clone(), usd_jpy.clone()]);
+
+        // Currencies don’t chain
+        assert_eq!(usd_jpy.through(&eur_usd), None);
+    }
+
+    #[test]
+    fn through_derived_test() {
+        let eur_usd = rate(&EUR, &USD, 1.25, 1);
+        let eur_jpy = rate(&EUR, &JPY, 125., 2);
+        let usd_jpy = eur_usd.inverse().through(&eur_jpy).unwrap();
+
+        assert_eq!(usd_jpy.src(), &USD);
+        assert_eq!(usd_jpy.dst(), &JPY);
+        assert!((usd_jpy.rate() - 100.).abs() < 1e-9);
+        // Constituents are the stored rates, not intermediate derivations
+        assert_eq!(usd_jpy.constituents(), &[eur_usd, eur_jpy]);
+    }
+}
 
 /// Rate from a source currency to a destination currency
 #[derive(Clone, PartialOrd, PartialEq, Debug)]
@@ -45,6 +104,8 @@ pub struct Rate<'c> {
     provider: String,
     /// Cache until this date. If None, can’t be cached
     cache_until: Option<DateTime<Utc>>,
+    /// Rates this one was computed from, empty if it was given by a provider
+    constituents: Vec<Rate<'c>>,
 }
 
 impl<'c> Default for Rate<'c> {
@@ -56,6 +117,7 @@ impl<'c> Default for Rate<'c> {
             rate: 0.,
             provider: String::from("DEFAULT"),
             cache_until: None,
+            constituents: Vec::new(),
         }
     }
 }
@@ -64,14 +126,26 @@ impl<'c> fmt::Display for Rate<'c> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "1 {src} ≈ {rate:.*} {dst} ({date} - {provider})",
-            3,
+            "1 {src} ≈ {rate:.*} {dst} ({date} - {provider}",
+            self.dst().decimals().max(3),
             rate = self.rate(),
             src = self.src(),
             dst = self.dst(),
             date = self.date().format("%F %T"),
             provider = self.provider()
-        )
+        )?;
+        for (i, constituent) in self.constituents().iter().enumerate() {
+            let sep = if i == 0 { " from " } else { ", " };
+            write!(
+                f,
+                "{}{}/{} {}",
+                sep,
+                constituent.src(),
+                constituent.dst(),
+                constituent.provider()
+            )?;
+        }
+        write!(f, ")")
     }
 }
 
@@ -92,6 +166,7 @@ impl<'c> Rate<'c> {
             rate,
             provider,
             cache_until,
+            constituents: Vec::new(),
         }
     }
 
@@ -113,14 +188,84 @@ impl<'c> Rate<'c> {
         Rate::new(c, c, Utc::now(), 1., String::from("PARITY"), None)
     }
 
+    /// Provider name of rates computed from other rates
+    pub const DERIVED: &'static str = "DERIVED";
+
+    /// Rate from the destination currency to the source currency, derived from this one
+    pub fn inverse(&self) -> Self {
+        Rate {
+            src: self.dst,
+            dst: self.src,
+            date: self.date,
+            rate: 1. / self.rate,
+            provider: String::from(Rate::DERIVED),
+            cache_until: self.cache_until,
+            constituents: self.leaves(),
+        }
+    }
+
+    /// Rate from the source currency of self to the destination currency of next, going through
+    /// the destination currency of self. None if that currency is not the source of next. The
+    /// result is as old as its oldest constituent.
+    pub fn through(&self, next: &Rate<'c>) -> Option<Self> {
+        if self.dst != next.src {
+            return None;
+        }
+
+        let mut constituents = self.leaves();
+        constituents.append(&mut next.leaves());
+        let cache_until = match (self.cache_until, next.cache_until) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            _ => None,
+        };
+
+        Some(Rate {
+            src: self.src,
+            dst: next.dst,
+            date: self.date.min(next.date),
+            rate: self.rate * next.rate,
+            provider: String::from(Rate::DERIVED),
+            cache_until,
+            constituents,
+        })
+    }
+
+    /// Rates given by providers this rate is made of, itself if it is not derived
+    fn leaves(&self) -> Vec<Rate<'c>> {
+        if self.is_derived() {
+            self.constituents.clone()
+        } else {
+            vec![self.clone()]
+        }
+    }
+
+    /// Whether the rate was computed from other rates instead of being given by a provider
+    pub fn is_derived(&self) -> bool {
+        !self.constituents.is_empty()
+    }
+
+    /// Providers of the rate, those of the rates it was derived from if it is derived
+    pub fn providers(&self) -> Vec<&str> {
+        if self.is_derived() {
+            self.constituents.iter().map(|r| r.provider()).collect()
+        } else {
+            vec![self.provider()]
+        }
+    }
+
+    /// Rates this one was derived from
+    pub fn constituents(&self) -> &[Rate<'c>] {
+        &self.constituents
+    }
+
     /// Source currency
-    pub fn src(&self) -> &Currency {
-        &self.src
+    pub fn src(&self) -> &'c Currency {
+        self.src
     }
 
     /// Destination currency
-    pub fn dst(&self) -> &Currency {
-        &self.dst
+    pub fn dst(&self) -> &'c Currency {
+        self.dst
     }
 
     /// Date of the rate
@@ -0,0 +1,307 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Repl subcommand: convert line after line, keeping the database, the configuration, the
+//! engine and the HTTP client of a single context
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use log::{info, trace, warn};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use std::path::PathBuf;
+
+use crate::convert::{
+    conversions_to_string, convert_with, currency_from_iso, rates_to_string, strings_of_conversions,
+};
+use crate::currency::Currency;
+use crate::db::history::HistoryConversion;
+use crate::price_in_text::Engine;
+use crate::MainContext;
+
+const HELP: &str = "\
+Type a text with price tags to convert them, or a command:
+  :to CURRENCY…        convert to these currencies, show them without any
+  :rate SRC [DST…]     show rates from SRC, to the target currencies by default
+  :at DATE|now         convert with the rates in effect on a day, like 2026-03-14
+  :offline [on|off]    use only the rates stored, toggles without argument
+  :help                show this help
+  :quit                exit, like Ctrl-D";
+
+/// File of the lines entered in previous sessions, next to the database
+fn history_path(ctxt: &MainContext) -> PathBuf {
+    ctxt.cfg.db_path.with_file_name("repl_history.txt")
+}
+
+pub(crate) fn run(ctxt: MainContext) -> Result<()> {
+    let history = history_path(&ctxt);
+    let mut editor = Editor::<()>::new();
+    if editor.load_history(&history).is_err() {
+        info!("No previous REPL history in {}", history.display());
+    }
+
+    let mut session = Session::new(ctxt);
+    eprintln!("Type :help for the commands, Ctrl-D to exit");
+    loop {
+        match editor.readline(&session.prompt()) {
+            Ok(line) => {
+                editor.add_history_entry(line.as_str());
+                match session.eval(&line) {
+                    Ok(Some(output)) if output.is_empty() => (),
+                    Ok(Some(output)) => println!("{}", output),
+                    Ok(None) => break,
+                    Err(err) => eprintln!("Error: {:#}", err),
+                }
+            }
+            // Ctrl-C drops the line being typed
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    if let Err(err) = editor.save_history(&history) {
+        warn!("Failed to save the REPL history: {}", err);
+    }
+    Ok(())
+}
+
+/// State of the REPL, changed by commands
+pub(crate) struct Session<'c> {
+    ctxt: MainContext<'c>,
+    engine: Engine<'c>,
+    /// Day of the rates, now if not set
+    at: Option<NaiveDate>,
+}
+
+impl<'c> Session<'c> {
+    pub fn new(ctxt: MainContext<'c>) -> Self {
+        Session {
+            ctxt,
+            engine: Engine::new(),
+            at: None,
+        }
+    }
+
+    fn prompt(&self) -> String {
+        let mut prompt = self
+            .ctxt
+            .destination_currencies
+            .iter()
+            .map(|c| c.get_main_iso())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(day) = self.at {
+            prompt.push_str(&format!(" @{}", day));
+        }
+        if self.ctxt.offline {
+            prompt.push_str(" (offline)");
+        }
+        prompt.push_str("> ");
+        prompt
+    }
+
+    /// Run a line, returning what to print, or None to exit
+    pub fn eval(&mut self, line: &str) -> Result<Option<String>> {
+        let line = line.trim();
+        trace!("REPL line: {}", line);
+        if !line.starts_with(':') {
+            return self.convert(line).map(Some);
+        }
+
+        let mut words = line[1..].split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let output = match command {
+            "to" => self.to(&args)?,
+            "rate" => self.rate(&args)?,
+            "at" => self.at(&args)?,
+            "offline" => self.offline(&args)?,
+            "help" | "h" => String::from(HELP),
+            "quit" | "q" | "exit" => return Ok(None),
+            _ => bail!(
+                "Unknown command ':{}', type :help for the commands",
+                command
+            ),
+        };
+        Ok(Some(output))
+    }
+
+    fn convert(&self, txt: &str) -> Result<String> {
+        if txt.is_empty() {
+            return Ok(String::new());
+        }
+        let all_conversions = convert_with(&self.ctxt, &self.engine, txt, None, self.at)?;
+        let history_conversions: Vec<HistoryConversion> =
+            all_conversions.iter().flatten().map(|c| c.into()).collect();
+        self.ctxt.db.add_to_history(txt, &history_conversions)?;
+        conversions_to_string(strings_of_conversions(&all_conversions, self.at))
+    }
+
+    fn to(&mut self, isos: &[&str]) -> Result<String> {
+        if !isos.is_empty() {
+            self.ctxt.destination_currencies = isos
+                .iter()
+                .map(|iso| currency_from_iso(iso))
+                .collect::<Result<_>>()?;
+        }
+        Ok(self
+            .ctxt
+            .destination_currencies
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(", "))
+    }
+
+    fn rate(&self, isos: &[&str]) -> Result<String> {
+        let (src, dsts) = match isos.split_first() {
+            Some((src, dsts)) => (currency_from_iso(src)?, dsts),
+            None => bail!("Usage: :rate SRC [DST…]"),
+        };
+        let dsts: Vec<&Currency> = if dsts.is_empty() {
+            self.ctxt.destination_currencies.clone()
+        } else {
+            dsts.iter()
+                .map(|iso| currency_from_iso(iso))
+                .collect::<Result<_>>()?
+        };
+        rates_to_string(&self.ctxt, src, &dsts, self.at)
+    }
+
+    fn at(&mut self, args: &[&str]) -> Result<String> {
+        match args {
+            [] => (),
+            ["now"] => self.at = None,
+            [day] => self.at = Some(day.parse()?),
+            _ => bail!("Usage: :at DATE|now"),
+        }
+        Ok(match self.at {
+            Some(day) => format!("Rates in effect on {}", day),
+            None => String::from("Current rates"),
+        })
+    }
+
+    fn offline(&mut self, args: &[&str]) -> Result<String> {
+        self.ctxt.offline = match args {
+            [] => !self.ctxt.offline,
+            ["on"] => true,
+            ["off"] => false,
+            _ => bail!("Usage: :offline [on|off]"),
+        };
+        Ok(String::from(if self.ctxt.offline {
+            "Offline, using the rates stored only"
+        } else {
+            "Online"
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::{self, fixtures, MockServer, Route};
+
+    fn session(server: &MockServer) -> Session<'static> {
+        Session::new(mock::context(server))
+    }
+
+    fn eval(session: &mut Session, line: &str) -> String {
+        session.eval(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn convert_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let mut session = session(&server);
+
+        assert_eq!(
+            eval(&mut session, "A coffee for 2 €"),
+            "EUR 2.00 ➜ USD 2.18\nEUR 2.00 ➜ GBP 1.71"
+        );
+        assert_eq!(eval(&mut session, "  "), "");
+        assert_eq!(
+            eval(&mut session, "10 EUR"),
+            "EUR 10.00 ➜ USD 10.88\nEUR 10.00 ➜ GBP 8.53"
+        );
+        // Rates of the first line are reused
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(session.ctxt.db.read_from_history_max(10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn to_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let mut session = session(&server);
+
+        assert_eq!(eval(&mut session, ":to GBP"), "GBP");
+        assert_eq!(session.prompt(), "GBP> ");
+        assert_eq!(eval(&mut session, "2 €"), "EUR 2.00 ➜ GBP 1.71");
+        assert_eq!(eval(&mut session, ":to"), "GBP");
+        assert!(session.eval(":to GBP ___").is_err());
+        assert_eq!(eval(&mut session, ":to"), "GBP");
+    }
+
+    #[test]
+    fn offline_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let mut session = session(&server);
+
+        eval(&mut session, ":offline");
+        assert_eq!(session.prompt(), "EUR USD GBP (offline)> ");
+        // Price tag found, but no rate to convert it
+        assert_eq!(eval(&mut session, "2 €"), "");
+        assert_eq!(
+            eval(&mut session, ":rate EUR GBP"),
+            "No rate found for EUR ➜ GBP"
+        );
+        assert!(server.requests().is_empty());
+
+        eval(&mut session, ":offline off");
+        eval(&mut session, "2 €");
+        assert_eq!(server.requests().len(), 1);
+
+        // Stored rates are used offline, in both directions
+        eval(&mut session, ":offline on");
+        assert_eq!(
+            eval(&mut session, "8.532 GBP"),
+            "GBP 8.53 ➜ EUR 10.00 (derived)"
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn commands_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let mut session = session(&server);
+
+        assert!(eval(&mut session, ":rate EUR USD").starts_with("1 EUR ≈ 1.088 USD"));
+        assert!(session.eval(":rate").is_err());
+        assert_eq!(
+            eval(&mut session, ":at 2026-03-14"),
+            "Rates in effect on 2026-03-14"
+        );
+        assert_eq!(session.prompt(), "EUR USD GBP @2026-03-14> ");
+        assert_eq!(eval(&mut session, ":at now"), "Current rates");
+        assert!(session.eval(":at yesterday").is_err());
+        assert!(session.eval(":unknown").is_err());
+        assert!(eval(&mut session, ":help").contains(":offline"));
+        assert_eq!(session.eval(":q").unwrap(), None);
+    }
+}
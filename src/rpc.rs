@@ -0,0 +1,477 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Rpc subcommand: JSON-RPC 2.0 on stdin and stdout, one message per line, for editors and other
+//! tools keeping sesters running
+//!
+//! Methods:
+//! - `extract` with `{"text": "…", "markup": "html"}`: price tags of the text, with their byte
+//!   offsets
+//! - `convert` with `{"amount": 12.5, "from": "EUR", "to": ["USD"], "at": "2026-03-14"}`:
+//!   conversions of an amount, `to` defaults to the target currencies and `at` to now
+//! - `currencies`: currencies known and target currencies
+//! - `set_targets` with `{"currencies": ["USD", "GBP"]}`: change the target currencies for the
+//!   next requests
+//!
+//! Requests without an id are notifications and get no response. The results of `extract` and
+//! `convert` are the same as the ones of the serve subcommand.
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use log::{debug, trace};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::api::RateError;
+use crate::currency::{self, Currency, PriceTag, ALL_CURRENCIES};
+use crate::markup::Markup;
+use crate::price_in_text::Engine;
+use crate::serve::{conversions, price_tags};
+use crate::MainContext;
+
+pub(crate) fn run(mut ctxt: MainContext) -> Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    answer_all(&mut ctxt, stdin.lock(), &mut stdout.lock())?;
+    Ok(())
+}
+
+/// Answer each line of input until its end. Lines that are not UTF-8 get a parse error
+fn answer_all(
+    ctxt: &mut MainContext,
+    mut input: impl BufRead,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let engine = Engine::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        let response = match std::str::from_utf8(&line) {
+            Ok(message) if message.trim().is_empty() => continue,
+            Ok(message) => {
+                let message = message.trim_end();
+                trace!("RPC message: {}", message);
+                answer(ctxt, &engine, message)
+            }
+            Err(err) => Some(error_response(
+                Value::Null,
+                &RpcError::Parse(format!("Invalid UTF-8, {}", err)),
+            )),
+        };
+        if let Some(response) = response {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+}
+
+/// Error answered to a request, with the codes of the JSON-RPC specification
+#[derive(Debug)]
+enum RpcError {
+    /// The message is not JSON
+    Parse(String),
+    /// The message is not a JSON-RPC request
+    InvalidRequest(String),
+    MethodNotFound(String),
+    InvalidParams(String),
+    /// Rates or the database could not be reached
+    Internal(anyhow::Error),
+}
+
+impl RpcError {
+    fn code(&self) -> i64 {
+        match self {
+            RpcError::Parse(_) => -32700,
+            RpcError::InvalidRequest(_) => -32600,
+            RpcError::MethodNotFound(_) => -32601,
+            RpcError::InvalidParams(_) => -32602,
+            RpcError::Internal(err) => match err.downcast_ref::<RateError>() {
+                Some(RateError::Db(_)) | None => -32603,
+                // The rate provider failed, in the range left to applications
+                Some(_) => -32000,
+            },
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Parse(message) => write!(f, "Parse error: {}", message),
+            RpcError::InvalidRequest(message) => write!(f, "Invalid request: {}", message),
+            RpcError::MethodNotFound(method) => write!(f, "Method not found: {}", method),
+            RpcError::InvalidParams(message) => write!(f, "Invalid params: {}", message),
+            RpcError::Internal(err) => write!(f, "{:#}", err),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        RpcError::Internal(err)
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Absent for notifications
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Response to a message, None for notifications
+fn answer(ctxt: &mut MainContext, engine: &Engine, message: &str) -> Option<Value> {
+    let (id, result) = match parse_request(message) {
+        Ok(request) => {
+            let result = call(ctxt, engine, &request.method, request.params);
+            if let Err(err) = &result {
+                debug!("RPC method {} failed: {}", request.method, err);
+            }
+            (request.id?, result)
+        }
+        Err((id, err)) => (id, Err(err)),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => error_response(id, &err),
+    })
+}
+
+fn error_response(id: Value, err: &RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": err.code(), "message": err.to_string() },
+    })
+}
+
+/// Request in a message, or the id to answer the error with
+fn parse_request(message: &str) -> Result<Request, (Value, RpcError)> {
+    let value: Value = serde_json::from_str(message)
+        .map_err(|err| (Value::Null, RpcError::Parse(err.to_string())))?;
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request: Request = serde_json::from_value(value)
+        .map_err(|err| (id.clone(), RpcError::InvalidRequest(err.to_string())))?;
+    if request.jsonrpc != "2.0" {
+        return Err((
+            id,
+            RpcError::InvalidRequest(format!("Unsupported version '{}'", request.jsonrpc)),
+        ));
+    }
+    Ok(request)
+}
+
+fn call(
+    ctxt: &mut MainContext,
+    engine: &Engine,
+    method: &str,
+    params: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "extract" => extract(engine, parse_params(params)?),
+        "convert" => convert(ctxt, parse_params(params)?),
+        "currencies" => Ok(currencies(ctxt)),
+        "set_targets" => set_targets(ctxt, parse_params(params)?),
+        _ => Err(RpcError::MethodNotFound(method.to_string())),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::InvalidParams(err.to_string()))
+}
+
+fn parse_currency(iso: &str) -> Result<&'static Currency, RpcError> {
+    currency::existing_from_iso(&iso.to_uppercase())
+        .ok_or_else(|| RpcError::InvalidParams(format!("Unknown currency '{}'", iso)))
+}
+
+#[derive(Deserialize)]
+struct ExtractParams {
+    text: String,
+    #[serde(default)]
+    markup: Option<String>,
+}
+
+fn extract(engine: &Engine, params: ExtractParams) -> Result<Value, RpcError> {
+    let markup = match &params.markup {
+        Some(markup) => Markup::from_str(markup, true)
+            .map_err(|_| RpcError::InvalidParams(format!("Unknown markup '{}'", markup)))?,
+        None => Markup::Text,
+    };
+    Ok(json!({ "price_tags": price_tags(engine, markup, &params.text) }))
+}
+
+#[derive(Deserialize)]
+struct ConvertParams {
+    amount: f64,
+    from: String,
+    /// Target currencies of the context if empty
+    #[serde(default)]
+    to: Vec<String>,
+    #[serde(default)]
+    at: Option<String>,
+}
+
+fn convert(ctxt: &MainContext, params: ConvertParams) -> Result<Value, RpcError> {
+    if !params.amount.is_finite() {
+        return Err(RpcError::InvalidParams(format!(
+            "Invalid amount '{}'",
+            params.amount
+        )));
+    }
+    let price_tag = PriceTag::new(parse_currency(&params.from)?, params.amount);
+    let dsts = if params.to.is_empty() {
+        ctxt.destination_currencies.clone()
+    } else {
+        params
+            .to
+            .iter()
+            .map(|iso| parse_currency(iso))
+            .collect::<Result<_, _>>()?
+    };
+    let at: Option<NaiveDate> = params
+        .at
+        .map(|day| {
+            day.parse().map_err(|_| {
+                RpcError::InvalidParams(format!("Invalid date '{}', expected YYYY-MM-DD", day))
+            })
+        })
+        .transpose()?;
+    Ok(conversions(ctxt, &price_tag, &dsts, at)?)
+}
+
+#[derive(Serialize)]
+struct CurrencyJson {
+    iso: &'static str,
+    isos: &'static [&'static str],
+    symbols: &'static [&'static str],
+    names: &'static [&'static str],
+    crypto: bool,
+}
+
+fn targets<'c>(ctxt: &MainContext<'c>) -> Vec<&'c str> {
+    ctxt.destination_currencies
+        .iter()
+        .map(|c| c.get_main_iso())
+        .collect()
+}
+
+fn currencies(ctxt: &MainContext) -> Value {
+    let all: &'static [Currency] = &ALL_CURRENCIES;
+    let currencies: Vec<CurrencyJson> = all
+        .iter()
+        .map(|c| CurrencyJson {
+            iso: c.isos()[0],
+            isos: c.isos(),
+            symbols: c.symbols(),
+            names: c.names(),
+            crypto: c.is_crypto(),
+        })
+        .collect();
+    json!({ "currencies": currencies, "targets": targets(ctxt) })
+}
+
+#[derive(Deserialize)]
+struct SetTargetsParams {
+    currencies: Vec<String>,
+}
+
+fn set_targets(ctxt: &mut MainContext, params: SetTargetsParams) -> Result<Value, RpcError> {
+    if params.currencies.is_empty() {
+        return Err(RpcError::InvalidParams(String::from(
+            "At least one currency expected",
+        )));
+    }
+    ctxt.destination_currencies = params
+        .currencies
+        .iter()
+        .map(|iso| parse_currency(iso))
+        .collect::<Result<_, _>>()?;
+    Ok(json!({ "targets": targets(ctxt) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::{context, fixtures, MockServer, Route};
+
+    /// Responses to each line of input
+    fn responses(ctxt: &mut MainContext, input: &str) -> Vec<Value> {
+        responses_to_bytes(ctxt, input.as_bytes())
+    }
+
+    fn responses_to_bytes(ctxt: &mut MainContext, input: &[u8]) -> Vec<Value> {
+        let mut output = Vec::new();
+        answer_all(ctxt, input, &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn extract_test() {
+        let server = MockServer::start(vec![]);
+        let mut ctxt = context(&server);
+
+        let responses = responses(
+            &mut ctxt,
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "extract", "params": {"text": "A coffee for 2 €"}}
+
+{"jsonrpc": "2.0", "id": "b", "method": "extract", "params": {"text": "<i>3 $</i>", "markup": "HTML"}}
+{"jsonrpc": "2.0", "id": 3, "method": "extract", "params": {"markup": "html"}}"#,
+        );
+        assert_eq!(
+            responses,
+            vec![
+                json!({"jsonrpc": "2.0", "id": 1, "result": {"price_tags": [
+                    {"amount": 2.0, "currency": "EUR", "start": 13, "end": 18}
+                ]}}),
+                json!({"jsonrpc": "2.0", "id": "b", "result": {"price_tags": [
+                    {"amount": 3.0, "currency": "USD", "start": 3, "end": 6}
+                ]}}),
+                json!({"jsonrpc": "2.0", "id": 3, "error": {
+                    "code": -32602, "message": "Invalid params: missing field `text`"
+                }}),
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let mut ctxt = context(&server);
+
+        let responses = responses(
+            &mut ctxt,
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "convert", "params": {"amount": 10, "from": "eur", "to": ["USD"]}}
+{"jsonrpc": "2.0", "id": 2, "method": "convert", "params": {"amount": 2, "from": "EUR"}}
+{"jsonrpc": "2.0", "id": 3, "method": "convert", "params": {"amount": 2, "from": "XYZ"}}
+{"jsonrpc": "2.0", "id": 4, "method": "convert", "params": {"amount": 2, "from": "EUR", "at": "soon"}}"#,
+        );
+        let result = &responses[0]["result"];
+        assert_eq!(result["from"], "EUR");
+        assert_eq!(result["conversions"].as_array().unwrap().len(), 1);
+        assert!((result["conversions"][0]["amount"].as_f64().unwrap() - 10.876).abs() < 1e-9);
+        // Target currencies by default, GBP retrieved in a second request
+        assert_eq!(
+            responses[1]["result"]["conversions"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(
+            responses[2]["error"]["message"],
+            "Invalid params: Unknown currency 'XYZ'"
+        );
+        assert_eq!(
+            responses[3]["error"]["message"],
+            "Invalid params: Invalid date 'soon', expected YYYY-MM-DD"
+        );
+    }
+
+    #[test]
+    fn targets_test() {
+        let server = MockServer::start(vec![]);
+        let mut ctxt = context(&server);
+
+        let responses = responses(
+            &mut ctxt,
+            r#"{"jsonrpc": "2.0", "method": "set_targets", "params": {"currencies": ["gbp", "JPY"]}}
+{"jsonrpc": "2.0", "id": 1, "method": "currencies"}
+{"jsonrpc": "2.0", "id": 2, "method": "set_targets", "params": {"currencies": []}}"#,
+        );
+        // No response to the notification
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["result"]["targets"], json!(["GBP", "JPY"]));
+        let currencies = responses[0]["result"]["currencies"].as_array().unwrap();
+        assert_eq!(currencies.len(), ALL_CURRENCIES.len());
+        assert_eq!(
+            currencies[0],
+            json!({"iso": "BTC", "isos": ["BTC", "XBT"], "symbols": ["₿", "฿", "Ƀ"],
+                   "names": ["Bitcoin"], "crypto": true})
+        );
+        assert_eq!(responses[1]["error"]["code"], -32602);
+        assert_eq!(ctxt.destination_currencies.len(), 2);
+    }
+
+    #[test]
+    fn invalid_messages_test() {
+        let server = MockServer::start(vec![Route::new("/api/v7/convert", 500, "Oops")]);
+        let mut ctxt = context(&server);
+
+        let responses = responses(
+            &mut ctxt,
+            r#"{"jsonrpc": "2.0", "id": 1, "method"
+{"jsonrpc": "1.0", "id": 2, "method": "currencies"}
+{"id": 3}
+{"jsonrpc": "2.0", "id": 4, "method": "shout"}
+{"jsonrpc": "2.0", "id": 5, "method": "convert", "params": {"amount": 1, "from": "EUR", "to": ["USD"]}}"#,
+        );
+        let errors: Vec<(&Value, &Value)> = responses
+            .iter()
+            .map(|r| (&r["id"], &r["error"]["code"]))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (&json!(null), &json!(-32700)),
+                (&json!(2), &json!(-32600)),
+                (&json!(3), &json!(-32600)),
+                (&json!(4), &json!(-32601)),
+                (&json!(5), &json!(-32000)),
+            ]
+        );
+    }
+
+    // Lines that are not UTF-8 are answered with a parse error, and the next ones still are
+    #[test]
+    fn invalid_utf8_test() {
+        let server = MockServer::start(vec![]);
+        let mut ctxt = context(&server);
+
+        let responses = responses_to_bytes(
+            &mut ctxt,
+            b"{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"\xff\xfe\"}\r\n\
+              {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"extract\", \"params\": {\"text\": \"2 \xe2\x82\xac\"}}",
+        );
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], json!(null));
+        assert_eq!(responses[0]["error"]["code"], -32700);
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(
+            responses[1]["result"]["price_tags"][0]["currency"],
+            json!("EUR")
+        );
+    }
+}
@@ -0,0 +1,417 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Serve subcommand: a local HTTP server answering JSON, for tools that need conversions without
+//! running sesters for each of them
+//!
+//! Endpoints:
+//! - `POST /extract` with `{"text": "…", "markup": "html"}`: price tags of the text
+//! - `GET /convert?amount=12.5&from=EUR&to=USD,GBP&at=2026-03-14`: conversions of an amount
+//! - `GET /rates?from=EUR&to=USD,GBP&at=2026-03-14`: rates from a currency
+//! - `GET /history?max=50`: latest history entries, most recent first, with their conversions
+//!
+//! `to` defaults to the target currencies and `at` to now.
+//!
+//! All requests share the database connection and the rate store of a single context, so that
+//! rates retrieved for a request are reused by the next ones: the connection pool has exactly one
+//! connection. Requests are thus answered one at a time, in the order they arrive. SQLite
+//! serializes writes anyway, and most requests are answered from the rates already stored.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use std::fmt;
+use std::io::Read;
+
+use crate::api::RateError;
+use crate::convert::{current_rates, historical_rates};
+use crate::currency::{self, Currency, PriceTag};
+use crate::db::history::{History, HistoryConversion};
+use crate::markup::{self, Markup};
+use crate::price_in_text::Engine;
+use crate::rate::Rate;
+use crate::MainContext;
+
+#[cfg(test)]
+mod tests;
+
+/// Largest request body accepted, in bytes
+const MAX_BODY_LEN: u64 = 1024 * 1024;
+
+/// Number of history entries returned when not set in the request, and at most
+const DEFAULT_HISTORY_MAX: usize = 50;
+const HISTORY_MAX: usize = 1000;
+
+pub(crate) fn run(ctxt: MainContext, listen: String) -> Result<()> {
+    let server =
+        Server::http(&listen).map_err(|err| anyhow!("Can’t listen on {}: {}", listen, err))?;
+    eprintln!("Listening on http://{}", server.server_addr());
+    serve(&ctxt, &server);
+    Ok(())
+}
+
+/// Answer requests one after the other, with the connection of the context, until the server is
+/// unblocked
+fn serve(ctxt: &MainContext, server: &Server) {
+    let engine = Engine::new();
+    for request in server.incoming_requests() {
+        handle(ctxt, &engine, request);
+    }
+}
+
+/// Error answered to a request
+#[derive(Debug)]
+enum ApiError {
+    /// Invalid parameters or body
+    BadRequest(String),
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    /// Rates or the database could not be reached
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    fn status(&self) -> u16 {
+        match self {
+            ApiError::BadRequest(_) => 400,
+            ApiError::NotFound => 404,
+            ApiError::MethodNotAllowed => 405,
+            ApiError::PayloadTooLarge => 413,
+            ApiError::Internal(err) => match err.downcast_ref::<RateError>() {
+                Some(RateError::Db(_)) | None => 500,
+                // The rate provider failed
+                Some(_) => 502,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message) => write!(f, "{}", message),
+            ApiError::NotFound => write!(f, "Not found"),
+            ApiError::MethodNotAllowed => write!(f, "Method not allowed"),
+            ApiError::PayloadTooLarge => {
+                write!(f, "Body larger than {} bytes", MAX_BODY_LEN)
+            }
+            ApiError::Internal(err) => write!(f, "{:#}", err),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        ApiError::Internal(err)
+    }
+}
+
+fn handle(ctxt: &MainContext, engine: &Engine, mut request: Request) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    let (status, body) = match respond(ctxt, engine, &mut request) {
+        Ok(body) => (200, body),
+        Err(err) => (err.status(), json!({ "error": err.to_string() })),
+    };
+    info!("{} {} {}", method, url, status);
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(err) = request.respond(response) {
+        warn!("Failed to answer {} {}: {}", method, url, err);
+    }
+}
+
+fn respond(ctxt: &MainContext, engine: &Engine, request: &mut Request) -> Result<Value, ApiError> {
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+    let params = Params::new(&query);
+    match (request.method(), path.as_str()) {
+        (Method::Post, "/extract") => extract(engine, &body(request)?),
+        (Method::Get, "/convert") => convert(ctxt, &params),
+        (Method::Get, "/rates") => rates(ctxt, &params),
+        (Method::Get, "/history") => history(ctxt, &params),
+        (_, "/extract") | (_, "/convert") | (_, "/rates") | (_, "/history") => {
+            Err(ApiError::MethodNotAllowed)
+        }
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+/// Body of a request, as text
+fn body(request: &mut Request) -> Result<String, ApiError> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_LEN + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| ApiError::BadRequest(format!("Can’t read the body: {}", err)))?;
+    if body.len() as u64 > MAX_BODY_LEN {
+        return Err(ApiError::PayloadTooLarge);
+    }
+    String::from_utf8(body).map_err(|_| ApiError::BadRequest(String::from("Body is not UTF-8")))
+}
+
+/// Parameters in the query of a request
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn new(query: &str) -> Self {
+        Params(
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect(),
+        )
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required(&self, name: &str) -> Result<&str, ApiError> {
+        self.get(name)
+            .ok_or_else(|| ApiError::BadRequest(format!("Missing parameter '{}'", name)))
+    }
+
+    /// Source currency, in the from parameter
+    fn src(&self) -> Result<&'static Currency, ApiError> {
+        parse_currency(self.required("from")?)
+    }
+
+    /// Destination currencies, in repeated or comma separated to parameters. The ones of the
+    /// context if there is none
+    fn dsts<'c>(&self, ctxt: &MainContext<'c>) -> Result<Vec<&'c Currency>, ApiError> {
+        let isos: Vec<&str> = self
+            .0
+            .iter()
+            .filter(|(n, _)| n == "to")
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|iso| !iso.is_empty())
+            .collect();
+        if isos.is_empty() {
+            return Ok(ctxt.destination_currencies.clone());
+        }
+        isos.into_iter().map(parse_currency).collect()
+    }
+
+    /// Day of the rates, in the at parameter
+    fn at(&self) -> Result<Option<NaiveDate>, ApiError> {
+        self.get("at")
+            .map(|day| {
+                day.parse().map_err(|_| {
+                    ApiError::BadRequest(format!("Invalid date '{}', expected YYYY-MM-DD", day))
+                })
+            })
+            .transpose()
+    }
+}
+
+fn parse_currency(iso: &str) -> Result<&'static Currency, ApiError> {
+    currency::existing_from_iso(&iso.to_uppercase())
+        .ok_or_else(|| ApiError::BadRequest(format!("Unknown currency '{}'", iso)))
+}
+
+#[derive(Deserialize)]
+struct ExtractRequest {
+    text: String,
+    #[serde(default)]
+    markup: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PriceTagJson {
+    amount: f64,
+    currency: String,
+    /// Byte offsets of the price tag in the document
+    start: usize,
+    end: usize,
+}
+
+fn extract(engine: &Engine, body: &str) -> Result<Value, ApiError> {
+    let request: ExtractRequest = serde_json::from_str(body)
+        .map_err(|err| ApiError::BadRequest(format!("Invalid body: {}", err)))?;
+    let markup = match &request.markup {
+        Some(markup) => Markup::from_str(markup, true)
+            .map_err(|_| ApiError::BadRequest(format!("Unknown markup '{}'", markup)))?,
+        None => Markup::Text,
+    };
+
+    Ok(json!({ "price_tags": price_tags(engine, markup, &request.text) }))
+}
+
+/// Price tags of a document, located in it
+pub(crate) fn price_tags(engine: &Engine, markup: Markup, doc: &str) -> Vec<PriceTagJson> {
+    let extracted = markup::extract(markup, doc);
+    engine
+        .located_price_tags(extracted.text())
+        .into_iter()
+        .map(|(location, price_tag)| {
+            let original = extracted.original(location.span());
+            PriceTagJson {
+                amount: price_tag.amount(),
+                currency: price_tag.currency().get_main_iso().to_string(),
+                start: original.start,
+                end: original.end,
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct RateJson {
+    src: String,
+    dst: String,
+    rate: f64,
+    provider: String,
+    date: DateTime<Utc>,
+    derived: bool,
+}
+
+impl From<&Rate<'_>> for RateJson {
+    fn from(rate: &Rate) -> Self {
+        RateJson {
+            src: rate.src().get_main_iso().to_string(),
+            dst: rate.dst().get_main_iso().to_string(),
+            rate: rate.rate(),
+            provider: rate.provider().to_string(),
+            date: *rate.date(),
+            derived: rate.is_derived(),
+        }
+    }
+}
+
+/// Rates from src to each of dsts, with the destination currencies without a rate
+fn rates_to<'c>(
+    ctxt: &MainContext,
+    src: &'c Currency,
+    dsts: &[&'c Currency],
+    at: Option<NaiveDate>,
+) -> Result<(Vec<Rate<'c>>, Vec<String>)> {
+    let dsts: Vec<&Currency> = dsts.iter().copied().filter(|dst| *dst != src).collect();
+    let rates = match at {
+        Some(day) => historical_rates(ctxt, src, &dsts, day)?,
+        None => current_rates(ctxt, src, &dsts)?,
+    };
+    let missing = dsts
+        .iter()
+        .zip(&rates)
+        .filter(|(_, rate)| rate.is_none())
+        .map(|(dst, _)| dst.get_main_iso().to_string())
+        .collect();
+    Ok((rates.into_iter().flatten().collect(), missing))
+}
+
+fn convert(ctxt: &MainContext, params: &Params) -> Result<Value, ApiError> {
+    let amount = params.required("amount")?;
+    let amount: f64 = match amount.parse() {
+        Ok(amount) if f64::is_finite(amount) => amount,
+        _ => return Err(ApiError::BadRequest(format!("Invalid amount '{}'", amount))),
+    };
+    let price_tag = PriceTag::new(params.src()?, amount);
+    Ok(conversions(
+        ctxt,
+        &price_tag,
+        &params.dsts(ctxt)?,
+        params.at()?,
+    )?)
+}
+
+/// Conversions of a price tag to each of dsts, with the destination currencies without a rate
+pub(crate) fn conversions(
+    ctxt: &MainContext,
+    price_tag: &PriceTag,
+    dsts: &[&Currency],
+    at: Option<NaiveDate>,
+) -> Result<Value> {
+    let (rates, missing) = rates_to(ctxt, price_tag.currency(), dsts, at)?;
+    let conversions: Vec<Value> = rates
+        .iter()
+        .filter_map(|rate| {
+            let quote = price_tag.convert(rate, None).ok()?;
+            Some(json!({
+                "to": rate.dst().get_main_iso(),
+                "amount": quote.mid_market().amount(),
+                "rate": RateJson::from(rate),
+            }))
+        })
+        .collect();
+    Ok(json!({
+        "amount": price_tag.amount(),
+        "from": price_tag.currency().get_main_iso(),
+        "conversions": conversions,
+        "missing": missing,
+    }))
+}
+
+fn rates(ctxt: &MainContext, params: &Params) -> Result<Value, ApiError> {
+    let src = params.src()?;
+    let (rates, missing) = rates_to(ctxt, src, &params.dsts(ctxt)?, params.at()?)?;
+    let rates: Vec<RateJson> = rates.iter().map(RateJson::from).collect();
+    Ok(json!({
+        "from": src.get_main_iso(),
+        "rates": rates,
+        "missing": missing,
+    }))
+}
+
+#[derive(Serialize)]
+struct HistoryJson {
+    #[serde(flatten)]
+    entry: History,
+    conversions: Vec<HistoryConversion>,
+}
+
+fn history(ctxt: &MainContext, params: &Params) -> Result<Value, ApiError> {
+    let max = match params.get("max") {
+        None => DEFAULT_HISTORY_MAX,
+        Some(max) => match max.parse() {
+            Ok(max) if (1..=HISTORY_MAX).contains(&max) => max,
+            _ => {
+                return Err(ApiError::BadRequest(format!(
+                    "Invalid max '{}', expected a number from 1 to {}",
+                    max, HISTORY_MAX
+                )))
+            }
+        },
+    };
+
+    let entries = ctxt
+        .db
+        .read_latest_history(max as u32)?
+        .into_iter()
+        .map(|entry| {
+            let conversions = ctxt.db.read_history_conversions(entry.rowid)?;
+            Ok(HistoryJson { entry, conversions })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(json!({ "entries": entries }))
+}
@@ -0,0 +1,224 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests of the serve subcommand, with the server on an ephemeral port and providers replaced by
+//! a mock server
+
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use super::*;
+use crate::api::mock::{self, fixtures, MockServer, Route};
+
+/// Server answering requests in another thread, stopped when dropped
+struct TestServer {
+    server: Arc<Server>,
+    handle: Option<JoinHandle<()>>,
+    /// Replaces rate providers
+    _providers: MockServer,
+}
+
+impl TestServer {
+    fn start(routes: Vec<Route>) -> Self {
+        Self::start_with(routes, |_| {})
+    }
+
+    /// Server with a context prepared by setup before the first request
+    fn start_with(routes: Vec<Route>, setup: fn(&MainContext)) -> Self {
+        let providers = MockServer::start(routes);
+        let cfg = mock::config(&providers);
+
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let handle = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                let ctxt = mock::context_with(cfg);
+                setup(&ctxt);
+                serve(&ctxt, &server);
+            })
+        };
+        TestServer {
+            server,
+            handle: Some(handle),
+            _providers: providers,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.server.server_addr(), path)
+    }
+
+    /// Status and body of the response
+    fn get(&self, path: &str) -> (u16, Value) {
+        response(ureq::get(&self.url(path)).call())
+    }
+
+    fn post(&self, path: &str, body: &str) -> (u16, Value) {
+        response(ureq::post(&self.url(path)).send_string(body))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+fn response(result: Result<ureq::Response, ureq::Error>) -> (u16, Value) {
+    let response = match result {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => panic!("{}", err),
+    };
+    assert_eq!(response.content_type(), "application/json");
+    let status = response.status();
+    (
+        status,
+        serde_json::from_str(&response.into_string().unwrap()).unwrap(),
+    )
+}
+
+#[test]
+fn extract_test() {
+    let server = TestServer::start(vec![]);
+
+    let (status, body) = server.post("/extract", r#"{"text": "A coffee for 2 €"}"#);
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({"price_tags": [{"amount": 2.0, "currency": "EUR", "start": 13, "end": 18}]})
+    );
+
+    let (status, body) = server.post(
+        "/extract",
+        r#"{"text": "<b>19<sup>99</sup>&nbsp;&euro;</b>", "markup": "html"}"#,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({"price_tags": [{"amount": 19.99, "currency": "EUR", "start": 3, "end": 30}]})
+    );
+
+    let (status, _) = server.post("/extract", r#"{"text": "2 €", "markup": "pdf"}"#);
+    assert_eq!(status, 400);
+    let (status, body) = server.post("/extract", "2 €");
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap().starts_with("Invalid body"));
+    let (status, _) = server.get("/extract");
+    assert_eq!(status, 405);
+}
+
+#[test]
+fn convert_test() {
+    let server = TestServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+
+    let (status, body) = server.get("/convert?amount=10&from=eur&to=USD&to=GBP");
+    assert_eq!(status, 200);
+    assert_eq!(body["from"], "EUR");
+    let conversions = body["conversions"].as_array().unwrap();
+    assert_eq!(conversions.len(), 2);
+    assert_eq!(conversions[0]["to"], "USD");
+    assert!((conversions[0]["amount"].as_f64().unwrap() - 10.876).abs() < 1e-9);
+    assert_eq!(conversions[0]["rate"]["src"], "EUR");
+    assert_eq!(conversions[1]["to"], "GBP");
+    assert_eq!(body["missing"], json!([]));
+
+    // Target currencies by default, rates from the database
+    let (status, body) = server.get("/convert?amount=2.5&from=EUR");
+    assert_eq!(status, 200);
+    assert_eq!(body["conversions"].as_array().unwrap().len(), 2);
+
+    for (path, error) in &[
+        ("/convert?from=EUR", "Missing parameter 'amount'"),
+        ("/convert?amount=abc&from=EUR", "Invalid amount 'abc'"),
+        ("/convert?amount=inf&from=EUR", "Invalid amount 'inf'"),
+        ("/convert?amount=1&from=XYZ", "Unknown currency 'XYZ'"),
+        (
+            "/convert?amount=1&from=EUR&at=yesterday",
+            "Invalid date 'yesterday', expected YYYY-MM-DD",
+        ),
+    ] {
+        let (status, body) = server.get(path);
+        assert_eq!(status, 400, "{}", path);
+        assert_eq!(body["error"], *error, "{}", path);
+    }
+}
+
+#[test]
+fn rates_test() {
+    let server = TestServer::start(vec![
+        Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR),
+        Route::ok("/stats/eurofxref/eurofxref-hist", fixtures::ECB_HIST_90D),
+    ]);
+
+    let (status, body) = server.get("/rates?from=EUR&to=USD,GBP,EUR");
+    assert_eq!(status, 200);
+    let rates = body["rates"].as_array().unwrap();
+    assert_eq!(rates.len(), 2);
+    assert_eq!(rates[0]["dst"], "USD");
+    assert_eq!(rates[0]["rate"], 1.0876);
+    assert_eq!(rates[0]["derived"], false);
+
+    let (status, body) = server.get("/rates?from=EUR&to=USD&at=2026-10-15");
+    assert_eq!(status, 200);
+    assert_eq!(body["rates"][0]["rate"], 1.25);
+}
+
+#[test]
+fn provider_error_test() {
+    let server = TestServer::start(vec![Route::new("/api/v7/convert", 500, "Oops")]);
+    let (status, body) = server.get("/rates?from=EUR&to=USD");
+    assert_eq!(status, 502);
+    assert!(body["error"].as_str().is_some());
+}
+
+#[test]
+fn history_test() {
+    let server = TestServer::start(vec![]);
+
+    let (status, body) = server.get("/history");
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({"entries": []}));
+
+    let (status, _) = server.get("/history?max=0");
+    assert_eq!(status, 400);
+    let (status, _) = server.get("/nowhere");
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn history_latest_test() {
+    let server = TestServer::start_with(vec![], |ctxt| {
+        for entry in &["first", "second", "third"] {
+            ctxt.db.add_to_history(entry, &[]).unwrap();
+        }
+    });
+
+    let (status, body) = server.get("/history?max=2");
+    assert_eq!(status, 200);
+    let contents: Vec<&Value> = body["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| &entry["content"])
+        .collect();
+    assert_eq!(contents, [&json!("third"), &json!("second")]);
+}
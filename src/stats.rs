@@ -0,0 +1,233 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Statistics on the rates stored over a period, for the rate subcommand
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use term_table::{row::Row, Table};
+
+use crate::currency::Currency;
+use crate::MainContext;
+
+/// Longest sparkline, in characters. Longer series are averaged over buckets of days
+const SPARKLINE_WIDTH: usize = 30;
+
+/// Bars of the sparkline, from the lowest value to the highest
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Number of days in a period like 90d, 12w, 6m or 1y. Months count 30 days and years 365
+pub(crate) fn parse_period(s: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid period '{}', expected a number of days like 90d", s);
+    let s = s.trim();
+    let (number, days_per_unit) = match s.char_indices().last() {
+        Some((i, 'd')) => (&s[..i], 1),
+        Some((i, 'w')) => (&s[..i], 7),
+        Some((i, 'm')) => (&s[..i], 30),
+        Some((i, 'y')) => (&s[..i], 365),
+        _ => (s, 1),
+    };
+    let number: u32 = number.parse().map_err(|_| invalid())?;
+    match number.checked_mul(days_per_unit) {
+        Some(days) if days > 0 => Ok(days),
+        _ => Err(invalid()),
+    }
+}
+
+/// Print statistics on the rates from src to each of dsts over the last days, from the rates
+/// stored only
+pub(crate) fn run(ctxt: &MainContext, src: &Currency, dsts: &[&Currency], days: u32) -> Result<()> {
+    let to = Utc::today().naive_utc();
+    let from = to - Duration::days(days.into());
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        "Pair",
+        "Days",
+        "Min",
+        "Max",
+        "Mean",
+        "Change",
+        "Volatility",
+        "Trend",
+    ]));
+    for dst in dsts.iter().copied().filter(|dst| *dst != src) {
+        let rates: Vec<f64> = ctxt
+            .db
+            .get_rate_series(src, dst, from, to)?
+            .iter()
+            .map(|rate| rate.rate())
+            .collect();
+        let pair = format!("{} ➜ {}", src, dst);
+        let stats = match Stats::new(&rates) {
+            Some(stats) => stats,
+            None => {
+                table.add_row(Row::new(vec![pair, String::from("no rate stored")]));
+                continue;
+            }
+        };
+
+        let precision = dst.decimals().max(3);
+        let percent = |p: Option<f64>| p.map_or(String::from("-"), |p| format!("{:+.2}%", p));
+        table.add_row(Row::new(vec![
+            pair,
+            rates.len().to_string(),
+            format!("{:.*}", precision, stats.min),
+            format!("{:.*}", precision, stats.max),
+            format!("{:.*}", precision, stats.mean),
+            percent(stats.change_percent()),
+            stats
+                .volatility
+                .map_or(String::from("-"), |v| format!("{:.2}%", v)),
+            sparkline(&rates, SPARKLINE_WIDTH),
+        ]));
+    }
+
+    println!("Rates stored over the last {} days", days);
+    println!("{}", table.render());
+    Ok(())
+}
+
+/// Summary of a series of rates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub first: f64,
+    pub last: f64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Standard deviation of the changes from a rate to the next, in percent. None with less than
+    /// three rates
+    pub volatility: Option<f64>,
+}
+
+impl Stats {
+    /// Statistics on rates, oldest first. None if there is no rate
+    pub fn new(rates: &[f64]) -> Option<Self> {
+        let first = *rates.first()?;
+        let last = *rates.last()?;
+        let min = rates.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = rates.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+
+        let changes: Vec<f64> = rates
+            .windows(2)
+            .filter(|w| w[0] != 0.)
+            .map(|w| (w[1] - w[0]) / w[0] * 100.)
+            .collect();
+        let volatility = if changes.len() < 2 {
+            None
+        } else {
+            let n = changes.len() as f64;
+            let mean_change = changes.iter().sum::<f64>() / n;
+            let variance = changes
+                .iter()
+                .map(|c| (c - mean_change).powi(2))
+                .sum::<f64>()
+                / (n - 1.);
+            Some(variance.sqrt())
+        };
+
+        Some(Stats {
+            first,
+            last,
+            min,
+            max,
+            mean,
+            volatility,
+        })
+    }
+
+    /// Change from the first rate to the last, in percent. None if the first rate is zero
+    pub fn change_percent(&self) -> Option<f64> {
+        if self.first == 0. {
+            None
+        } else {
+            Some((self.last - self.first) / self.first * 100.)
+        }
+    }
+}
+
+/// Sparkline of values, at most width characters long. Consecutive values are averaged when
+/// there are more values than characters
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+    let buckets = values.len().div_ceil(width);
+    let values: Vec<f64> = values
+        .chunks(buckets)
+        .map(|chunk| chunk.iter().sum::<f64>() / chunk.len() as f64)
+        .collect();
+
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|v| {
+            if max == min {
+                SPARKS[SPARKS.len() / 2]
+            } else {
+                let i = ((v - min) / (max - min) * (SPARKS.len() - 1) as f64).round();
+                SPARKS[i as usize]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_period_test() {
+        assert_eq!(parse_period("90d"), Ok(90));
+        assert_eq!(parse_period("90"), Ok(90));
+        assert_eq!(parse_period("2w"), Ok(14));
+        assert_eq!(parse_period("6m"), Ok(180));
+        assert_eq!(parse_period("1y"), Ok(365));
+        for invalid in &["", "d", "0d", "-3d", "3h", "1.5y", "99999999999y"] {
+            assert!(parse_period(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn stats_test() {
+        assert_eq!(Stats::new(&[]), None);
+
+        let stats = Stats::new(&[1., 1.1, 0.99, 1.2]).unwrap();
+        assert_eq!((stats.first, stats.last), (1., 1.2));
+        assert_eq!((stats.min, stats.max), (0.99, 1.2));
+        assert!((stats.mean - 1.0725).abs() < 1e-9);
+        assert!((stats.change_percent().unwrap() - 20.).abs() < 1e-9);
+        // Changes of +10%, -10% and about +21.21%
+        assert!((stats.volatility.unwrap() - 15.81).abs() < 0.01);
+
+        let stats = Stats::new(&[1., 2.]).unwrap();
+        assert_eq!(stats.volatility, None);
+    }
+
+    #[test]
+    fn sparkline_test() {
+        assert_eq!(sparkline(&[], 10), "");
+        assert_eq!(sparkline(&[1., 2., 3., 4., 5., 6., 7., 8.], 10), "▁▂▃▄▅▆▇█");
+        assert_eq!(sparkline(&[3., 3.], 10), "▅▅");
+        // Averaged by two
+        assert_eq!(sparkline(&[1., 1., 8., 8., 1., 1.], 3), "▁█▁");
+    }
+}
@@ -0,0 +1,153 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Stores caching the current rates retrieved from rate sources
+//!
+//! Backends:
+//! - the SQLite database of the context, the default,
+//! - [`MemoryStore`], lost when dropped,
+//! - [`SnapshotStore`], rates loaded from a JSON file, with the rates retrieved since kept in memory.
+//!
+//! The backend is selected with `rate_store` in the configuration. The rate series, the history,
+//! the alerts and the requests sent to providers are always kept in the database.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::trace;
+use serde_derive::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+
+use crate::currency::Currency;
+use crate::rate::Rate;
+
+mod memory;
+mod snapshot;
+#[cfg(test)]
+mod tests;
+
+pub use memory::MemoryStore;
+pub use snapshot::SnapshotStore;
+
+/// Backend of the rate store, in the configuration
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StoreConfig {
+    /// Rates cached in the database
+    #[default]
+    Sqlite,
+    /// Rates cached in memory, retrieved again on each run
+    Memory,
+    /// Rates read from a JSON file, never updated, and rates retrieved since kept in memory
+    Snapshot { path: PathBuf },
+}
+
+/// Store for the configuration, None for the database
+pub(crate) fn from_config(cfg: &StoreConfig) -> Result<Option<Box<dyn RateStore>>> {
+    Ok(match cfg {
+        StoreConfig::Sqlite => None,
+        StoreConfig::Memory => Some(Box::new(MemoryStore::default())),
+        StoreConfig::Snapshot { path } => Some(Box::new(SnapshotStore::open(path)?)),
+    })
+}
+
+/// Cache of the current rates, with at most one rate per pair and provider
+pub trait RateStore {
+    /// Up-to-date rates from a currency to another, of a provider
+    fn get_uptodate_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Rate<'c>>>;
+
+    /// Most recent rate stored for a pair or for the reverse pair, even if it is outdated
+    fn get_latest_rate<'c>(&self, src: &'c Currency, dst: &'c Currency)
+        -> Result<Option<Rate<'c>>>;
+
+    /// Store rates, replacing the ones of the same pair and provider. Rates need a cache until
+    /// date, none is stored if one of them lacks it
+    fn put_rates(&self, rates: &[Rate]) -> Result<()>;
+
+    /// Removes outdated rates of a pair and a provider. Returns the number of rates removed
+    fn remove_outdated_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<usize>;
+
+    /// Retrieve an up-to-date rate from a currency to another, derived from stored rates: either
+    /// the inverse of the reverse pair or a chain of rates through one or two pivot currencies.
+    /// Pivots are tried in the order given.
+    fn get_uptodate_derived_rate<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        pivots: &[&'c Currency],
+        now: DateTime<Utc>,
+    ) -> Result<Option<Rate<'c>>> {
+        trace!(
+            "get_uptodate_derived_rate({}, {}, {:?})",
+            src,
+            dst,
+            provider
+        );
+        // Stored rate from a to b, or inverse of the stored rate from b to a
+        let leg = |a: &'c Currency, b: &'c Currency| -> Result<Option<Rate<'c>>> {
+            if let Some(rate) = self.get_uptodate_rates(a, b, provider, now)?.pop() {
+                return Ok(Some(rate));
+            }
+            Ok(self
+                .get_uptodate_rates(b, a, provider, now)?
+                .pop()
+                .map(|rate| rate.inverse()))
+        };
+
+        if let Some(rate) = self.get_uptodate_rates(dst, src, provider, now)?.pop() {
+            return Ok(Some(rate.inverse()));
+        }
+
+        let pivots: Vec<&Currency> = pivots
+            .iter()
+            .copied()
+            .filter(|p| *p != src && *p != dst)
+            .collect();
+
+        for pivot in &pivots {
+            if let (Some(first), Some(second)) = (leg(src, pivot)?, leg(pivot, dst)?) {
+                return Ok(first.through(&second));
+            }
+        }
+
+        for p1 in &pivots {
+            for p2 in pivots.iter().filter(|p2| *p2 != p1) {
+                if let (Some(first), Some(second), Some(third)) =
+                    (leg(src, p1)?, leg(p1, p2)?, leg(p2, dst)?)
+                {
+                    return Ok(first.through(&second).and_then(|r| r.through(&third)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
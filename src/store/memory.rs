@@ -0,0 +1,134 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Rate store in memory
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::{trace, warn};
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::{Mutex, MutexGuard};
+
+use super::RateStore;
+use crate::currency::Currency;
+use crate::db::rate::RateInternal;
+use crate::rate::Rate;
+
+/// Pair and provider of a rate
+type Key = (String, String, String);
+
+fn key(src: &Currency, dst: &Currency, provider: &str) -> Key {
+    (
+        src.get_main_iso().to_string(),
+        dst.get_main_iso().to_string(),
+        provider.to_string(),
+    )
+}
+
+/// Rates kept in memory, lost when the store is dropped
+#[derive(Default)]
+pub struct MemoryStore {
+    rates: Mutex<BTreeMap<Key, RateInternal>>,
+}
+
+impl MemoryStore {
+    fn rates(&self) -> MutexGuard<'_, BTreeMap<Key, RateInternal>> {
+        // Rates are only replaced as a whole, a panic can’t leave one half written
+        self.rates.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Rates stored, in no particular order
+    pub(crate) fn internal_rates(&self) -> Vec<RateInternal> {
+        self.rates().values().cloned().collect()
+    }
+}
+
+impl RateStore for MemoryStore {
+    fn get_uptodate_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Rate<'c>>> {
+        trace!("get_rates_uptodate_rates({}, {}, {:?})", src, dst, provider);
+        if src == dst {
+            return Ok(vec![Rate::parity(src)]);
+        }
+        match self.rates().get(&key(src, dst, provider)) {
+            Some(rate) if rate.cache_until > now => Ok(vec![rate.clone().try_into()?]),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn get_latest_rate<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+    ) -> Result<Option<Rate<'c>>> {
+        let (src, dst) = (src.get_main_iso(), dst.get_main_iso());
+        let latest = self
+            .rates()
+            .values()
+            .filter(|rate| {
+                (rate.src == src && rate.dst == dst) || (rate.src == dst && rate.dst == src)
+            })
+            .max_by_key(|rate| rate.date)
+            .cloned();
+        Ok(latest.map(|rate| rate.try_into()).transpose()?)
+    }
+
+    fn put_rates(&self, rates: &[Rate]) -> Result<()> {
+        let rates = rates
+            .iter()
+            .filter(|rate| {
+                if rate.src() == rate.dst() {
+                    warn!("Same source and destination currency, don’t store");
+                }
+                rate.src() != rate.dst()
+            })
+            .map(|rate| rate.try_into())
+            .collect::<Result<Vec<RateInternal>, _>>()?;
+        let mut stored = self.rates();
+        for rate in rates {
+            let key = (rate.src.clone(), rate.dst.clone(), rate.provider.clone());
+            stored.insert(key, rate);
+        }
+        Ok(())
+    }
+
+    fn remove_outdated_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        let mut rates = self.rates();
+        let key = key(src, dst, provider);
+        match rates.get(&key) {
+            Some(rate) if rate.cache_until <= now => {
+                rates.remove(&key);
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
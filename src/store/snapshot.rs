@@ -0,0 +1,122 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Read-only rate store, loaded from a JSON file
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::{MemoryStore, RateStore};
+use crate::currency::Currency;
+use crate::db::rate::RateInternal;
+use crate::rate::Rate;
+
+/// Rates read from a JSON array of objects like
+/// `{"src": "EUR", "dst": "USD", "date": "2026-10-16T16:00:00Z", "rate": 1.0876,
+/// "provider": "ECB", "cache_until": "2026-10-17T16:00:00Z"}`.
+///
+/// Rates put in the store are kept in memory on top of the snapshot, until the store is dropped.
+/// Rates of the snapshot are never removed, even when outdated, and the file is never changed
+pub struct SnapshotStore {
+    rates: MemoryStore,
+    /// Rates put in the store since the snapshot was read
+    fetched: MemoryStore,
+}
+
+impl SnapshotStore {
+    /// Snapshot in a JSON file
+    pub fn open(path: &Path) -> Result<Self> {
+        info!("Reading rate snapshot {}", path.display());
+        let file = File::open(path)
+            .with_context(|| format!("Can’t open the rate snapshot {}", path.display()))?;
+        Self::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid rate snapshot {}", path.display()))
+    }
+
+    /// Snapshot in JSON, read from reader
+    pub fn from_reader(reader: impl Read) -> Result<Self> {
+        let rates: Vec<RateInternal> = serde_json::from_reader(reader)?;
+        let store = MemoryStore::default();
+        for rate in rates {
+            let rate: Rate = rate.try_into()?;
+            store.put_rates(&[rate])?;
+        }
+        Ok(SnapshotStore {
+            rates: store,
+            fetched: MemoryStore::default(),
+        })
+    }
+
+    /// Snapshot of some rates, as JSON
+    pub fn write(rates: &[Rate], writer: impl std::io::Write) -> Result<()> {
+        let store = MemoryStore::default();
+        store.put_rates(rates)?;
+        serde_json::to_writer_pretty(writer, &store.internal_rates())?;
+        Ok(())
+    }
+}
+
+impl RateStore for SnapshotStore {
+    fn get_uptodate_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Rate<'c>>> {
+        let fetched = self.fetched.get_uptodate_rates(src, dst, provider, now)?;
+        if !fetched.is_empty() {
+            return Ok(fetched);
+        }
+        self.rates.get_uptodate_rates(src, dst, provider, now)
+    }
+
+    fn get_latest_rate<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+    ) -> Result<Option<Rate<'c>>> {
+        let fetched = self.fetched.get_latest_rate(src, dst)?;
+        let snapshot = self.rates.get_latest_rate(src, dst)?;
+        Ok(match (fetched, snapshot) {
+            (Some(fetched), Some(snapshot)) if snapshot.date() > fetched.date() => Some(snapshot),
+            (fetched, snapshot) => fetched.or(snapshot),
+        })
+    }
+
+    fn put_rates(&self, rates: &[Rate]) -> Result<()> {
+        debug!("Keeping {} rates in memory, over the snapshot", rates.len());
+        self.fetched.put_rates(rates)
+    }
+
+    fn remove_outdated_rates<'c>(
+        &self,
+        src: &'c Currency,
+        dst: &'c Currency,
+        provider: &str,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        self.fetched.remove_outdated_rates(src, dst, provider, now)
+    }
+}
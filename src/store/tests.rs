@@ -0,0 +1,292 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests shared by all rate stores, and tests specific to some of them
+
+use chrono::{Duration, Utc};
+use test_case::test_case;
+
+use super::*;
+use crate::api::mock::{context, fixtures, MockServer, Route};
+use crate::config::Config;
+use crate::currency::{CHF, EUR, GBP, JPY, USD};
+use crate::db::Db;
+use crate::MainContext;
+
+/// Store holding the rates given
+type Seeded = fn(&[Rate]) -> Box<dyn RateStore>;
+
+fn sqlite_store(rates: &[Rate]) -> Box<dyn RateStore> {
+    let db = Db::new_in_memory().unwrap();
+    db.put_rates(rates).unwrap();
+    Box::new(db)
+}
+
+fn memory_store(rates: &[Rate]) -> Box<dyn RateStore> {
+    let store = MemoryStore::default();
+    store.put_rates(rates).unwrap();
+    Box::new(store)
+}
+
+fn snapshot_store(rates: &[Rate]) -> Box<dyn RateStore> {
+    let mut json = Vec::new();
+    SnapshotStore::write(rates, &mut json).unwrap();
+    Box::new(SnapshotStore::from_reader(json.as_slice()).unwrap())
+}
+
+fn rate<'c>(src: &'c Currency, dst: &'c Currency, rate: f64, provider: &str) -> Rate<'c> {
+    Rate::now(src, dst, rate, provider.into(), Some(Duration::hours(1)))
+}
+
+#[test_case(sqlite_store ; "sqlite")]
+#[test_case(memory_store ; "memory")]
+#[test_case(snapshot_store ; "snapshot")]
+fn uptodate_rates_test(seeded: Seeded) {
+    let now = Utc::now();
+    let eur_usd = rate(&EUR, &USD, 1.25, "ecb");
+    let store = seeded(&[eur_usd.clone(), rate(&EUR, &USD, 1.2, "other")]);
+
+    assert_eq!(
+        store.get_uptodate_rates(&EUR, &USD, "ecb", now).unwrap(),
+        vec![eur_usd]
+    );
+    // Outdated, other pair or provider
+    assert!(store
+        .get_uptodate_rates(&EUR, &USD, "ecb", now + Duration::hours(2))
+        .unwrap()
+        .is_empty());
+    assert!(store
+        .get_uptodate_rates(&USD, &EUR, "ecb", now)
+        .unwrap()
+        .is_empty());
+    assert!(store
+        .get_uptodate_rates(&EUR, &USD, "nobody", now)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        store.get_uptodate_rates(&GBP, &GBP, "ecb", now).unwrap()[0].rate(),
+        1.
+    );
+}
+
+#[test_case(sqlite_store ; "sqlite")]
+#[test_case(memory_store ; "memory")]
+#[test_case(snapshot_store ; "snapshot")]
+fn latest_rate_test(seeded: Seeded) {
+    let now = Utc::now();
+    let old = Rate::new(
+        &EUR,
+        &USD,
+        now - Duration::days(3),
+        1.1,
+        "p".into(),
+        Some(now - Duration::days(2)),
+    );
+    let new = Rate::new(&USD, &EUR, now, 0.8, "q".into(), Some(now));
+    let store = seeded(&[old, new.clone()]);
+
+    // Outdated rates and rates of the reverse pair count
+    assert_eq!(store.get_latest_rate(&EUR, &USD).unwrap(), Some(new));
+    assert_eq!(store.get_latest_rate(&EUR, &GBP).unwrap(), None);
+}
+
+#[test_case(sqlite_store ; "sqlite")]
+#[test_case(memory_store ; "memory")]
+#[test_case(snapshot_store ; "snapshot")]
+fn derived_rate_test(seeded: Seeded) {
+    let now = Utc::now();
+    let eur_usd = rate(&EUR, &USD, 1.25, "ecb");
+    let eur_gbp = rate(&EUR, &GBP, 0.75, "ecb");
+    let store = seeded(&[eur_usd.clone(), eur_gbp.clone()]);
+    let pivots = [&EUR, &USD];
+
+    let usd_gbp = store
+        .get_uptodate_derived_rate(&USD, &GBP, "ecb", &pivots, now)
+        .unwrap()
+        .unwrap();
+    assert!((usd_gbp.rate() - 0.6).abs() < 1e-9);
+    assert_eq!(usd_gbp.constituents(), &[eur_usd, eur_gbp]);
+    assert_eq!(
+        store
+            .get_uptodate_derived_rate(&CHF, &JPY, "ecb", &pivots, now)
+            .unwrap(),
+        None
+    );
+}
+
+#[test_case(sqlite_store ; "sqlite")]
+#[test_case(memory_store ; "memory")]
+#[test_case(snapshot_store ; "snapshot")]
+fn put_rates_test(seeded: Seeded) {
+    let now = Utc::now();
+    let store = seeded(&[rate(&EUR, &USD, 1.25, "ecb")]);
+
+    // Replaced by the rate of the same pair and provider
+    let eur_usd = rate(&EUR, &USD, 1.3, "ecb");
+    store.put_rates(std::slice::from_ref(&eur_usd)).unwrap();
+    assert_eq!(
+        store.get_uptodate_rates(&EUR, &USD, "ecb", now).unwrap(),
+        vec![eur_usd.clone()]
+    );
+
+    // None stored if one lacks a cache until date
+    let without_cache = Rate::new(&EUR, &GBP, now, 0.75, "ecb".into(), None);
+    assert!(store
+        .put_rates(&[rate(&EUR, &CHF, 0.9, "ecb"), without_cache])
+        .is_err());
+    assert_eq!(store.get_latest_rate(&EUR, &CHF).unwrap(), None);
+
+    // Same source and destination currency ignored
+    store.put_rates(&[Rate::parity(&EUR)]).unwrap();
+    assert_eq!(store.get_latest_rate(&EUR, &EUR).unwrap(), None);
+}
+
+#[test_case(sqlite_store ; "sqlite")]
+#[test_case(memory_store ; "memory")]
+fn remove_outdated_rates_test(seeded: Seeded) {
+    let now = Utc::now();
+    let store = seeded(&[rate(&EUR, &USD, 1.25, "ecb")]);
+
+    assert_eq!(
+        store.remove_outdated_rates(&EUR, &USD, "ecb", now).unwrap(),
+        0
+    );
+    let later = now + Duration::hours(2);
+    assert_eq!(
+        store
+            .remove_outdated_rates(&EUR, &USD, "other", later)
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        store
+            .remove_outdated_rates(&EUR, &USD, "ecb", later)
+            .unwrap(),
+        1
+    );
+    assert_eq!(store.get_latest_rate(&EUR, &USD).unwrap(), None);
+}
+
+#[test]
+fn snapshot_layer_test() {
+    let now = Utc::now();
+    let eur_usd = rate(&EUR, &USD, 1.25, "ecb");
+    let store = snapshot_store(std::slice::from_ref(&eur_usd));
+
+    // Rates put are kept over the snapshot
+    let eur_gbp = rate(&EUR, &GBP, 0.75, "ecb");
+    store.put_rates(std::slice::from_ref(&eur_gbp)).unwrap();
+    assert_eq!(store.get_latest_rate(&EUR, &GBP).unwrap(), Some(eur_gbp));
+
+    // Rates of the snapshot are never removed
+    let later = now + Duration::hours(2);
+    assert_eq!(
+        store
+            .remove_outdated_rates(&EUR, &USD, "ecb", later)
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        store.get_latest_rate(&EUR, &USD).unwrap(),
+        Some(eur_usd.clone())
+    );
+
+    // Newer rates put hide those of the snapshot
+    let newer = Rate::new(
+        &EUR,
+        &USD,
+        now + Duration::minutes(1),
+        1.3,
+        "ecb".into(),
+        Some(later),
+    );
+    store.put_rates(std::slice::from_ref(&newer)).unwrap();
+    assert_eq!(
+        store.get_latest_rate(&EUR, &USD).unwrap(),
+        Some(newer.clone())
+    );
+    assert_eq!(
+        store.get_uptodate_rates(&EUR, &USD, "ecb", now).unwrap(),
+        vec![newer]
+    );
+}
+
+#[test]
+fn snapshot_open_test() {
+    let path = std::env::temp_dir().join(format!("sesters-snapshot-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"[{"src": "EUR", "dst": "USD", "date": "2026-10-16T16:00:00Z", "rate": 1.0876,
+             "provider": "ECB", "cache_until": "2026-10-17T16:00:00Z"}]"#,
+    )
+    .unwrap();
+    let store = from_config(&StoreConfig::Snapshot { path: path.clone() })
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        store.get_latest_rate(&USD, &EUR).unwrap().unwrap().rate(),
+        1.0876
+    );
+
+    std::fs::write(&path, r#"[{"src": "XYZ"}]"#).unwrap();
+    assert!(SnapshotStore::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(SnapshotStore::open(&path).is_err());
+}
+
+// Rates retrieved online go to the store of the context, not to the database
+#[test]
+fn context_store_test() {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let mut cfg = Config::default();
+    cfg.apis.currency_converter_api_com.base_url = server.url();
+    cfg.rate_store = StoreConfig::Memory;
+    let ctxt = MainContext::new_in_memory(cfg, vec![&USD]).unwrap();
+
+    let conversions = crate::convert::convert(&ctxt, "2 €", None, None).unwrap();
+    assert_eq!(conversions[0].len(), 1);
+    assert!(ctxt.store().get_latest_rate(&EUR, &USD).unwrap().is_some());
+    assert_eq!(ctxt.db.get_latest_rate(&EUR, &USD).unwrap(), None);
+
+    // Rates are reused from the store
+    crate::convert::convert(&ctxt, "3 €", None, None).unwrap();
+    assert_eq!(server.requests().len(), 1);
+}
+
+// Outdated rates are retrieved online once, and then reused
+#[test_case(sqlite_store ; "sqlite")]
+#[test_case(memory_store ; "memory")]
+#[test_case(snapshot_store ; "snapshot")]
+fn outdated_rates_retrieved_once_test(seeded: Seeded) {
+    let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+    let mut ctxt = context(&server);
+    let now = Utc::now();
+    let stale = Rate::new(
+        &EUR,
+        &USD,
+        now - Duration::days(2),
+        1.1,
+        "currencyconverterapi.com".into(),
+        Some(now - Duration::days(1)),
+    );
+    ctxt.set_store(seeded(&[stale]));
+
+    crate::convert::convert(&ctxt, "2 €", None, None).unwrap();
+    crate::convert::convert(&ctxt, "3 €", None, None).unwrap();
+    assert_eq!(server.requests().len(), 1);
+}
@@ -0,0 +1,377 @@
+/*
+Sesters: easily convert one currency to another
+Copyright (C) 2018-2022  Clément Joly <oss+sesters@131719.xyz>
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Csv subcommand: convert a column of amounts in CSV or TSV, appending a column per target
+//! currency
+
+use anyhow::{anyhow, bail, Result};
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use log::trace;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use crate::convert::{current_rates, historical_rates};
+use crate::currency::{self, PriceTag};
+use crate::price_format;
+use crate::price_in_text::Engine;
+use crate::rate::Rate;
+use crate::MainContext;
+
+/// Names of the columns to read
+pub(crate) struct Columns {
+    /// Amounts, or whole price tags like "12,50 €" without a currency column
+    pub amount: String,
+    /// Currencies, by ISO code or symbol
+    pub currency: Option<String>,
+    /// Days of the rates to use, like 2026-03-14. Current rates are used without it
+    pub date: Option<String>,
+}
+
+/// Indices of the columns in the header
+struct Indices {
+    amount: usize,
+    currency: Option<usize>,
+    date: Option<usize>,
+}
+
+impl Indices {
+    fn new(headers: &StringRecord, columns: &Columns) -> Result<Self> {
+        let index = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| anyhow!("No column named '{}'", name))
+        };
+        Ok(Indices {
+            amount: index(&columns.amount)?,
+            currency: columns.currency.as_deref().map(index).transpose()?,
+            date: columns.date.as_deref().map(index).transpose()?,
+        })
+    }
+}
+
+pub(crate) fn run(
+    ctxt: MainContext,
+    file: Option<PathBuf>,
+    delimiter: u8,
+    columns: Columns,
+) -> Result<()> {
+    let input: Box<dyn Read> = match file {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+    let errors = convert(&ctxt, input, io::stdout(), delimiter, &columns)?;
+    if errors > 0 {
+        bail!("{} rows could not be converted", errors);
+    }
+    Ok(())
+}
+
+/// Copy the table from input to output, with a column of converted amounts appended for each
+/// destination currency. Rows that can’t be converted are reported on stderr, and copied with
+/// empty cells. Returns the number of such rows
+fn convert(
+    ctxt: &MainContext,
+    input: impl Read,
+    output: impl Write,
+    delimiter: u8,
+    columns: &Columns,
+) -> Result<usize> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(input);
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_writer(output);
+
+    let mut headers = reader.headers()?.clone();
+    let indices = Indices::new(&headers, columns)?;
+    for dst in &ctxt.destination_currencies {
+        headers.push_field(&format!("{}_{}", columns.amount, dst.get_main_iso()));
+    }
+    writer.write_record(&headers)?;
+
+    let engine = Engine::new();
+    let mut snapshot = HashMap::new();
+    let mut errors = 0;
+    for record in reader.records() {
+        let mut record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let converted = price_tag(&engine, &record, &indices)
+            .and_then(|(price_tag, day)| convert_row(ctxt, &mut snapshot, &price_tag, day));
+        let converted = match converted {
+            Ok(converted) => converted,
+            Err(err) => vec![Err(err); ctxt.destination_currencies.len()],
+        };
+
+        let mut row_errors = Vec::new();
+        for cell in converted {
+            match cell {
+                Ok(cell) => record.push_field(&cell),
+                Err(err) => {
+                    if !row_errors.contains(&err) {
+                        row_errors.push(err);
+                    }
+                    record.push_field("");
+                }
+            }
+        }
+        if !row_errors.is_empty() {
+            errors += 1;
+            eprintln!("Line {}: {}", line, row_errors.join(", "));
+        }
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()?;
+    Ok(errors)
+}
+
+/// Price tag of a row, with the day of the rates to use if there is a date column
+fn price_tag<'c>(
+    engine: &Engine<'c>,
+    record: &StringRecord,
+    indices: &Indices,
+) -> Result<(PriceTag<'c>, Option<NaiveDate>), String> {
+    let cell = |i: usize| {
+        record
+            .get(i)
+            .map(str::trim)
+            .ok_or_else(|| format!("missing column {}", i + 1))
+    };
+
+    let amount = cell(indices.amount)?;
+    let price_tag = match indices.currency {
+        Some(i) => {
+            let iso = cell(i)?;
+            let currency = currency::from_iso_or_symbol(iso)
+                .ok_or_else(|| format!("unknown currency '{}'", iso))?;
+            PriceTag::new(currency, parse_amount(amount)?)
+        }
+        None => engine
+            .top_price_tags(1, amount)
+            .pop()
+            .ok_or_else(|| format!("no price tag in '{}'", amount))?,
+    };
+    let day = match indices.date {
+        Some(i) => Some(parse_day(cell(i)?)?),
+        None => None,
+    };
+    trace!("price tag: {:?}, day: {:?}", price_tag, day);
+    Ok((price_tag, day))
+}
+
+/// Amount alone in a cell, like 1234.5, -3 or 12,50
+fn parse_amount(cell: &str) -> Result<f64, String> {
+    if let Ok(amount) = cell.parse() {
+        return Ok(amount);
+    }
+    match price_format::COMMON.captures_iter(cell).as_slice() {
+        [m] if m.start() == 0 && m.end() == cell.len() => Ok(m.price()),
+        _ => Err(format!("invalid amount '{}'", cell)),
+    }
+}
+
+/// Day at the start of a cell, like 2026-03-14 or 2026-03-14T10:12:00
+fn parse_day(cell: &str) -> Result<NaiveDate, String> {
+    cell.get(..10)
+        .unwrap_or(cell)
+        .parse()
+        .map_err(|_| format!("invalid date '{}'", cell))
+}
+
+/// Rates from a currency to each destination currency, by source currency and day
+type Snapshot<'c> = HashMap<(&'c str, Option<NaiveDate>), Vec<Option<Rate<'c>>>>;
+
+/// Converted amount for each destination currency, or why it is missing. Rates are retrieved once
+/// per currency and day, so that rows in the same currency are converted with the same rate
+fn convert_row<'c>(
+    ctxt: &MainContext<'c>,
+    snapshot: &mut Snapshot<'c>,
+    price_tag: &PriceTag<'c>,
+    day: Option<NaiveDate>,
+) -> Result<Vec<Result<String, String>>, String> {
+    let src = price_tag.currency();
+    let key = (src.get_main_iso(), day);
+    if !snapshot.contains_key(&key) {
+        let rates = match day {
+            Some(day) => historical_rates(ctxt, src, &ctxt.destination_currencies, day),
+            None => current_rates(ctxt, src, &ctxt.destination_currencies),
+        };
+        // Not retried on the next rows
+        let rates = rates.map_err(|err| {
+            snapshot.insert(key, vec![None; ctxt.destination_currencies.len()]);
+            format!("{:#}", err)
+        })?;
+        snapshot.insert(key, rates);
+    }
+
+    Ok(ctxt
+        .destination_currencies
+        .iter()
+        .zip(&snapshot[&key])
+        .map(|(dst, rate)| {
+            if src == *dst {
+                return Ok(format!("{:.*}", dst.decimals(), price_tag.amount()));
+            }
+            let no_rate = || format!("no rate from {} to {}", src, dst);
+            let rate = rate.as_ref().ok_or_else(no_rate)?;
+            let quote = price_tag.convert(rate, None).map_err(|_| no_rate())?;
+            Ok(format!(
+                "{:.*}",
+                dst.decimals(),
+                quote.mid_market().amount()
+            ))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::{self, fixtures, MockServer, Route};
+    use crate::currency::{GBP, USD};
+
+    fn context(server: &MockServer) -> MainContext<'static> {
+        MainContext::new_in_memory(mock::config(server), vec![&USD, &GBP]).unwrap()
+    }
+
+    fn columns(amount: &str, currency: Option<&str>, date: Option<&str>) -> Columns {
+        Columns {
+            amount: amount.to_string(),
+            currency: currency.map(str::to_string),
+            date: date.map(str::to_string),
+        }
+    }
+
+    fn run_convert(
+        ctxt: &MainContext,
+        input: &str,
+        delimiter: u8,
+        columns: &Columns,
+    ) -> (String, usize) {
+        let mut output = Vec::new();
+        let errors = convert(ctxt, input.as_bytes(), &mut output, delimiter, columns).unwrap();
+        (String::from_utf8(output).unwrap(), errors)
+    }
+
+    #[test]
+    fn parse_amount_test() {
+        assert_eq!(parse_amount("1234.5"), Ok(1234.5));
+        assert_eq!(parse_amount("-3"), Ok(-3.));
+        assert_eq!(parse_amount("12,50"), Ok(12.5));
+        assert_eq!(parse_amount("1 234,50"), Ok(1234.5));
+        assert!(parse_amount("").is_err());
+        assert!(parse_amount("12 EUR").is_err());
+    }
+
+    #[test]
+    fn currency_column_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let ctxt = context(&server);
+
+        let input = "\
+item,price,cur
+\"Coffee, large\",2,EUR
+Hotel,\"1 234,50\",€
+Taxi,12,usd
+Tip,abc,EUR
+Book,3,XYZ
+";
+        let (output, errors) =
+            run_convert(&ctxt, input, b',', &columns("price", Some("cur"), None));
+        assert_eq!(
+            output,
+            "\
+item,price,cur,price_USD,price_GBP
+\"Coffee, large\",2,EUR,2.18,1.71
+Hotel,\"1 234,50\",€,1342.64,1053.28
+Taxi,12,usd,12.00,9.41
+Tip,abc,EUR,,
+Book,3,XYZ,,
+"
+        );
+        assert_eq!(errors, 2);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn price_tag_column_test() {
+        let server = MockServer::start(vec![Route::ok("/api/v7/convert", fixtures::CURRCONV_EUR)]);
+        let ctxt = context(&server);
+
+        let input = "item\tprice\nCoffee\t2 €\nNothing\tfree\n";
+        let (output, errors) = run_convert(&ctxt, input, b'\t', &columns("price", None, None));
+        assert_eq!(
+            output,
+            "item\tprice\tprice_USD\tprice_GBP\nCoffee\t2 €\t2.18\t1.71\nNothing\tfree\t\t\n"
+        );
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn date_column_test() {
+        let server = MockServer::start(vec![Route::ok(
+            "/stats/eurofxref/eurofxref-hist",
+            fixtures::ECB_HIST_90D,
+        )]);
+        let ctxt = context(&server);
+
+        let input = "\
+date,price
+2026-10-15,10 EUR
+2026-10-16T09:30:00,10 EUR
+yesterday,10 EUR
+";
+        let (output, errors) =
+            run_convert(&ctxt, input, b',', &columns("price", None, Some("date")));
+        assert_eq!(
+            output,
+            "\
+date,price,price_USD,price_GBP
+2026-10-15,10 EUR,12.50,7.50
+2026-10-16T09:30:00,10 EUR,10.88,8.53
+yesterday,10 EUR,,
+"
+        );
+        assert_eq!(errors, 1);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn missing_column_test() {
+        let server = MockServer::start(vec![]);
+        let ctxt = context(&server);
+        let mut output = Vec::new();
+        let err = convert(
+            &ctxt,
+            "item,cost\n".as_bytes(),
+            &mut output,
+            b',',
+            &columns("price", None, None),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "No column named 'price'");
+    }
+}